@@ -0,0 +1,475 @@
+//! Compact binary format for saving and loading a [`MiniZ`]
+//!
+//! [`MiniZ::write_binary()`] writes a store's schema and tuples and
+//! [`MiniZ::read_binary()`] reads them back.  The format is meant for fast
+//! loading of a persisted store, not for interchange: it has no provision for
+//! evolving the schema, and the change log (see [`MiniZ::enable_log()`]) is
+//! not saved.
+//!
+//! The layout is:
+//!
+//! * the magic bytes `MINIZ` followed by a format version byte (currently 5)
+//! * the number of relationships, followed by each relationship: its name,
+//!   its subsets, its inherited relationships, its userset member
+//!   relationship, its deny relationships, its description, its expected
+//!   member kind, and whether it's direct-only
+//! * the number of caveats, followed by each caveat: its name and its
+//!   definition (in which `AnyOf` and `AllOf` may be nested at most
+//!   [`MAX_CAVEAT_DEPTH`] deep)
+//! * the number of tuples, followed by each tuple: the index of its
+//!   relationship (in the order above), the object, the member, and the name
+//!   of its caveat (if any)
+//!
+//! A member is a tag byte (0 for an object, 1 for a user, or 2 for an
+//! object-relation userset) followed by the object or user id.  A userset's
+//! id is followed by the index of its relationship.
+//!
+//! Counts and indexes are little-endian `u32`s, except that the number of
+//! tuples is a `u64`.  Strings are a count of bytes followed by the UTF-8
+//! bytes.  Object and user ids are encoded by their [`BinaryId`] impls.
+
+use crate::Caveat;
+use crate::CaveatId;
+use crate::CheckResultCache;
+use crate::InheritedIndex;
+use crate::Member;
+use crate::MemberKind;
+use crate::MiniZ;
+use crate::ReachabilityCache;
+use crate::Relationship;
+use crate::RelationshipId;
+use crate::Tuple;
+use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+
+const MAGIC: &[u8; 5] = b"MINIZ";
+const FORMAT_VERSION: u8 = 5;
+
+/// Deepest that caveats may be nested inside `AnyOf` and `AllOf` in a saved
+/// store
+///
+/// Caveats are read recursively, so without a limit, corrupt input could
+/// overflow the stack rather than producing an error.
+pub const MAX_CAVEAT_DEPTH: usize = 64;
+
+///
+/// An object or user id type that can be saved in the binary format
+///
+/// `decode()` must read back exactly what `encode()` wrote.
+///
+pub trait BinaryId: Sized {
+    /// Write `self` to `w`
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()>;
+    /// Read an id written by `encode()` from `r`
+    fn decode<R: Read>(r: &mut R) -> io::Result<Self>;
+}
+
+impl BinaryId for String {
+    fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write_str(w, self)
+    }
+
+    fn decode<R: Read>(r: &mut R) -> io::Result<Self> {
+        read_string(r)
+    }
+}
+
+macro_rules! impl_binary_id_int {
+    ($t:ty) => {
+        impl BinaryId for $t {
+            fn encode<W: Write>(&self, w: &mut W) -> io::Result<()> {
+                w.write_all(&self.to_le_bytes())
+            }
+
+            fn decode<R: Read>(r: &mut R) -> io::Result<Self> {
+                let mut buf = [0u8; core::mem::size_of::<$t>()];
+                r.read_exact(&mut buf)?;
+                Ok(<$t>::from_le_bytes(buf))
+            }
+        }
+    };
+}
+
+impl_binary_id_int!(u8);
+impl_binary_id_int!(u16);
+impl_binary_id_int!(u32);
+impl_binary_id_int!(u64);
+
+impl<O, U> MiniZ<O, U>
+where
+    O: BinaryId + Clone + fmt::Debug + Ord,
+    U: BinaryId + Clone + fmt::Debug + Ord,
+{
+    ///
+    /// Write the schema and tuples of this store to `w` in the binary format
+    ///
+    /// See the [`binary`](crate::binary) module for details.  Callers writing
+    /// to a file or socket probably want to use a buffered writer.
+    ///
+    pub fn write_binary<W: Write>(&self, mut w: W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&[FORMAT_VERSION])?;
+
+        let indexes = self
+            .sets
+            .keys()
+            .enumerate()
+            .map(|(i, rid)| (rid, i))
+            .collect::<BTreeMap<_, _>>();
+        write_len(&mut w, self.sets.len())?;
+        for (rid, set) in &self.sets {
+            write_str(&mut w, &rid.0)?;
+            write_len(&mut w, set.contained_relationships.len())?;
+            for subrid in &set.contained_relationships {
+                write_str(&mut w, &subrid.0)?;
+            }
+            write_len(&mut w, set.inherited_relationships.len())?;
+            for (via, parent_rids) in &set.inherited_relationships {
+                write_str(&mut w, &via.0)?;
+                write_len(&mut w, parent_rids.len())?;
+                for parent_rid in parent_rids {
+                    write_str(&mut w, &parent_rid.0)?;
+                }
+            }
+            write_optional_str(
+                &mut w,
+                set.userset_member_relation.as_ref().map(|rid| &*rid.0),
+            )?;
+            write_len(&mut w, set.deny_relationships.len())?;
+            for deny in &set.deny_relationships {
+                write_str(&mut w, &deny.0)?;
+            }
+            write_optional_str(&mut w, set.description.as_deref())?;
+            w.write_all(&[match set.expected_member_kind {
+                None => 0,
+                Some(MemberKind::Object) => 1,
+                Some(MemberKind::User) => 2,
+            }])?;
+            w.write_all(&[u8::from(set.direct_only)])?;
+        }
+
+        write_len(&mut w, self.caveats.len())?;
+        for (caveat_id, caveat) in &self.caveats {
+            write_str(&mut w, &caveat_id.0)?;
+            write_caveat(&mut w, caveat, 0)?;
+        }
+
+        let ntuples = self.stats().num_tuples as u64;
+        w.write_all(&ntuples.to_le_bytes())?;
+        for (rid, set) in &self.sets {
+            for (parent, members) in &set.direct_members {
+                for member in members {
+                    write_len(&mut w, indexes[rid])?;
+                    parent.encode(&mut w)?;
+                    match member {
+                        Member::Object(object) => {
+                            w.write_all(&[0])?;
+                            object.encode(&mut w)?;
+                        }
+                        Member::User(user) => {
+                            w.write_all(&[1])?;
+                            user.encode(&mut w)?;
+                        }
+                        Member::ObjectRelation { object, relation } => {
+                            w.write_all(&[2])?;
+                            object.encode(&mut w)?;
+                            write_len(&mut w, indexes[relation])?;
+                        }
+                    }
+                    let caveat = set
+                        .caveated_members
+                        .get(parent)
+                        .and_then(|caveated| caveated.get(member));
+                    write_optional_str(
+                        &mut w,
+                        caveat.map(|caveat_id| &*caveat_id.0),
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Read a store written by [`MiniZ::write_binary()`] from `r`
+    ///
+    /// The returned store has the same schema and tuples as the one that was
+    /// written.  Its version reflects loading the tuples, and its change log
+    /// is not enabled.  Malformed input produces an error of kind
+    /// [`io::ErrorKind::InvalidData`] (or [`io::ErrorKind::UnexpectedEof`] if
+    /// it's truncated).
+    ///
+    pub fn read_binary<R: Read>(mut r: R) -> io::Result<MiniZ<O, U>> {
+        let mut header = [0u8; MAGIC.len() + 1];
+        r.read_exact(&mut header)?;
+        if &header[..MAGIC.len()] != MAGIC {
+            return Err(invalid("not a MiniZ binary store"));
+        }
+        if header[MAGIC.len()] != FORMAT_VERSION {
+            return Err(invalid("unsupported format version"));
+        }
+
+        let nsets = read_len(&mut r)?;
+        let mut rids = Vec::new();
+        let mut sets = BTreeMap::new();
+        for _ in 0..nsets {
+            let rid = read_rid(&mut r)?;
+            let contained_relationships = (0..read_len(&mut r)?)
+                .map(|_| read_rid(&mut r))
+                .collect::<io::Result<BTreeSet<_>>>()?;
+            let mut inherited_relationships = BTreeMap::new();
+            for _ in 0..read_len(&mut r)? {
+                let via = read_rid(&mut r)?;
+                let parent_rids = (0..read_len(&mut r)?)
+                    .map(|_| read_rid(&mut r))
+                    .collect::<io::Result<BTreeSet<_>>>()?;
+                inherited_relationships.insert(via, parent_rids);
+            }
+            let userset_member_relation =
+                read_optional_string(&mut r)?.map(RelationshipId);
+            let deny_relationships = (0..read_len(&mut r)?)
+                .map(|_| read_rid(&mut r))
+                .collect::<io::Result<BTreeSet<_>>>()?;
+            let description = read_optional_string(&mut r)?;
+            let expected_member_kind = match read_u8(&mut r)? {
+                0 => None,
+                1 => Some(MemberKind::Object),
+                2 => Some(MemberKind::User),
+                _ => return Err(invalid("bad member kind")),
+            };
+            let direct_only = match read_u8(&mut r)? {
+                0 => false,
+                1 => true,
+                _ => return Err(invalid("bad direct-only flag")),
+            };
+            let set = Relationship {
+                direct_members: BTreeMap::new(),
+                contained_relationships,
+                inherited_relationships,
+                userset_member_relation,
+                deny_relationships,
+                description,
+                expected_member_kind,
+                direct_only,
+                caveated_members: BTreeMap::new(),
+            };
+            if sets.insert(rid.clone(), set).is_some() {
+                return Err(invalid("duplicate relationship"));
+            }
+            rids.push(rid);
+        }
+
+        /*
+         * Every relationship that the schema refers to must exist, or checks
+         * would panic later.
+         */
+        for set in sets.values() {
+            let referenced = set
+                .contained_relationships
+                .iter()
+                .chain(set.inherited_relationships.keys())
+                .chain(set.inherited_relationships.values().flatten())
+                .chain(set.userset_member_relation.iter())
+                .chain(set.deny_relationships.iter());
+            for rid in referenced {
+                if !sets.contains_key(rid) {
+                    return Err(invalid("reference to unknown relationship"));
+                }
+            }
+        }
+
+        let mut caveats = BTreeMap::new();
+        for _ in 0..read_len(&mut r)? {
+            let caveat_id = CaveatId(read_string(&mut r)?);
+            let caveat = read_caveat(&mut r, 0)?;
+            if caveats.insert(caveat_id, caveat).is_some() {
+                return Err(invalid("duplicate caveat"));
+            }
+        }
+
+        let mut miniz = MiniZ {
+            reachability: ReachabilityCache::new(&sets),
+            sets,
+            memberships: BTreeMap::new(),
+            version: 0,
+            log: None,
+            check_results: CheckResultCache::new(),
+            inherited_index: InheritedIndex::new(),
+            subset_closures: None,
+            caveats,
+        };
+
+        let mut buf = [0u8; 8];
+        r.read_exact(&mut buf)?;
+        let ntuples = u64::from_le_bytes(buf);
+        let mut tuples = Vec::new();
+        let mut seen = BTreeSet::new();
+        let mut caveated = Vec::new();
+        for _ in 0..ntuples {
+            let rid = rids
+                .get(read_len(&mut r)?)
+                .ok_or_else(|| invalid("bad relationship index"))?;
+            let parent = O::decode(&mut r)?;
+            let member = match read_u8(&mut r)? {
+                0 => Member::Object(O::decode(&mut r)?),
+                1 => Member::User(U::decode(&mut r)?),
+                2 => Member::ObjectRelation {
+                    object: O::decode(&mut r)?,
+                    relation: rids
+                        .get(read_len(&mut r)?)
+                        .ok_or_else(|| invalid("bad relationship index"))?
+                        .clone(),
+                },
+                _ => return Err(invalid("bad member tag")),
+            };
+            if !miniz.sets[rid].accepts_member(&member) {
+                return Err(invalid("member of unexpected kind"));
+            }
+            let tuple = Tuple { rid: rid.clone(), parent, member };
+            if !seen.insert(tuple.clone()) {
+                return Err(invalid("duplicate tuple"));
+            }
+            if let Some(caveat_id) = read_optional_string(&mut r)? {
+                let caveat_id = CaveatId(caveat_id);
+                if !miniz.caveats.contains_key(&caveat_id) {
+                    return Err(invalid("reference to unknown caveat"));
+                }
+                caveated.push((tuple.clone(), caveat_id));
+            }
+            tuples.push(tuple);
+        }
+
+        miniz.load_tuples(tuples);
+        for (tuple, caveat_id) in caveated {
+            let set = miniz.sets.get_mut(&tuple.rid).unwrap();
+            set.caveated_members
+                .entry(tuple.parent)
+                .or_default()
+                .insert(tuple.member, caveat_id);
+        }
+        Ok(miniz)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn write_len<W: Write>(w: &mut W, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "too long"))?;
+    w.write_all(&len.to_le_bytes())
+}
+
+fn write_str<W: Write>(w: &mut W, s: &str) -> io::Result<()> {
+    write_len(w, s.len())?;
+    w.write_all(s.as_bytes())
+}
+
+fn write_optional_str<W: Write>(w: &mut W, s: Option<&str>) -> io::Result<()> {
+    match s {
+        None => w.write_all(&[0]),
+        Some(s) => {
+            w.write_all(&[1])?;
+            write_str(w, s)
+        }
+    }
+}
+
+/// Writes `caveat`, which is nested `depth` deep in the caveat being written
+fn write_caveat<W: Write>(
+    w: &mut W,
+    caveat: &Caveat,
+    depth: usize,
+) -> io::Result<()> {
+    match caveat {
+        Caveat::Equals { key, value } => {
+            w.write_all(&[0])?;
+            write_str(w, key)?;
+            write_str(w, value)
+        }
+        Caveat::AnyOf(caveats) | Caveat::AllOf(caveats) => {
+            if depth >= MAX_CAVEAT_DEPTH {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "caveat nested too deeply",
+                ));
+            }
+            let tag = if let Caveat::AnyOf(_) = caveat { 1 } else { 2 };
+            w.write_all(&[tag])?;
+            write_len(w, caveats.len())?;
+            caveats.iter().try_for_each(|c| write_caveat(w, c, depth + 1))
+        }
+    }
+}
+
+/// Reads a caveat nested `depth` deep in the caveat being read
+fn read_caveat<R: Read>(r: &mut R, depth: usize) -> io::Result<Caveat> {
+    match read_u8(r)? {
+        0 => {
+            let key = read_string(r)?;
+            let value = read_string(r)?;
+            Ok(Caveat::Equals { key, value })
+        }
+        tag @ 1..=2 => {
+            if depth >= MAX_CAVEAT_DEPTH {
+                return Err(invalid("caveat nested too deeply"));
+            }
+            let caveats = (0..read_len(r)?)
+                .map(|_| read_caveat(r, depth + 1))
+                .collect::<io::Result<Vec<_>>>()?;
+            Ok(if tag == 1 {
+                Caveat::AnyOf(caveats)
+            } else {
+                Caveat::AllOf(caveats)
+            })
+        }
+        _ => Err(invalid("bad caveat tag")),
+    }
+}
+
+fn read_u8<R: Read>(r: &mut R) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    r.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_len<R: Read>(r: &mut R) -> io::Result<usize> {
+    let mut buf = [0u8; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf) as usize)
+}
+
+fn read_string<R: Read>(r: &mut R) -> io::Result<String> {
+    /*
+     * Don't trust the length enough to allocate it all up front: a corrupt
+     * length would otherwise make us allocate up to 4 GiB.
+     */
+    let len = read_len(r)?;
+    let mut bytes = Vec::new();
+    r.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|_| invalid("string is not UTF-8"))
+}
+
+fn read_optional_string<R: Read>(r: &mut R) -> io::Result<Option<String>> {
+    match read_u8(r)? {
+        0 => Ok(None),
+        1 => read_string(r).map(Some),
+        _ => Err(invalid("bad optional string tag")),
+    }
+}
+
+fn read_rid<R: Read>(r: &mut R) -> io::Result<RelationshipId> {
+    read_string(r).map(RelationshipId)
+}
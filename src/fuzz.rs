@@ -0,0 +1,356 @@
+//! Generating random-but-valid [`MiniZ`] instances for fuzzing
+//!
+//! A fuzzer (e.g., `cargo fuzz`) hands us an arbitrary byte string.
+//! [`StoreDescription::from_bytes()`] turns any such string into a description
+//! of a schema, a set of tuples, and a sequence of queries.  The description
+//! can then be turned into a [`MiniZ`] with [`StoreDescription::build()`].
+//!
+//! With the `arbitrary` feature, `StoreDescription` (as well as
+//! [`RelationshipId`] and [`Member`]) implements `arbitrary::Arbitrary`, so a
+//! fuzz target can take one as its input directly:
+//!
+//! ```ignore
+//! fuzz_target!(|description: StoreDescription| {
+//!     description.run();
+//! });
+//! ```
+//!
+//! Every description generated this way is guaranteed to be one for which
+//! [`MiniZ::check_member()`] terminates:
+//!
+//! * relationship `i` only ever refers to relationships with lower indexes
+//!   (as subsets, inherited sets, or userset member relationships), so the
+//!   graph of relationships is acyclic, and
+//! * object tuples always have a parent with a lower id than the child, so
+//!   following inherited relationships upward always terminates.
+
+use crate::Member;
+use crate::MiniZ;
+use crate::RelationshipId;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::vec::Vec;
+
+/// Type used for object ids in generated stores
+pub type FuzzObjectId = u8;
+/// Type used for user ids in generated stores
+pub type FuzzUserId = u8;
+
+/// Upper bound on the number of relationships in a generated schema
+const MAX_RELATIONSHIPS: u8 = 8;
+/// Upper bound on the number of distinct objects in a generated store
+const MAX_OBJECTS: u8 = 16;
+/// Upper bound on the number of distinct users in a generated store
+const MAX_USERS: u8 = 8;
+
+/// Describes one relationship in a generated schema
+///
+/// All indexes refer to lower-numbered relationships in the same
+/// [`StoreDescription`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RelationshipDescription {
+    /// indexes of relationships configured with `with_subset()`
+    pub subsets: BTreeSet<usize>,
+    /// indexes of relationships configured with `with_inherited_set()`
+    pub inherited: BTreeSet<usize>,
+    /// index of the relationship configured with
+    /// `with_userset_member_relation()`, if any
+    pub userset_member_relation: Option<usize>,
+}
+
+/// Describes one tuple in a generated store
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct TupleDescription {
+    /// index of the relationship
+    pub relationship: usize,
+    /// object on which the relationship is written
+    pub parent: FuzzObjectId,
+    /// member being written
+    pub member: Member<FuzzObjectId, FuzzUserId>,
+}
+
+/// Describes one `check_member()` query against a generated store
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct QueryDescription {
+    /// index of the relationship to check
+    pub relationship: usize,
+    /// object to check
+    pub object: FuzzObjectId,
+    /// user to check
+    pub user: FuzzUserId,
+}
+
+/// Describes a complete generated store plus queries to run against it
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoreDescription {
+    /// the schema
+    pub relationships: Vec<RelationshipDescription>,
+    /// tuples to write, in order (there are no duplicates)
+    pub tuples: Vec<TupleDescription>,
+    /// queries to run after the tuples have been written
+    pub queries: Vec<QueryDescription>,
+}
+
+/// Hands out bytes from the fuzzer's input, returning 0 once it's exhausted
+struct ByteSource<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> ByteSource<'a> {
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    fn next_u8(&mut self) -> u8 {
+        match self.bytes.split_first() {
+            Some((first, rest)) => {
+                self.bytes = rest;
+                *first
+            }
+            None => 0,
+        }
+    }
+
+    /// Returns a value in `[0, bound)`.  `bound` must be non-zero.
+    fn next_below(&mut self, bound: u8) -> u8 {
+        self.next_u8() % bound
+    }
+
+    fn next_bool(&mut self) -> bool {
+        self.next_u8() & 1 == 1
+    }
+
+    /// Returns an arbitrary subset of `[0, bound)`
+    fn next_subset(&mut self, bound: usize) -> BTreeSet<usize> {
+        let mask = self.next_u8();
+        (0..bound).filter(|i| mask & (1 << i) != 0).collect()
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for StoreDescription {
+    /*
+     * A derived impl could produce relationships that refer to themselves (or
+     * to ones that don't exist), so this goes through from_bytes() instead.
+     */
+    fn arbitrary(
+        u: &mut arbitrary::Unstructured<'a>,
+    ) -> arbitrary::Result<Self> {
+        let len = u.arbitrary_len::<u8>()?;
+        Ok(StoreDescription::from_bytes(u.bytes(len)?))
+    }
+
+    fn arbitrary_take_rest(
+        u: arbitrary::Unstructured<'a>,
+    ) -> arbitrary::Result<Self> {
+        Ok(StoreDescription::from_bytes(u.take_rest()))
+    }
+}
+
+impl StoreDescription {
+    /// Interpret an arbitrary byte string as a description of a valid store
+    pub fn from_bytes(bytes: &[u8]) -> StoreDescription {
+        let mut source = ByteSource { bytes };
+
+        let nrelationships =
+            usize::from(source.next_below(MAX_RELATIONSHIPS)) + 1;
+        let relationships = (0..nrelationships)
+            .map(|i| RelationshipDescription {
+                subsets: source.next_subset(i),
+                inherited: source.next_subset(i),
+                userset_member_relation: if i > 0 && source.next_bool() {
+                    Some(usize::from(source.next_u8()) % i)
+                } else {
+                    None
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let mut written = BTreeSet::new();
+        let mut tuples = Vec::new();
+        let mut queries = Vec::new();
+        while !source.is_empty() {
+            let relationship =
+                usize::from(source.next_u8()) % relationships.len();
+            let object = source.next_below(MAX_OBJECTS);
+            match source.next_below(3) {
+                0 => {
+                    let user = source.next_below(MAX_USERS);
+                    queries.push(QueryDescription {
+                        relationship,
+                        object,
+                        user,
+                    });
+                }
+                1 => {
+                    let member = Member::User(source.next_below(MAX_USERS));
+                    let tuple = TupleDescription {
+                        relationship,
+                        parent: object,
+                        member,
+                    };
+                    if written.insert(tuple.clone()) {
+                        tuples.push(tuple);
+                    }
+                }
+                _ => {
+                    /*
+                     * Object tuples always point from a lower-numbered parent
+                     * to a higher-numbered child so that there are no cycles.
+                     */
+                    let other = source.next_below(MAX_OBJECTS);
+                    if object == other {
+                        continue;
+                    }
+                    let tuple = TupleDescription {
+                        relationship,
+                        parent: object.min(other),
+                        member: Member::Object(object.max(other)),
+                    };
+                    if written.insert(tuple.clone()) {
+                        tuples.push(tuple);
+                    }
+                }
+            }
+        }
+
+        StoreDescription { relationships, tuples, queries }
+    }
+
+    ///
+    /// Construct the [`MiniZ`] described by `self`
+    ///
+    /// Returns the store along with the ids of its relationships, indexed the
+    /// same way as `self.relationships`.
+    ///
+    pub fn build(
+        &self,
+    ) -> (MiniZ<FuzzObjectId, FuzzUserId>, Vec<RelationshipId>) {
+        let mut miniz_builder = MiniZ::builder();
+        let mut rids: Vec<RelationshipId> = Vec::new();
+        for (i, desc) in self.relationships.iter().enumerate() {
+            let mut rbuilder =
+                miniz_builder.new_relationship(format!("r{}", i));
+            for subset in &desc.subsets {
+                rbuilder = rbuilder.with_subset(&rids[*subset]);
+            }
+            for inherited in &desc.inherited {
+                rbuilder = rbuilder.with_inherited_set(&rids[*inherited]);
+            }
+            if let Some(member_rid) = desc.userset_member_relation {
+                rbuilder =
+                    rbuilder.with_userset_member_relation(&rids[member_rid]);
+            }
+            rids.push(rbuilder.build());
+        }
+
+        let mut miniz = miniz_builder.build().unwrap();
+        for tuple in &self.tuples {
+            let rid = &rids[tuple.relationship];
+            match &tuple.member {
+                Member::Object(o) => miniz.write_object(rid, tuple.parent, *o),
+                Member::User(u) => miniz.write_user(rid, tuple.parent, *u),
+                Member::ObjectRelation { object, relation } => miniz
+                    .write_object_relation(
+                        rid,
+                        tuple.parent,
+                        *object,
+                        relation,
+                    ),
+            };
+        }
+
+        (miniz, rids)
+    }
+
+    ///
+    /// Build the described store and run all of the described queries,
+    /// returning their results
+    ///
+    pub fn run(&self) -> Vec<bool> {
+        let (miniz, rids) = self.build();
+        self.queries
+            .iter()
+            .map(|q| {
+                miniz.check_member(&rids[q.relationship], q.object, q.user)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::StoreDescription;
+
+    /// Simple xorshift generator so that the test is deterministic
+    struct XorShift(u64);
+
+    impl XorShift {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_random_stores() {
+        let mut rng = XorShift(0x5eed_1234_abcd_ef01);
+        for _ in 0..500 {
+            let len = (rng.next() % 512) as usize;
+            let bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            let description = StoreDescription::from_bytes(&bytes);
+            let results = description.run();
+            assert_eq!(results.len(), description.queries.len());
+
+            /* The results agree with the reference implementation. */
+            let (miniz, rids) = description.build();
+            let probes = description
+                .queries
+                .iter()
+                .map(|q| (rids[q.relationship].clone(), q.object, q.user))
+                .collect::<Vec<_>>();
+            assert!(miniz.verify_against_naive(&probes).is_empty());
+
+            /* The same input always produces the same store. */
+            assert_eq!(description, StoreDescription::from_bytes(&bytes));
+        }
+
+        /* Degenerate inputs still produce a valid, if boring, store. */
+        let empty = StoreDescription::from_bytes(&[]);
+        assert_eq!(empty.relationships.len(), 1);
+        assert!(empty.tuples.is_empty());
+        assert!(empty.run().is_empty());
+    }
+
+    #[cfg(feature = "arbitrary")]
+    #[test]
+    fn test_arbitrary_stores() {
+        use super::FuzzObjectId;
+        use super::FuzzUserId;
+        use crate::Member;
+        use crate::RelationshipId;
+        use arbitrary::Arbitrary;
+        use arbitrary::Unstructured;
+
+        /*
+         * This is what a `cargo fuzz` target would do with its input.  Each
+         * input yields a couple of stores, a member, and a relationship id.
+         */
+        let mut rng = XorShift(0x0a2b_17a2_c0f5_eed5);
+        for _ in 0..200 {
+            let len = (rng.next() % 1024) as usize;
+            let bytes: Vec<u8> = (0..len).map(|_| rng.next() as u8).collect();
+            let mut u = Unstructured::new(&bytes);
+            let first = StoreDescription::arbitrary(&mut u).unwrap();
+            let _: Member<FuzzObjectId, FuzzUserId> = u.arbitrary().unwrap();
+            let _: RelationshipId = u.arbitrary().unwrap();
+            let rest = StoreDescription::arbitrary_take_rest(u).unwrap();
+            for description in [first, rest].iter() {
+                let results = description.run();
+                assert_eq!(results.len(), description.queries.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+//! Interning string ids as small integers
+//!
+//! A store keyed by `String`s spends most of its memory on copies of those
+//! strings (every tuple is stored twice: once as a member and once in the
+//! reverse index) and much of its time comparing them.  An [`Interner`] maps
+//! each distinct string to a compact [`InternedId`] so that a
+//! `MiniZ<InternedId, InternedId>` stores and compares only integers.  The
+//! `*_str` methods on such a store translate strings on the way in.
+//!
+//! One interner can (and usually should) be used for both object ids and user
+//! ids: the same string always maps to the same `InternedId`, and
+//! [`crate::Member`] keeps objects and users apart regardless.
+
+use crate::Membership;
+use crate::MiniZ;
+use crate::RelationshipId;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+/// Compact id standing for a string in an [`Interner`]
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct InternedId(u32);
+
+///
+/// Maps strings to [`InternedId`]s and back
+///
+/// Ids are handed out sequentially starting from 0 and are never reused.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Interner {
+    ids: BTreeMap<String, InternedId>,
+    names: Vec<String>,
+}
+
+impl Interner {
+    /// Returns a new, empty interner
+    pub fn new() -> Interner {
+        Interner::default()
+    }
+
+    /// Returns the id for `name`, assigning a new one if necessary
+    pub fn intern(&mut self, name: &str) -> InternedId {
+        if let Some(id) = self.ids.get(name) {
+            return *id;
+        }
+
+        let id = InternedId(
+            u32::try_from(self.names.len()).expect("too many interned ids"),
+        );
+        self.ids.insert(name.to_owned(), id);
+        self.names.push(name.to_owned());
+        id
+    }
+
+    /// Returns the id for `name` if it has been interned
+    pub fn get(&self, name: &str) -> Option<InternedId> {
+        self.ids.get(name).copied()
+    }
+
+    /// Returns the string that `id` stands for
+    ///
+    /// Panics if `id` did not come from this interner.
+    pub fn resolve(&self, id: InternedId) -> &str {
+        &self.names[id.0 as usize]
+    }
+
+    /// Returns the number of distinct strings interned
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns whether nothing has been interned
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
+
+impl MiniZ<InternedId, InternedId> {
+    ///
+    /// Like [`MiniZ::write_object()`], but takes string ids and interns them
+    /// with `interner`
+    ///
+    pub fn write_object_str(
+        &mut self,
+        interner: &mut Interner,
+        rid: &RelationshipId,
+        parent: &str,
+        child: &str,
+    ) -> Membership<InternedId> {
+        let parent = interner.intern(parent);
+        let child = interner.intern(child);
+        self.write_object(rid, parent, child)
+    }
+
+    ///
+    /// Like [`MiniZ::write_user()`], but takes string ids and interns them
+    /// with `interner`
+    ///
+    pub fn write_user_str(
+        &mut self,
+        interner: &mut Interner,
+        rid: &RelationshipId,
+        parent: &str,
+        child: &str,
+    ) -> Membership<InternedId> {
+        let parent = interner.intern(parent);
+        let child = interner.intern(child);
+        self.write_user(rid, parent, child)
+    }
+
+    ///
+    /// Like [`MiniZ::check_member()`], but takes string ids and looks them up
+    /// in `interner`
+    ///
+    /// A string that has never been interned can't appear in the store, so
+    /// the check fails without consulting it.
+    ///
+    pub fn check_member_str(
+        &self,
+        interner: &Interner,
+        rid: &RelationshipId,
+        object: &str,
+        user: &str,
+    ) -> bool {
+        match (interner.get(object), interner.get(user)) {
+            (Some(object), Some(user)) => self.check_member(rid, object, user),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,390 @@
+//! JSON-lines format for exchanging tuples with other tools
+//!
+//! [`MiniZ::export_jsonl()`] writes each tuple in a store as one JSON object
+//! per line and [`MiniZ::import_jsonl()`] writes tuples read in that format
+//! into a store.  Unlike the [`binary`](crate::binary) format, only tuples are
+//! exchanged: the importing store must already have the relationships (and
+//! caveats) that the tuples refer to.
+//!
+//! Each line looks like this:
+//!
+//! ```text
+//! {"object":"doc123","relation":"viewer","subject":"user:alice"}
+//! ```
+//!
+//! The subject is `user:` followed by the user id or `object:` followed by the
+//! object id, depending on what kind of member it is.  A
+//! [`Member::ObjectRelation`] subject is `userset:` followed by the object id,
+//! `#`, and the relationship's name, as in `userset:eng#member`.  (The object
+//! id may contain `#`, but the relationship's name may not.)  A tuple written
+//! with a caveat also has a `"caveat"` key whose value is the caveat's name.
+//! All values are JSON strings, and object and user ids are converted to and
+//! from strings by their [`JsonId`] impls.  Blank lines are ignored on import.
+
+use crate::CaveatId;
+use crate::Member;
+use crate::MiniZ;
+use crate::RelationshipId;
+use crate::Tuple;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::string::String;
+use alloc::string::ToString;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::Peekable;
+use core::str::Chars;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+
+const USER_PREFIX: &str = "user:";
+const OBJECT_PREFIX: &str = "object:";
+const USERSET_PREFIX: &str = "userset:";
+
+///
+/// An object or user id type that can be exchanged in the JSON-lines format
+///
+/// `from_json_string()` must accept exactly what `to_json_string()` produces.
+///
+pub trait JsonId: Sized {
+    /// Returns the string that represents `self`
+    fn to_json_string(&self) -> String;
+    /// Returns the id represented by `s`, or `None` if `s` isn't valid
+    fn from_json_string(s: String) -> Option<Self>;
+}
+
+impl JsonId for String {
+    fn to_json_string(&self) -> String {
+        self.clone()
+    }
+
+    fn from_json_string(s: String) -> Option<Self> {
+        Some(s)
+    }
+}
+
+macro_rules! impl_json_id_int {
+    ($t:ty) => {
+        impl JsonId for $t {
+            fn to_json_string(&self) -> String {
+                self.to_string()
+            }
+
+            fn from_json_string(s: String) -> Option<Self> {
+                s.parse().ok()
+            }
+        }
+    };
+}
+
+impl_json_id_int!(u8);
+impl_json_id_int!(u16);
+impl_json_id_int!(u32);
+impl_json_id_int!(u64);
+
+impl<O, U> MiniZ<O, U>
+where
+    O: JsonId + Clone + fmt::Debug + Ord,
+    U: JsonId + Clone + fmt::Debug + Ord,
+{
+    ///
+    /// Write every tuple in this store to `w`, one JSON object per line
+    ///
+    /// See the [`jsonl`](crate::jsonl) module for details.  Callers writing
+    /// to a file or socket probably want to use a buffered writer.
+    ///
+    pub fn export_jsonl<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for (rid, set) in &self.sets {
+            for (parent, members) in &set.direct_members {
+                for member in members {
+                    let subject = match member {
+                        Member::Object(object) => {
+                            format!(
+                                "{}{}",
+                                OBJECT_PREFIX,
+                                object.to_json_string()
+                            )
+                        }
+                        Member::User(user) => {
+                            format!("{}{}", USER_PREFIX, user.to_json_string())
+                        }
+                        Member::ObjectRelation { object, relation } => {
+                            format!(
+                                "{}{}#{}",
+                                USERSET_PREFIX,
+                                object.to_json_string(),
+                                relation.0
+                            )
+                        }
+                    };
+                    let mut line = String::from("{");
+                    push_field(&mut line, "object", &parent.to_json_string());
+                    line.push(',');
+                    push_field(&mut line, "relation", &rid.0);
+                    line.push(',');
+                    push_field(&mut line, "subject", &subject);
+                    if let Some(caveat_id) = set.caveat_for(parent, member) {
+                        line.push(',');
+                        push_field(&mut line, "caveat", &caveat_id.0);
+                    }
+                    line.push_str("}\n");
+                    w.write_all(line.as_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    ///
+    /// Read tuples written by [`MiniZ::export_jsonl()`] (or another tool
+    /// using the same format) from `r` and write them into this store
+    ///
+    /// Every relationship and caveat named in the input must already exist in
+    /// this store.  The input is checked in its entirety before any tuples are
+    /// written, so if this returns an error, the store is unchanged.  Input
+    /// that can't be parsed, that refers to a relationship or caveat that
+    /// doesn't exist, that has a member of a kind its relationship doesn't
+    /// allow, or that repeats a tuple (or a tuple that's already in the store)
+    /// produces an error of kind [`io::ErrorKind::InvalidData`] whose message
+    /// includes the line number.  Returns the number of tuples written.
+    ///
+    pub fn import_jsonl<R: BufRead>(&mut self, r: R) -> io::Result<usize> {
+        let mut tuples = Vec::new();
+        let mut seen = BTreeSet::new();
+        for (i, line) in r.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let (tuple, caveat) = self
+                .parse_tuple(&line)
+                .map_err(|message| invalid(i + 1, message))?;
+            if !seen.insert(tuple.clone()) {
+                return Err(invalid(i + 1, "duplicate tuple"));
+            }
+            tuples.push((tuple, caveat));
+        }
+
+        let count = tuples.len();
+        for (tuple, caveat) in tuples {
+            self.write_member(
+                &tuple.rid,
+                tuple.parent,
+                tuple.member,
+                caveat.as_ref(),
+            );
+        }
+        Ok(count)
+    }
+
+    /// Parses one line of the JSON-lines format and validates the resulting
+    /// tuple against this store
+    fn parse_tuple(
+        &self,
+        line: &str,
+    ) -> Result<(Tuple<O, U>, Option<CaveatId>), &'static str> {
+        let mut fields = parse_object(line)?;
+        let mut take = |key| fields.remove(key).ok_or("missing key");
+        let object = take("object")?;
+        let relation = take("relation")?;
+        let subject = take("subject")?;
+        let caveat = fields.remove("caveat").map(CaveatId);
+        if !fields.is_empty() {
+            return Err("unexpected key");
+        }
+
+        let rid = RelationshipId(relation);
+        let set = self.sets.get(&rid).ok_or("unknown relationship")?;
+        let parent = O::from_json_string(object).ok_or("bad object id")?;
+        let member = if let Some(user) = subject.strip_prefix(USER_PREFIX) {
+            Member::User(
+                U::from_json_string(user.to_owned()).ok_or("bad user id")?,
+            )
+        } else if let Some(object) = subject.strip_prefix(OBJECT_PREFIX) {
+            Member::Object(
+                O::from_json_string(object.to_owned())
+                    .ok_or("bad object id")?,
+            )
+        } else if let Some(userset) = subject.strip_prefix(USERSET_PREFIX) {
+            let (object, relation) =
+                userset.rsplit_once('#').ok_or("userset without '#'")?;
+            let relation = RelationshipId(relation.to_owned());
+            if !self.sets.contains_key(&relation) {
+                return Err("unknown relationship");
+            }
+            Member::ObjectRelation {
+                object: O::from_json_string(object.to_owned())
+                    .ok_or("bad object id")?,
+                relation,
+            }
+        } else {
+            return Err("subject is not a user, object, or userset");
+        };
+        if !set.accepts_member(&member) {
+            return Err("member of unexpected kind");
+        }
+        if set
+            .direct_members
+            .get(&parent)
+            .is_some_and(|members| members.contains(&member))
+        {
+            return Err("tuple already exists");
+        }
+        if let Some(caveat_id) = &caveat {
+            if !self.caveats.contains_key(caveat_id) {
+                return Err("unknown caveat");
+            }
+        }
+
+        Ok((Tuple { rid, parent, member }, caveat))
+    }
+}
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line, message),
+    )
+}
+
+/// Appends `"key":"value"` to `line`, escaping as needed
+fn push_field(line: &mut String, key: &str, value: &str) {
+    push_json_str(line, key);
+    line.push(':');
+    push_json_str(line, value);
+}
+
+fn push_json_str(line: &mut String, s: &str) {
+    line.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => line.push_str("\\\""),
+            '\\' => line.push_str("\\\\"),
+            '\n' => line.push_str("\\n"),
+            '\r' => line.push_str("\\r"),
+            '\t' => line.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                line.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => line.push(c),
+        }
+    }
+    line.push('"');
+}
+
+///
+/// Parses a JSON object whose values are all strings
+///
+/// This is all that the format needs, so we don't bother with the rest of
+/// JSON.
+///
+fn parse_object(line: &str) -> Result<BTreeMap<String, String>, &'static str> {
+    let mut chars = line.chars().peekable();
+    let mut fields = BTreeMap::new();
+    skip_whitespace(&mut chars);
+    if chars.next() != Some('{') {
+        return Err("expected a JSON object");
+    }
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            if chars.next() != Some(':') {
+                return Err("expected ':'");
+            }
+            skip_whitespace(&mut chars);
+            let value = parse_string(&mut chars)?;
+            if fields.insert(key, value).is_some() {
+                return Err("duplicate key");
+            }
+            skip_whitespace(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'"),
+            }
+        }
+    }
+    skip_whitespace(&mut chars);
+    if chars.next().is_some() {
+        return Err("unexpected characters after object");
+    }
+    Ok(fields)
+}
+
+fn skip_whitespace(chars: &mut Peekable<Chars<'_>>) {
+    while chars.next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r')).is_some() {}
+}
+
+fn parse_string(
+    chars: &mut Peekable<Chars<'_>>,
+) -> Result<String, &'static str> {
+    if chars.next() != Some('"') {
+        return Err("expected a string");
+    }
+
+    let mut s = String::new();
+    loop {
+        match chars.next().ok_or("unterminated string")? {
+            '"' => return Ok(s),
+            '\\' => {
+                s.push(match chars.next().ok_or("unterminated string")? {
+                    '"' => '"',
+                    '\\' => '\\',
+                    '/' => '/',
+                    'b' => '\u{8}',
+                    'f' => '\u{c}',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => parse_unicode_escape(chars)?,
+                    _ => return Err("bad escape"),
+                })
+            }
+            c if (c as u32) < 0x20 => {
+                return Err("control character in string")
+            }
+            c => s.push(c),
+        }
+    }
+}
+
+/// Parses the rest of a `\u` escape, including the second half of a
+/// surrogate pair
+fn parse_unicode_escape(
+    chars: &mut Peekable<Chars<'_>>,
+) -> Result<char, &'static str> {
+    let high = parse_hex4(chars)?;
+    let code = if (0xd800..0xdc00).contains(&high) {
+        if chars.next() != Some('\\') || chars.next() != Some('u') {
+            return Err("unpaired surrogate");
+        }
+        let low = parse_hex4(chars)?;
+        if !(0xdc00..0xe000).contains(&low) {
+            return Err("unpaired surrogate");
+        }
+        0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+    } else {
+        high
+    };
+    char::from_u32(code).ok_or("bad unicode escape")
+}
+
+fn parse_hex4(chars: &mut Peekable<Chars<'_>>) -> Result<u32, &'static str> {
+    (0..4).try_fold(0, |code, _| {
+        let digit = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or("bad unicode escape")?;
+        Ok(code * 16 + digit)
+    })
+}
@@ -1,11 +1,14 @@
-/// Tiny in-memory implementation of the Zanzibar data model
-///
-/// See [`MiniZ`] for basic usage information.
-///
-/// A few notes:
-///
-/// * We use the term "relationship" where Zanzibar uses the term "relation" to
-///   avoid confusion with the database term "relation".
+//! Tiny in-memory implementation of the Zanzibar data model
+//!
+//! See [`MiniZ`] for basic usage information.
+//!
+//! A few notes:
+//!
+//! * We use the term "relationship" where Zanzibar uses the term "relation" to
+//!   avoid confusion with the database term "relation".
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
 /*
  * TODO:
  *
@@ -27,15 +30,45 @@
  *   cloning all over the place.
  * - Add an example
  */
-use std::collections::BTreeMap;
-use std::collections::BTreeSet;
-use std::fmt;
+extern crate alloc;
+
+#[cfg(any(feature = "std", test))]
+pub mod binary;
+#[cfg(any(test, feature = "arbitrary"))]
+pub mod fuzz;
+pub mod intern;
+#[cfg(any(feature = "std", test))]
+pub mod jsonl;
+pub mod memory;
+pub mod schema;
+pub mod source;
+pub mod typed;
+
+use alloc::borrow::ToOwned;
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::collections::BTreeSet;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::hash::Hash;
+use core::hash::Hasher;
+use core::ops::Bound;
 
 /// Unique id for a user-defined relationship
-#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct RelationshipId(String);
 
-#[derive(Debug)]
+impl fmt::Display for RelationshipId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 struct Relationship<O, U> {
     /// For a given object, set of Members (objects or users) having this
     /// relationship with that object
@@ -60,13 +93,166 @@ struct Relationship<O, U> {
     /// well.  The implementation here would include "parent" in the set of
     /// inherited relationships for "viewer".
     ///
-    /// Our implementation is less general than Zanzibar's, in that Zanzibar
-    /// supports saying something like: if U has a "recursive-viewer"
-    /// relationship with an object O1, and O1 is a parent of O2, then U has a
-    /// "viewer" relationship with O2.  We require that the two relationships be
-    /// the same.  This seems easy to generalize, though.
+    /// This maps each inherited relationship (like "parent") to the
+    /// relationships to check on the objects found through it.  Usually that's
+    /// just this relationship, but see
+    /// [`RelationshipBuilder::with_inherited_as`].
+    ///
+    inherited_relationships: BTreeMap<RelationshipId, BTreeSet<RelationshipId>>,
+
+    /// Relationship to resolve on objects that are direct members of this one
+    ///
+    /// See [`RelationshipBuilder::with_userset_member_relation`].
+    userset_member_relation: Option<RelationshipId>,
+
+    /// Relationships whose users never have this relationship
+    ///
+    /// See [`RelationshipBuilder::with_deny_relation`].
+    deny_relationships: BTreeSet<RelationshipId>,
+
+    /// Human-readable description of this relationship, for tooling
+    ///
+    /// See [`RelationshipBuilder::with_description`].
+    description: Option<String>,
+
+    /// Kind of member that this relationship may hold, if restricted
+    ///
+    /// See [`RelationshipBuilder::expect_objects`].
+    expected_member_kind: Option<MemberKind>,
+
+    /// Whether checks consider only this relationship's direct members
+    ///
+    /// See [`RelationshipBuilder::direct_only`].
+    direct_only: bool,
+
+    /// For a given object, the caveats on any of its direct members that were
+    /// written with one
+    ///
+    /// See [`MiniZ::write_user_caveated`].
+    caveated_members: BTreeMap<O, BTreeMap<Member<O, U>, CaveatId>>,
+}
+
+impl<O, U> Relationship<O, U> {
+    /// Returns a copy of this relationship's configuration with no members
+    fn clone_schema(&self) -> Relationship<O, U> {
+        Relationship {
+            direct_members: BTreeMap::new(),
+            contained_relationships: self.contained_relationships.clone(),
+            inherited_relationships: self.inherited_relationships.clone(),
+            userset_member_relation: self.userset_member_relation.clone(),
+            deny_relationships: self.deny_relationships.clone(),
+            description: self.description.clone(),
+            expected_member_kind: self.expected_member_kind,
+            direct_only: self.direct_only,
+            caveated_members: BTreeMap::new(),
+        }
+    }
+
+    /// Returns the caveat on the tuple for `member` on `object`, if any
+    fn caveat_for(&self, object: &O, member: &Member<O, U>) -> Option<&CaveatId>
+    where
+        O: Ord,
+        U: Ord,
+    {
+        self.caveated_members
+            .get(object)
+            .and_then(|caveated| caveated.get(member))
+    }
+
+    ///
+    /// Returns the relationships this one refers to: its subsets, inherited
+    /// sets, userset member relationship, and deny relationships
+    ///
+    /// These are the edges from this relationship in the graph used by
+    /// [`MiniZ::relation_reachable()`].
+    ///
+    fn references(&self) -> impl Iterator<Item = &RelationshipId> {
+        self.contained_relationships
+            .iter()
+            .chain(self.inherited_relationships.keys())
+            .chain(self.inherited_relationships.values().flatten())
+            .chain(self.userset_member_relation.iter())
+            .chain(self.deny_relationships.iter())
+    }
+
+    ///
+    /// Returns the (relationship, object) pair whose users are members of
+    /// this relationship by way of direct member `member`, if any
+    ///
+    /// That's the userset that `member` names, if it's a
+    /// [`Member::ObjectRelation`], or the object member followed as a userset
+    /// of [`Relationship::userset_member_relation`], if that's configured.
+    /// Direct-only relationships don't follow any members.  Both
+    /// [`MiniZ::edges()`] and [`MiniZ::check_member_async()`] use this, so
+    /// that they agree on which members to follow.
+    ///
+    fn userset_edge<'a>(
+        &'a self,
+        member: &'a Member<O, U>,
+    ) -> Option<(&'a RelationshipId, &'a O)> {
+        if self.direct_only {
+            return None;
+        }
+        match member {
+            Member::User(_) => None,
+            Member::Object(group) => {
+                self.userset_member_relation.as_ref().map(|rid| (rid, group))
+            }
+            Member::ObjectRelation { object, relation } => {
+                Some((relation, object))
+            }
+        }
+    }
+
+    ///
+    /// Returns the relationships to resolve on an object that this
+    /// relationship's object has relationship `via` with
     ///
-    inherited_relationships: BTreeSet<RelationshipId>,
+    /// Like [`Relationship::userset_edge()`], this is shared by
+    /// [`MiniZ::edges()`] and [`MiniZ::check_member_async()`].
+    ///
+    fn inherited_edges<'a>(
+        &'a self,
+        via: &RelationshipId,
+    ) -> impl Iterator<Item = &'a RelationshipId> + 'a {
+        self.inherited_relationships
+            .get(via)
+            .filter(|_| !self.direct_only)
+            .into_iter()
+            .flatten()
+    }
+
+    /// Returns whether `member` is the kind of member this relationship holds
+    fn accepts_member(&self, member: &Member<O, U>) -> bool {
+        !matches!(
+            (self.expected_member_kind, member),
+            (
+                Some(MemberKind::Object),
+                Member::User(_) | Member::ObjectRelation { .. }
+            ) | (Some(MemberKind::User), Member::Object(_))
+        )
+    }
+
+    /// Panics if `member` is not the kind of member this relationship holds
+    fn check_member_kind(&self, rid: &RelationshipId, member: &Member<O, U>) {
+        if !self.accepts_member(member) {
+            match member {
+                Member::User(_) | Member::ObjectRelation { .. } => {
+                    panic!("relationship {:?} only has object members", rid.0)
+                }
+                Member::Object(_) => {
+                    panic!("relationship {:?} only has user members", rid.0)
+                }
+            }
+        }
+    }
+}
+
+/// Kinds of [`Member`]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum MemberKind {
+    Object,
+    User,
 }
 
 ///
@@ -74,31 +260,74 @@ struct Relationship<O, U> {
 ///
 /// Confusingly, Zanzibar calls this a "user", and it may be either a user_id or
 /// a "userset", and a "userset" is essentially an object-relation combination.
+/// Here, a userset is a [`Member::ObjectRelation`]: writing it as a member of
+/// relationship R1 on object O1 means that every user who has relationship
+/// `relation` with `object` also has R1 with O1.  (`group:eng#member` in
+/// Zanzibar's notation would be `Member::ObjectRelation { object: "eng",
+/// relation: "member" }`.)
+///
+/// Objects and users may come from the same id space (i.e., `O` and `U` may
+/// be the same type).  That's fine: a `Member::Object(x)` and a
+/// `Member::User(x)` are always distinct, both as members of a relationship
+/// and in the index of memberships, so writing `x` as a user never makes it
+/// look like an object member or vice versa.  [`Member::object()`] and
+/// [`Member::user()`] make it clearer which one is meant in code where the
+/// types alone don't say.
 ///
 //
-// XXX This seems like a divergence: S2.1 of the paper says that a tuple could
-// be:
+// S2.1 of the paper says that a tuple could be:
 //
 //     object_id # relation @ object_id # relation
 //
-// What does this mean?  I erroneusly assumed that this form meant:
+// which means that the set of _users_ having relationship R2 to the second
+// object have relationship R1 to the first object.  That's
+// `Member::ObjectRelation`.  We also support the form:
 //
 //     object_id # relation @ object_id
 //
-// which would clearly mean the second object has the given relationship to the
-// first object.
-//
-// I guess the first one means that the set of _users_ having relationship R2 to
-// the second object have relationship R1 to the first object.  But if that's
-// true: how do you express relationships between objects?  What if you want to
-// say that O1 is a parent of O2?
+// (`Member::Object`), which means the second object itself has the given
+// relationship to the first object.  That's how we express relationships
+// between objects, like O1 being the parent of O2.
 //
-#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Member<O, U> {
     /// an object has the relationship to the given object
     Object(O),
     /// a user has the relationship to the given object
     User(U),
+    /// every user having relationship `relation` with object `object` has the
+    /// relationship to the given object
+    ObjectRelation { object: O, relation: RelationshipId },
+}
+
+impl<O, U> Member<O, U> {
+    /// Returns a member describing object `object`
+    pub fn object(object: O) -> Self {
+        Member::Object(object)
+    }
+
+    /// Returns a member describing user `user`
+    pub fn user(user: U) -> Self {
+        Member::User(user)
+    }
+
+    /// Returns a member describing the users having relationship `relation`
+    /// with object `object`
+    pub fn object_relation(object: O, relation: &RelationshipId) -> Self {
+        Member::ObjectRelation { object, relation: relation.clone() }
+    }
+
+    /// Returns a member that borrows the id of this one
+    pub fn as_ref(&self) -> Member<&O, &U> {
+        match self {
+            Member::Object(object) => Member::Object(object),
+            Member::User(user) => Member::User(user),
+            Member::ObjectRelation { object, relation } => {
+                Member::ObjectRelation { object, relation: relation.clone() }
+            }
+        }
+    }
 }
 
 ///
@@ -106,7 +335,7 @@ pub enum Member<O, U> {
 ///
 /// This is the dual of a [`Member`].
 ///
-#[derive(Debug, Eq, Ord, PartialEq, PartialOrd)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub struct Membership<O> {
     /// the object has relationship `rid`
     pub rid: RelationshipId,
@@ -115,519 +344,10213 @@ pub struct Membership<O> {
 }
 
 ///
-/// Builder for a [`MiniZ`]
+/// Describes one direct relationship: `member` has relationship `rid` with
+/// object `parent`
 ///
-/// Conceptually, this object provides facilities that you might use when
-/// processing a Zanzibar configuration file.  Anything that can be changed at
-/// runtime (outside the configuration) does not belong here.
+/// This is what Zanzibar calls a "relation tuple".  See
+/// [`MiniZ::write_tuple()`].
 ///
-/// The only thing you can do with this is define relationships and then build a
-/// [`MiniZ`].
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Tuple<O, U> {
+    /// the relationship
+    pub rid: RelationshipId,
+    /// the object on which the relationship is written
+    pub parent: O,
+    /// the object or user having the relationship with `parent`
+    pub member: Member<O, U>,
+}
+
 ///
-#[derive(Debug)]
-pub struct MiniZBuilder<O, U> {
-    /// Configured relationships
-    relationships: BTreeMap<RelationshipId, Relationship<O, U>>,
+/// One write in a batch applied with [`MiniZ::apply_writes()`] or
+/// [`MiniZ::apply_writes_besteffort()`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Write<O, U> {
+    /// write the tuple (if it doesn't already exist)
+    Insert(Tuple<O, U>),
+    /// remove the tuple (if it exists)
+    Delete(Tuple<O, U>),
 }
 
-impl<O, U> MiniZBuilder<O, U>
-where
-    O: Clone + fmt::Debug + Ord,
-    U: Clone + fmt::Debug + Ord,
-{
-    /// Defines a new `Relationship` named `relationship_name`
-    pub fn new_relationship<S: AsRef<str>>(
-        &mut self,
-        relationship_name: S,
-    ) -> RelationshipBuilder<'_, O, U> {
-        RelationshipBuilder {
-            miniz_builder: self,
-            name: relationship_name.as_ref().to_owned(),
-            contained_relationships: BTreeSet::new(),
-            inherited_relationships: BTreeSet::new(),
+///
+/// Error describing why a [`Write`] can't be applied
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WriteError {
+    /// the write refers to relationship `rid`, which does not exist
+    NoSuchRelationship(RelationshipId),
+    /// the write would add a member of a kind that relationship `rid` does not
+    /// allow (see [`RelationshipBuilder::expect_objects()`])
+    UnexpectedMemberKind(RelationshipId),
+}
+
+impl fmt::Display for WriteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WriteError::NoSuchRelationship(rid) => {
+                write!(
+                    f,
+                    "write refers to nonexistent relationship \"{}\"",
+                    rid
+                )
+            }
+            WriteError::UnexpectedMemberKind(rid) => write!(
+                f,
+                "relationship \"{}\" does not allow members of this kind",
+                rid
+            ),
         }
     }
+}
 
-    /// Returns a `MiniZ` with the configuration defined in the builder
-    pub fn build(self) -> MiniZ<O, U> {
-        MiniZ { sets: self.relationships, memberships: BTreeMap::new() }
-    }
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for WriteError {}
+
+///
+/// Describes one change recorded in a [`MiniZ`]'s log
+///
+/// See [`MiniZ::enable_log()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LogOp<O, U> {
+    /// the tuple was written
+    Write(Tuple<O, U>),
+    /// the tuple was written with a caveat
+    WriteCaveated(Tuple<O, U>, CaveatId),
+    /// the tuple was removed
+    Remove(Tuple<O, U>),
 }
 
-/// Used to configure a `Relationship`.  See [`MiniZBuilder`].
-pub struct RelationshipBuilder<'a, O, U> {
-    miniz_builder: &'a mut MiniZBuilder<O, U>,
-    name: String,
-    contained_relationships: BTreeSet<RelationshipId>,
-    inherited_relationships: BTreeSet<RelationshipId>,
+///
+/// One entry in a [`MiniZ`]'s log
+///
+/// See [`MiniZ::enable_log()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LogEntry<O, U> {
+    /// version of the store immediately after this change was applied
+    pub version: u64,
+    /// the change
+    pub op: LogOp<O, U>,
 }
 
-impl<'a, O, U> RelationshipBuilder<'a, O, U>
-where
-    O: Clone + fmt::Debug + Ord,
-    U: Clone + fmt::Debug + Ord,
-{
-    ///
-    /// Specify that the relationship `subrid` implies the current relationship
+///
+/// How fresh the answer from [`MiniZ::check_member_consistency()`] must be
+///
+/// These are loosely modeled on the consistency options for checks in the
+/// Zanzibar paper, with the check cache (see [`MiniZ::enable_check_cache()`])
+/// standing in for a stale snapshot.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Consistency {
+    /// reflect every change made to the store so far, using a cached result
+    /// only if no change since it was computed could have affected it
     ///
-    /// A typical example: the "owner" relationship implies the "viewer"
-    /// relationship, which means that any user that's an "owner" is also a
-    /// "viewer".  More formally, if an object O1 has relationship `subrid` to
-    /// another object O2, then it also has this relationship with O2.
+    /// This is what [`MiniZ::check_member()`] does.
+    Strong,
+    /// use any cached result for the same check, even if it's been
+    /// invalidated by a change to the store since it was computed
     ///
-    pub fn with_subset(mut self, subrid: &RelationshipId) -> Self {
-        self.contained_relationships.insert(subrid.clone());
-        self
-    }
+    /// The answer may be stale until a `Strong` check of the same
+    /// relationship, object, and user refreshes it or the schema changes.
+    /// Without a cached result, this is the same as `Strong`.
+    MinimizeLatency,
+}
 
+///
+/// Result of [`MiniZ::check_member_detailed()`]
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CheckResult {
+    /// the user has the relationship with the object
+    Allowed,
+    /// the user does not have the relationship with the object
+    Denied,
+    /// the user does not have the relationship with the object, and the store
+    /// knows nothing at all about the object: it has no members in any
+    /// relationship and it's not a member of any relationship
     ///
-    /// Specify that the relationship `subrid` is inherited by the current
-    /// relationship
-    ///
-    /// This is similar to what Zanzibar calls "tuple_to_userset" when combined
-    /// with "computed_userset".  
-    ///
-    /// A typical example: we say that the "viewer" relationship inherits the
-    /// "parent" relationship, which means that if a user U has a "viewer"
-    /// relationship to an object O1, and object O1 has the "parent"
-    /// relationship to object O2, then U has a "viewer" relationship to O2 as
-    /// well.  The implementation here would include "parent" in the set of
-    /// inherited relationships for "viewer".
-    ///
-    /// Our implementation is less general than Zanzibar's, in that Zanzibar
-    /// supports saying something like: if U has a "recursive-viewer"
-    /// relationship with an object O1, and O1 is a parent of O2, then U has a
-    /// "viewer" relationship with O2.  Unlike Zanzibar, we require that the two
-    /// relationships be the same.  (This seems easy to generalize, though.)
-    ///
-    pub fn with_inherited_set(mut self, rid: &RelationshipId) -> Self {
-        self.inherited_relationships.insert(rid.clone());
-        self
-    }
+    /// This often indicates a bad object id or missing data rather than a
+    /// genuine denial.
+    ObjectUnknown,
+}
 
-    ///
-    /// Add the relationship configured by this builder to the parent
-    /// [`MiniZBuilder`] and return a [`RelationshipId`] for it.
-    ///
-    pub fn build(self) -> RelationshipId {
-        let rid = RelationshipId(self.name);
-        self.miniz_builder.relationships.insert(
-            rid.clone(),
-            Relationship {
-                direct_members: BTreeMap::new(),
-                contained_relationships: self.contained_relationships,
-                inherited_relationships: self.inherited_relationships,
-            },
-        );
+///
+/// Error returned by [`MiniZ::try_check_member()`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CheckError {
+    /// the check refers to relationship `rid`, which does not exist
+    NoSuchRelationship(RelationshipId),
+}
 
-        rid
+impl fmt::Display for CheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CheckError::NoSuchRelationship(rid) => {
+                write!(
+                    f,
+                    "check refers to nonexistent relationship \"{}\"",
+                    rid
+                )
+            }
+        }
     }
 }
 
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for CheckError {}
+
 ///
-/// A toy in-memory implementation of the Zanzibar data model
+/// Describes a direct relationship that would grant a user access
 ///
-/// In the Zanzibar data model, consumers statically configure relationships
-/// (like "parent" or "viewer").  At runtime, consumers add or remove specific
-/// relationships (e.g., X is a "parent" of Y).  In this API, the static
-/// configuration is provided via the [`MiniZBuilder`] (see
-/// [`MiniZ::builder()`]).  The runtime operations are provided by [`MiniZ`].
+/// See [`MiniZ::explain_denied()`].
 ///
-/// See the test case below for an example that corresponds to the one in the
-/// Zanzibar paper.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct GrantSuggestion<O> {
+    /// the user would need relationship `rid`
+    pub rid: RelationshipId,
+    /// with object `object`
+    pub object: O,
+}
+
 ///
-pub struct MiniZ<O, U> {
-    sets: BTreeMap<RelationshipId, Relationship<O, U>>,
-    memberships: BTreeMap<Member<O, U>, BTreeSet<Membership<O>>>,
+/// One step in the resolution of a relationship
+///
+/// Each step explains why a user has relationship `rid` with object `object`:
+/// either because they're a direct member or because they have some other
+/// relationship (described by the step) that implies it.  See
+/// [`MiniZ::shortest_grant_path()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CheckStep<O> {
+    /// the user is a direct member of `rid` for `object`
+    Direct { rid: RelationshipId, object: O },
+    /// `rid` includes `subrid` on the same object
+    /// (see [`RelationshipBuilder::with_subset()`])
+    Subset { rid: RelationshipId, object: O, subrid: RelationshipId },
+    /// object `group` is a direct member of `rid` for `object`, and it stands
+    /// for the users having relationship `member_rid` with it
+    /// (see [`RelationshipBuilder::with_userset_member_relation()`])
+    UsersetMember {
+        rid: RelationshipId,
+        object: O,
+        group: O,
+        member_rid: RelationshipId,
+    },
+    /// `object` has relationship `via` with `parent`, through which `rid`
+    /// inherits `parent_rid` (see [`RelationshipBuilder::with_inherited_as()`])
+    Inherited {
+        rid: RelationshipId,
+        object: O,
+        via: RelationshipId,
+        parent: O,
+        parent_rid: RelationshipId,
+    },
 }
 
-impl<O, U> MiniZ<O, U>
-where
-    O: Clone + fmt::Debug + Ord,
-    U: Clone + fmt::Debug + Ord,
-{
-    /// Return a builder used to configure relationships known to this instance
-    pub fn builder() -> MiniZBuilder<O, U> {
-        MiniZBuilder { relationships: BTreeMap::new() }
-    }
+///
+/// Record of everything that a check explored
+///
+/// See [`MiniZ::trace_check()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CheckTrace<O> {
+    /// whether the check succeeded
+    pub allowed: bool,
+    /// what happened at each (relationship, object) pair, in order
+    pub steps: Vec<TraceStep<O>>,
+}
 
-    /*
-     * Write operations
-     */
+///
+/// One thing that happened while resolving relationship `rid` on object
+/// `object`
+///
+/// See [`MiniZ::trace_check()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceStep<O> {
+    /// relationship being resolved
+    pub rid: RelationshipId,
+    /// object on which it's being resolved
+    pub object: O,
+    /// what happened
+    pub outcome: TraceOutcome<O>,
+}
 
+///
+/// What happened in a [`TraceStep`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TraceOutcome<O> {
+    /// the user is a direct member of this set, so the check succeeded
+    DirectHit,
+    /// the user is not a direct member of this set
+    DirectMiss,
+    /// descended into `subrid` on the same object
+    SubsetDescended { subrid: RelationshipId },
+    /// descended into `member_rid` on `group`, an object member of this set
+    UsersetDescended { group: O, member_rid: RelationshipId },
+    /// descended into `parent_rid` on `parent`, which this object has
+    /// relationship `via` with
+    InheritedDescended {
+        via: RelationshipId,
+        parent: O,
+        parent_rid: RelationshipId,
+    },
+    /// everything reachable from this set was explored without finding the
+    /// user
+    DeadEnd,
+}
+
+///
+/// What a [`CheckVisitor`] wants [`MiniZ::walk()`] to do next
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WalkAction {
+    /// keep going (descending into the set just described, if any)
+    Continue,
+    /// keep going, but don't descend into the set just described
     ///
-    /// Specify that object `child` directly has the `rid` relationship to
-    /// object `parent`
-    ///
-    /// (The relationship need not be hierarchical like "parent" is, but it's
-    /// easier to talk about the two objects with concrete names.)
-    ///
-    pub fn write_object(&mut self, rid: &RelationshipId, parent: O, child: O) {
-        let set = self.sets.get_mut(rid).expect("no such set");
-        let members = set
-            .direct_members
-            .entry(parent.clone())
-            .or_insert_with(BTreeSet::new);
-        let new_value = Member::Object(child);
-        assert!(!members.contains(&new_value));
-        assert!(members.insert(new_value.clone()));
+    /// This is the same as `Continue` for a direct member.
+    Skip,
+    /// stop the walk immediately
+    Stop,
+}
 
-        /* Update the reverse index. */
-        let memberships =
-            self.memberships.entry(new_value).or_insert_with(BTreeSet::new);
-        memberships.insert(Membership { rid: rid.clone(), object: parent });
+///
+/// Receives callbacks as [`MiniZ::walk()`] traverses the sets that make up a
+/// relationship
+///
+/// Every method has a default implementation that does nothing and returns
+/// [`WalkAction::Continue`], so implementations need only provide the
+/// callbacks they care about.  The "enter" callbacks are only invoked for
+/// sets that the walk has not already descended into.
+///
+pub trait CheckVisitor<O, U> {
+    /// `member` is a direct member of `rid` for `object`
+    fn visit_direct_member(
+        &mut self,
+        _rid: &RelationshipId,
+        _object: &O,
+        _member: &Member<O, U>,
+    ) -> WalkAction {
+        WalkAction::Continue
     }
 
-    ///
-    /// Specify that user `child` directly has the `rid` relationship to object
-    /// `parent`
-    ///
-    /// (The relationship need not be hierarchical like "parent" is, but it's
-    /// easier to talk about the two objects with concrete names.)
-    ///
-    pub fn write_user(&mut self, rid: &RelationshipId, parent: O, child: U) {
-        let set = self.sets.get_mut(rid).expect("no such set");
-        let members = set
-            .direct_members
-            .entry(parent.clone())
-            .or_insert_with(BTreeSet::new);
-        let new_value = Member::User(child);
-        assert!(!members.contains(&new_value));
-        assert!(members.insert(new_value.clone()));
+    /// the walk is about to descend from `rid` into its subset `subrid` on the
+    /// same object
+    fn enter_subset(
+        &mut self,
+        _rid: &RelationshipId,
+        _object: &O,
+        _subrid: &RelationshipId,
+    ) -> WalkAction {
+        WalkAction::Continue
+    }
 
-        /* Update the reverse index. */
-        let memberships =
-            self.memberships.entry(new_value).or_insert_with(BTreeSet::new);
-        memberships.insert(Membership { rid: rid.clone(), object: parent });
+    /// the walk is about to descend from `rid` on `object` into `member_rid`
+    /// on `group`, an object member followed as a userset
+    fn enter_userset_member(
+        &mut self,
+        _rid: &RelationshipId,
+        _object: &O,
+        _group: &O,
+        _member_rid: &RelationshipId,
+    ) -> WalkAction {
+        WalkAction::Continue
     }
 
-    /*
-     * Read operations
-     */
+    /// the walk is about to descend from `rid` on `object` into `parent_rid`
+    /// on `parent`, which `object` has relationship `via` with
+    fn enter_inherited(
+        &mut self,
+        _rid: &RelationshipId,
+        _object: &O,
+        _via: &RelationshipId,
+        _parent: &O,
+        _parent_rid: &RelationshipId,
+    ) -> WalkAction {
+        WalkAction::Continue
+    }
+}
 
+///
+/// Result of [`MiniZ::effective_user_count_hint()`]
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CountHint {
+    /// exactly this many users have the relationship
+    Exact(usize),
+    /// at least this many users have the relationship (and possibly more)
+    AtLeast(usize),
+}
+
+///
+/// Describes the work done by one check
+///
+/// See [`MiniZ::check_member_instrumented()`].
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CheckMetrics {
+    /// number of times a set's direct members were searched for the user
+    pub direct_member_lookups: usize,
+    /// number of times the check descended into a contained set
+    pub subset_descents: usize,
+    /// number of times the check descended into an object member followed as
+    /// a userset
+    pub userset_descents: usize,
+    /// number of times the check descended into an inherited set
+    pub inherited_descents: usize,
+    /// largest number of sets being resolved at once (i.e., the depth of the
+    /// recursion)
+    pub max_depth: usize,
+}
+
+///
+/// One rule in the definition of a relationship
+///
+/// These correspond to the userset rewrite rules in the Zanzibar paper.  See
+/// [`RelationshipBuilder::with_union()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UsersetRule {
+    /// the relationship includes its direct members
     ///
-    /// Returns whether object `child` has relationship `rid` with object
-    /// `parent` _directly_.  To check for indirect relationships, see
-    /// [`check_member()`].
-    ///
-    pub fn set_contains_object_directly(
+    /// Every relationship always does, so this is only useful for spelling
+    /// out a definition in full.
+    This,
+    /// the relationship includes relationship `rid` on the same object
+    /// (see [`RelationshipBuilder::with_computed_userset()`])
+    ComputedUserset(RelationshipId),
+    /// the relationship includes relationship `computed_userset` on every
+    /// object that the object has relationship `tupleset` with
+    /// (see [`RelationshipBuilder::with_inherited_as()`])
+    TupleToUserset {
+        tupleset: RelationshipId,
+        computed_userset: RelationshipId,
+    },
+}
+
+///
+/// An expectation about whether a user has a relationship with an object
+///
+/// See [`MiniZ::run_assertions()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Assertion<O, U> {
+    /// the relationship to check
+    pub rid: RelationshipId,
+    /// the object to check
+    pub object: O,
+    /// the user to check
+    pub user: U,
+    /// whether the user is expected to have the relationship with the object
+    pub expected: bool,
+}
+
+///
+/// Describes an [`Assertion`] that did not hold
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssertionFailure<O, U> {
+    /// index of the assertion in the list passed to
+    /// [`MiniZ::run_assertions()`]
+    pub index: usize,
+    /// the assertion
+    pub assertion: Assertion<O, U>,
+    /// whether the user actually has the relationship with the object (always
+    /// the opposite of `assertion.expected`)
+    pub actual: bool,
+}
+
+///
+/// Result of [`MiniZ::run_assertions()`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AssertionReport<O, U> {
+    /// number of assertions that held
+    pub passed: usize,
+    /// assertions that did not hold, in the order they were given
+    pub failures: Vec<AssertionFailure<O, U>>,
+}
+
+impl<O, U> AssertionReport<O, U> {
+    /// Returns whether every assertion held
+    pub fn is_ok(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+///
+/// A check for which [`MiniZ::check_member()`] disagrees with a simple
+/// reference implementation
+///
+/// See [`MiniZ::verify_against_naive()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Discrepancy<O, U> {
+    /// the relationship checked
+    pub rid: RelationshipId,
+    /// the object checked
+    pub object: O,
+    /// the user checked
+    pub user: U,
+    /// answer from the reference implementation
+    pub expected: bool,
+    /// answer from `check_member()`
+    pub actual: bool,
+}
+
+///
+/// The result of expanding a relationship on an object
+///
+/// This is what Zanzibar calls a "userset tree": the direct members of the
+/// set for relationship `rid` on object `object`, plus the expansion of every
+/// other set whose members are included in it.  See [`MiniZ::expand()`].
+///
+/// The `Display` impl prints this as an indented tree, which is handy for
+/// debugging.
+///
+/// Expanding, printing, and dropping a tree work no matter how deep it is.
+/// The `Clone`, `Debug`, and `PartialEq` impls recurse once per level, though,
+/// so they can overflow the stack for very deep hierarchies (e.g., tens of
+/// thousands of nested directories).
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UsersetTree<O, U> {
+    /// the relationship that was expanded
+    pub rid: RelationshipId,
+    /// the object on which it was expanded
+    pub object: O,
+    /// direct members of `rid` on `object`
+    pub members: Vec<Member<O, U>>,
+    /// expansions of the sets included in this one, in the order that checks
+    /// consider them
+    pub children: Vec<UsersetTree<O, U>>,
+}
+
+impl<O: fmt::Debug, U: fmt::Debug> UsersetTree<O, U> {
+    /// Prints this set and its direct members (but not its children),
+    /// indented for depth `depth` in the tree
+    fn fmt_node(
         &self,
-        rid: &RelationshipId,
-        parent: &O,
-        child: O,
-    ) -> bool {
-        let set = self.sets.get(rid).expect("no such set");
-        match set.direct_members.get(parent) {
-            Some(members) => members.contains(&Member::Object(child)),
-            None => false,
+        f: &mut fmt::Formatter<'_>,
+        depth: usize,
+    ) -> fmt::Result {
+        let indent = depth * 4;
+        writeln!(f, "{:indent$}{} on {:?}", "", self.rid, self.object)?;
+        for member in &self.members {
+            match member {
+                Member::Object(object) => {
+                    writeln!(f, "{:indent$}    object {:?}", "", object)?
+                }
+                Member::User(user) => {
+                    writeln!(f, "{:indent$}    user {:?}", "", user)?
+                }
+                Member::ObjectRelation { object, relation } => writeln!(
+                    f,
+                    "{:indent$}    userset {:?}#{}",
+                    "", object, relation
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<O: fmt::Debug, U: fmt::Debug> fmt::Display for UsersetTree<O, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        /*
+         * Print the trees in pre-order, using an explicit stack rather than
+         * recursion so that very deep trees don't overflow the call stack.
+         */
+        let mut stack = vec![(self, 0)];
+        while let Some((tree, depth)) = stack.pop() {
+            tree.fmt_node(f, depth)?;
+            stack.extend(tree.children.iter().rev().map(|c| (c, depth + 1)));
+        }
+        Ok(())
+    }
+}
+
+impl<O, U> Drop for UsersetTree<O, U> {
+    fn drop(&mut self) {
+        /*
+         * The default drop glue would recurse once per level.  Instead, take
+         * apart the tree here so that each child is dropped without children.
+         */
+        let mut descendants = core::mem::take(&mut self.children);
+        while let Some(mut tree) = descendants.pop() {
+            descendants.append(&mut tree.children);
+        }
+    }
+}
+
+///
+/// Runtime information against which caveats are evaluated: a map of keys to
+/// values
+///
+/// See [`MiniZ::check_member_with_context()`].
+///
+pub type Context = BTreeMap<String, String>;
+
+/// An empty [`Context`], used by checks that don't take one
+static EMPTY_CONTEXT: Context = BTreeMap::new();
+
+/// Unique id for a caveat defined with [`MiniZBuilder::new_caveat()`]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct CaveatId(String);
+
+///
+/// A condition on a [`Context`]
+///
+/// A tuple written with a caveat (e.g., with
+/// [`MiniZ::write_user_caveated()`]) only counts when its caveat is
+/// satisfied by the context of the check.
+///
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum Caveat {
+    /// satisfied if the context maps `key` to `value`
+    Equals { key: String, value: String },
+    /// satisfied if any of these caveats is satisfied
+    AnyOf(Vec<Caveat>),
+    /// satisfied if all of these caveats are satisfied
+    AllOf(Vec<Caveat>),
+}
+
+impl Caveat {
+    /// Returns whether `context` satisfies this caveat
+    pub fn evaluate(&self, context: &Context) -> bool {
+        match self {
+            Caveat::Equals { key, value } => context.get(key) == Some(value),
+            Caveat::AnyOf(caveats) => {
+                caveats.iter().any(|c| c.evaluate(context))
+            }
+            Caveat::AllOf(caveats) => {
+                caveats.iter().all(|c| c.evaluate(context))
+            }
+        }
+    }
+}
+
+///
+/// Summary of the size of a [`MiniZ`]
+///
+/// See [`MiniZ::stats()`].
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct StoreStats {
+    /// number of configured relationships
+    pub num_sets: usize,
+    /// number of direct members across all relationships and objects
+    pub num_tuples: usize,
+    /// number of objects that have a direct relationship with some object
+    pub num_objects_with_memberships: usize,
+}
+
+///
+/// Counts of the different kinds of direct members of one relationship
+///
+/// See [`MiniZ::relation_member_breakdown()`].
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemberBreakdown {
+    /// number of direct members that are objects
+    pub objects: usize,
+    /// number of direct members that are users
+    pub users: usize,
+    /// number of direct members that are usersets (see
+    /// [`Member::ObjectRelation`])
+    pub object_relations: usize,
+}
+
+///
+/// Error returned by [`MiniZ::rename_relation()`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RenameError {
+    /// there is no relationship with the id being renamed
+    NoSuchRelationship(RelationshipId),
+    /// a relationship with the requested new name already exists
+    AlreadyExists(RelationshipId),
+}
+
+impl fmt::Display for RenameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenameError::NoSuchRelationship(rid) => {
+                write!(f, "cannot rename nonexistent relationship \"{}\"", rid)
+            }
+            RenameError::AlreadyExists(rid) => {
+                write!(f, "relationship \"{}\" already exists", rid)
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for RenameError {}
+
+///
+/// Error returned by [`MiniZBuilder::build()`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum BuildError {
+    /// some relationship refers to relationship `rid` (e.g., as a subset or
+    /// an inherited set), but no relationship `rid` was defined
+    UnknownReference(RelationshipId),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::UnknownReference(rid) => write!(
+                f,
+                "schema refers to relationship \"{}\", which is not defined",
+                rid
+            ),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for BuildError {}
+
+///
+/// Error returned by [`MiniZ::replace_schema()`]
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SchemaSwapError {
+    /// relationship `rid` has tuples but does not exist in the new schema
+    MissingRelationship(RelationshipId),
+    /// relationship `rid` has tuples whose members are of a kind that the new
+    /// schema does not allow for it
+    UnexpectedMemberKind(RelationshipId),
+    /// some tuple was written with caveat `caveat`, which does not exist in
+    /// the new schema
+    MissingCaveat(CaveatId),
+    /// the new schema is invalid (see [`MiniZBuilder::build()`])
+    Build(BuildError),
+}
+
+impl fmt::Display for SchemaSwapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchemaSwapError::MissingRelationship(rid) => write!(
+                f,
+                "relationship \"{}\" has tuples but is not in the new schema",
+                rid
+            ),
+            SchemaSwapError::UnexpectedMemberKind(rid) => write!(
+                f,
+                "relationship \"{}\" has members of a kind that the new \
+                 schema does not allow",
+                rid
+            ),
+            SchemaSwapError::MissingCaveat(caveat) => write!(
+                f,
+                "caveat \"{}\" is used by some tuple but is not in the new \
+                 schema",
+                caveat.0
+            ),
+            SchemaSwapError::Build(error) => {
+                write!(f, "new schema is invalid: {}", error)
+            }
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for SchemaSwapError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SchemaSwapError::Build(error) => Some(error),
+            SchemaSwapError::MissingRelationship(_)
+            | SchemaSwapError::UnexpectedMemberKind(_)
+            | SchemaSwapError::MissingCaveat(_) => None,
+        }
+    }
+}
+
+///
+/// Describes an inconsistency between a [`MiniZ`]'s relationships and its
+/// reverse index
+///
+/// See [`MiniZ::check_invariants()`].
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InvariantError<O, U> {
+    /// `member` is a direct member of `rid` for object `parent`, but the
+    /// reverse index has no corresponding [`Membership`]
+    MissingMembership { rid: RelationshipId, parent: O, member: Member<O, U> },
+    /// the reverse index records `membership` for `member`, but `member` is
+    /// not a direct member of that relationship for that object
+    MissingDirectMember { member: Member<O, U>, membership: Membership<O> },
+    /// relationship `rid` has an empty set of direct members for `parent`
+    EmptyDirectMembers { rid: RelationshipId, parent: O },
+    /// the reverse index has an empty set of memberships for `member`
+    EmptyMemberships { member: Member<O, U> },
+}
+
+impl<O: fmt::Debug, U: fmt::Debug> fmt::Display for InvariantError<O, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvariantError::MissingMembership { rid, parent, member } => {
+                write!(
+                f,
+                "{:?} is a direct member of relationship \"{}\" on {:?}, but \
+                 the reverse index does not record it",
+                member, rid, parent
+            )
+            }
+            InvariantError::MissingDirectMember { member, membership } => {
+                write!(
+                    f,
+                    "the reverse index records {:?} as having relationship \
+                     \"{}\" with {:?}, but it's not a direct member",
+                    member, membership.rid, membership.object
+                )
+            }
+            InvariantError::EmptyDirectMembers { rid, parent } => write!(
+                f,
+                "relationship \"{}\" has an empty set of direct members for \
+                 {:?}",
+                rid, parent
+            ),
+            InvariantError::EmptyMemberships { member } => write!(
+                f,
+                "the reverse index has an empty set of memberships for {:?}",
+                member
+            ),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl<O: fmt::Debug, U: fmt::Debug> std::error::Error for InvariantError<O, U> {}
+
+///
+/// Any error returned by this crate, except [`InvariantError`]
+///
+/// Each of the more specific error types converts into this one, so callers
+/// that make several kinds of calls can use `?` with all of them.
+/// (`InvariantError` is left out because it's generic over the store's id
+/// types and only ever reported by [`MiniZ::check_invariants()`], which is
+/// meant for tests.)
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    /// see [`MiniZBuilder::build()`]
+    Build(BuildError),
+    /// see [`MiniZ::try_check_member()`]
+    Check(CheckError),
+    /// see [`MiniZBuilder::from_schema_str()`]
+    Parse(schema::ParseError),
+    /// see [`MiniZ::rename_relation()`]
+    Rename(RenameError),
+    /// see [`MiniZ::replace_schema()`]
+    SchemaSwap(SchemaSwapError),
+    /// see [`MiniZ::apply_writes()`]
+    Write(WriteError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Build(error) => error.fmt(f),
+            Error::Check(error) => error.fmt(f),
+            Error::Parse(error) => error.fmt(f),
+            Error::Rename(error) => error.fmt(f),
+            Error::SchemaSwap(error) => error.fmt(f),
+            Error::Write(error) => error.fmt(f),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Build(error) => Some(error),
+            Error::Check(error) => Some(error),
+            Error::Parse(error) => Some(error),
+            Error::Rename(error) => Some(error),
+            Error::SchemaSwap(error) => Some(error),
+            Error::Write(error) => Some(error),
+        }
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(error: BuildError) -> Error {
+        Error::Build(error)
+    }
+}
+
+impl From<CheckError> for Error {
+    fn from(error: CheckError) -> Error {
+        Error::Check(error)
+    }
+}
+
+impl From<schema::ParseError> for Error {
+    fn from(error: schema::ParseError) -> Error {
+        Error::Parse(error)
+    }
+}
+
+impl From<RenameError> for Error {
+    fn from(error: RenameError) -> Error {
+        Error::Rename(error)
+    }
+}
+
+impl From<SchemaSwapError> for Error {
+    fn from(error: SchemaSwapError) -> Error {
+        Error::SchemaSwap(error)
+    }
+}
+
+impl From<WriteError> for Error {
+    fn from(error: WriteError) -> Error {
+        Error::Write(error)
+    }
+}
+
+///
+/// Builder for a [`MiniZ`]
+///
+/// Conceptually, this object provides facilities that you might use when
+/// processing a Zanzibar configuration file.  Anything that can be changed at
+/// runtime (outside the configuration) does not belong here.
+///
+/// The only thing you can do with this is define relationships and then build a
+/// [`MiniZ`].
+///
+#[derive(Debug)]
+pub struct MiniZBuilder<O, U> {
+    /// Configured relationships
+    relationships: BTreeMap<RelationshipId, Relationship<O, U>>,
+    /// Configured caveats
+    caveats: BTreeMap<CaveatId, Caveat>,
+}
+
+impl<O, U> MiniZBuilder<O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    /// Defines a new `Relationship` named `relationship_name`
+    pub fn new_relationship<S: AsRef<str>>(
+        &mut self,
+        relationship_name: S,
+    ) -> RelationshipBuilder<'_, O, U> {
+        RelationshipBuilder {
+            miniz_builder: self,
+            name: relationship_name.as_ref().to_owned(),
+            contained_relationships: BTreeSet::new(),
+            inherited_relationships: BTreeMap::new(),
+            userset_member_relation: None,
+            deny_relationships: BTreeSet::new(),
+            description: None,
+            expected_member_kind: None,
+            direct_only: false,
         }
     }
 
     ///
-    /// Returns whether user `child` has relationship `rid` with object
-    /// `parent` _directly_.  To check for indirect relationships, see
-    /// [`check_member()`].
+    /// Returns a handle for adding rules to the relationship named
+    /// `relationship_name`, defining it first (with no rules) if necessary
     ///
-    pub fn set_contains_user_directly(
-        &self,
-        rid: &RelationshipId,
-        parent: &O,
-        child: U,
-    ) -> bool {
-        let set = self.sets.get(rid).expect("no such set");
-        match set.direct_members.get(parent) {
-            Some(members) => members.contains(&Member::User(child)),
-            None => false,
-        }
+    /// This is like [`BTreeMap::entry()`]: unlike
+    /// [`MiniZBuilder::new_relationship()`], which replaces any existing
+    /// relationship with the same name, the rules added through the handle
+    /// are added to whatever the relationship already has.  That lets a
+    /// schema be put together by several functions that each contribute some
+    /// rules to the same relationship.
+    ///
+    pub fn set_entry<S: AsRef<str>>(
+        &mut self,
+        relationship_name: S,
+    ) -> SetEntry<'_, O, U> {
+        let rid = RelationshipId(relationship_name.as_ref().to_owned());
+        let set = self.relationships.entry(rid.clone()).or_insert_with(|| {
+            Relationship {
+                direct_members: BTreeMap::new(),
+                contained_relationships: BTreeSet::new(),
+                inherited_relationships: BTreeMap::new(),
+                userset_member_relation: None,
+                deny_relationships: BTreeSet::new(),
+                description: None,
+                expected_member_kind: None,
+                direct_only: false,
+                caveated_members: BTreeMap::new(),
+            }
+        });
+        SetEntry { rid, set }
+    }
+
+    ///
+    /// Defines a new caveat named `caveat_name`, which is satisfied when
+    /// `caveat` is
+    ///
+    /// Tuples can then be written with this caveat (e.g., with
+    /// [`MiniZ::write_user_caveated()`]).
+    ///
+    pub fn new_caveat<S: AsRef<str>>(
+        &mut self,
+        caveat_name: S,
+        caveat: Caveat,
+    ) -> CaveatId {
+        let id = CaveatId(caveat_name.as_ref().to_owned());
+        self.caveats.insert(id.clone(), caveat);
+        id
+    }
+
+    ///
+    /// Returns a `MiniZ` with the configuration defined in the builder
+    ///
+    /// This fails if any relationship refers to one that was never defined
+    /// in this builder, as can happen with a [`RelationshipId`] that came from
+    /// a different builder.  Every subset, inherited set, userset member
+    /// relationship, and deny relationship is checked.
+    ///
+    pub fn build(self) -> Result<MiniZ<O, U>, BuildError> {
+        self.validate()?;
+        Ok(MiniZ {
+            reachability: ReachabilityCache::new(&self.relationships),
+            caveats: self.caveats,
+            sets: self.relationships,
+            memberships: BTreeMap::new(),
+            version: 0,
+            log: None,
+            check_results: CheckResultCache::new(),
+            subset_closures: None,
+            inherited_index: InheritedIndex::new(),
+        })
+    }
+
+    /// Returns an error if any relationship refers to one that's not defined
+    fn validate(&self) -> Result<(), BuildError> {
+        for set in self.relationships.values() {
+            if let Some(rid) = set
+                .references()
+                .find(|rid| !self.relationships.contains_key(rid))
+            {
+                return Err(BuildError::UnknownReference(rid.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    ///
+    /// Like [`MiniZBuilder::build()`], but precomputes which relationships
+    /// each relationship includes, directly or indirectly, as subsets
+    ///
+    /// For example, if "viewer" includes "editor", which includes "owner", then
+    /// a check for "viewer" goes straight to "owner" instead of going through
+    /// "editor".  This speeds up checks for schemas with long chains of
+    /// subsets.  Answers are the same as for a store returned by `build()`.
+    /// Changing the schema afterwards (e.g., with [`MiniZ::add_subset()`])
+    /// recomputes the whole thing.
+    ///
+    pub fn build_optimized(self) -> Result<MiniZ<O, U>, BuildError> {
+        let mut miniz = self.build()?;
+        miniz.subset_closures = Some(BTreeMap::new());
+        miniz.schema_changed();
+        Ok(miniz)
+    }
+}
+
+/// Used to configure a `Relationship`.  See [`MiniZBuilder`].
+pub struct RelationshipBuilder<'a, O, U> {
+    miniz_builder: &'a mut MiniZBuilder<O, U>,
+    name: String,
+    contained_relationships: BTreeSet<RelationshipId>,
+    inherited_relationships: BTreeMap<RelationshipId, BTreeSet<RelationshipId>>,
+    userset_member_relation: Option<RelationshipId>,
+    deny_relationships: BTreeSet<RelationshipId>,
+    description: Option<String>,
+    expected_member_kind: Option<MemberKind>,
+    direct_only: bool,
+}
+
+impl<'a, O, U> RelationshipBuilder<'a, O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    ///
+    /// Specify that the relationship `subrid` implies the current relationship
+    ///
+    /// A typical example: the "owner" relationship implies the "viewer"
+    /// relationship, which means that any user that's an "owner" is also a
+    /// "viewer".  More formally, if an object O1 has relationship `subrid` to
+    /// another object O2, then it also has this relationship with O2.
+    ///
+    pub fn with_subset(mut self, subrid: &RelationshipId) -> Self {
+        self.contained_relationships.insert(subrid.clone());
+        self
+    }
+
+    ///
+    /// Specify that the current relationship includes the relationship `rid`
+    /// on the same object
+    ///
+    /// This is what Zanzibar calls "computed_userset": when checking whether a
+    /// user has the current relationship with an object O, we also check
+    /// whether the user has relationship `rid` with O.  No tuple needs to be
+    /// written for this to take effect.  For example, if "viewer" is configured
+    /// with a computed userset of "editor", then every editor of a document is
+    /// also a viewer of it.
+    ///
+    /// This is the same rule that [`RelationshipBuilder::with_subset`]
+    /// describes, spelled the way the Zanzibar paper spells it.
+    ///
+    pub fn with_computed_userset(self, rid: &RelationshipId) -> Self {
+        self.with_subset(rid)
+    }
+
+    ///
+    /// Specify that the relationship `subrid` is inherited by the current
+    /// relationship
+    ///
+    /// This is similar to what Zanzibar calls "tuple_to_userset" when combined
+    /// with "computed_userset".  
+    ///
+    /// A typical example: we say that the "viewer" relationship inherits the
+    /// "parent" relationship, which means that if a user U has a "viewer"
+    /// relationship to an object O1, and object O1 has the "parent"
+    /// relationship to object O2, then U has a "viewer" relationship to O2 as
+    /// well.  The implementation here would include "parent" in the set of
+    /// inherited relationships for "viewer".
+    ///
+    /// This always inherits the current relationship from the parent.  To
+    /// inherit a different one, see [`RelationshipBuilder::with_inherited_as`].
+    ///
+    pub fn with_inherited_set(self, rid: &RelationshipId) -> Self {
+        let this = self.id();
+        self.with_inherited_as(rid, &this)
+    }
+
+    ///
+    /// Specify that the current relationship includes users having
+    /// relationship `parent_relation` with any object that an object has
+    /// relationship `via` with
+    ///
+    /// This is what Zanzibar calls "tuple_to_userset".  A typical example:
+    /// "viewer" inherits "editor" via "parent", which means that if a user U
+    /// has an "editor" relationship to an object O1, and object O1 has the
+    /// "parent" relationship to object O2, then U has a "viewer" relationship
+    /// to O2.  In other words, editors of a folder can view the documents in
+    /// it.
+    ///
+    /// [`RelationshipBuilder::with_inherited_set`] is the special case where
+    /// `parent_relation` is the current relationship.  Each `via` is paired
+    /// only with the parent relationships configured for it: if "viewer"
+    /// inherits "editor" via "parent" and "viewer" via "folder", then viewers
+    /// of an object's "parent" are not thereby viewers of the object, and
+    /// editors of its "folder" are not either.
+    ///
+    pub fn with_inherited_as(
+        mut self,
+        via: &RelationshipId,
+        parent_relation: &RelationshipId,
+    ) -> Self {
+        self.inherited_relationships
+            .entry(via.clone())
+            .or_default()
+            .insert(parent_relation.clone());
+        self
+    }
+
+    ///
+    /// Specify that users having relationship `deny` with an object never have
+    /// the current relationship with it, regardless of how else they might
+    ///
+    /// This is an explicit denial layered on top of the usual grants.  For
+    /// example, if "viewer" is configured with "banned" as a deny relationship,
+    /// and "banned" inherits itself via "parent", then banning a user from a
+    /// folder keeps them from viewing every document in it, even ones that
+    /// they've been made a direct viewer of.
+    ///
+    /// The denial applies wherever a check reaches this relationship, not just
+    /// to checks of this relationship itself: if "commenter" includes
+    /// "viewer", then a banned user doesn't get "commenter" through "viewer"
+    /// (though they might through some other path).  Deny relationships are
+    /// themselves resolved without applying any deny relationships.  Functions
+    /// that list the users having a relationship (like
+    /// [`MiniZ::list_effective_users()`]) leave out denied users, too, but
+    /// functions that report how a relationship is put together (like
+    /// [`MiniZ::walk()`] and [`MiniZ::expand()`]) show every set regardless.
+    ///
+    pub fn with_deny_relation(mut self, deny: &RelationshipId) -> Self {
+        self.deny_relationships.insert(deny.clone());
+        self
+    }
+
+    ///
+    /// Specify that the current relationship only ever has objects as direct
+    /// members
+    ///
+    /// Relationships like "parent" relate objects to other objects, and
+    /// writing a user into one is almost certainly a mistake.  With this, any
+    /// attempt to do so panics.
+    ///
+    pub fn expect_objects(mut self) -> Self {
+        self.expected_member_kind = Some(MemberKind::Object);
+        self
+    }
+
+    ///
+    /// Specify that the current relationship only ever has users as direct
+    /// members
+    ///
+    /// Any attempt to write an object into the relationship panics.  This is
+    /// the counterpart of [`RelationshipBuilder::expect_objects()`].
+    ///
+    pub fn expect_users(mut self) -> Self {
+        self.expected_member_kind = Some(MemberKind::User);
+        self
+    }
+
+    ///
+    /// Specify that only direct members of the current relationship have it
+    ///
+    /// Some policies require a grant to be made explicitly: for example, a
+    /// "direct_owner" that must be assigned to the user by name.  With this,
+    /// a user has the current relationship with an object only if the user is
+    /// a direct member of it on that object.  Checks don't follow the
+    /// relationship's subsets, inherited relationships, or usersets (its
+    /// rules are still kept, and still count for things like
+    /// [`MiniZ::relation_reachable()`]), though deny relationships still
+    /// apply.  Other relationships can include this one as usual.
+    ///
+    pub fn direct_only(mut self) -> Self {
+        self.direct_only = true;
+        self
+    }
+
+    ///
+    /// Specify that the current relationship is the union of `rules`
+    ///
+    /// This mirrors the "union" rewrite rule from the Zanzibar paper, letting
+    /// a relationship be defined in one place as a list of [`UsersetRule`]s.
+    /// Each rule is equivalent to calling one of the other builder methods,
+    /// and they can be freely combined with those methods.  When checking a
+    /// user's membership, rules are tried in turn and the check stops at the
+    /// first one that includes the user.
+    ///
+    pub fn with_union(self, rules: Vec<UsersetRule>) -> Self {
+        rules.into_iter().fold(self, |builder, rule| match rule {
+            UsersetRule::This => builder,
+            UsersetRule::ComputedUserset(rid) => {
+                builder.with_computed_userset(&rid)
+            }
+            UsersetRule::TupleToUserset { tupleset, computed_userset } => {
+                builder.with_inherited_as(&tupleset, &computed_userset)
+            }
+        })
+    }
+
+    ///
+    /// Specify that objects that are direct members of the current
+    /// relationship stand for the users having relationship `rid` with them
+    ///
+    /// This is how groups are modeled.  A typical example: the "viewer"
+    /// relationship is configured with a userset member relationship of
+    /// "member".  If object G (a group) is a direct "viewer" of a document D,
+    /// then any user having the "member" relationship with G is also a
+    /// "viewer" of D.  Groups can contain other groups by configuring the
+    /// "member" relationship with itself as its userset member relationship
+    /// (see [`RelationshipBuilder::id()`]).
+    ///
+    /// Without this, object members of a relationship are never consulted
+    /// when checking a user's membership.
+    ///
+    pub fn with_userset_member_relation(
+        mut self,
+        rid: &RelationshipId,
+    ) -> Self {
+        self.userset_member_relation = Some(rid.clone());
+        self
+    }
+
+    ///
+    /// Attach a human-readable description to the relationship
+    ///
+    /// This has no effect on behavior.  It's intended for tooling, audit
+    /// exports, and the like.  See [`MiniZ::set_description()`].
+    ///
+    pub fn with_description(mut self, text: impl Into<String>) -> Self {
+        self.description = Some(text.into());
+        self
+    }
+
+    ///
+    /// Returns the [`RelationshipId`] that [`RelationshipBuilder::build()`]
+    /// will return
+    ///
+    /// This is useful for configuring a relationship in terms of itself.
+    ///
+    pub fn id(&self) -> RelationshipId {
+        RelationshipId(self.name.clone())
+    }
+
+    ///
+    /// Add the relationship configured by this builder to the parent
+    /// [`MiniZBuilder`] and return a [`RelationshipId`] for it.
+    ///
+    pub fn build(self) -> RelationshipId {
+        let rid = RelationshipId(self.name);
+        self.miniz_builder.relationships.insert(
+            rid.clone(),
+            Relationship {
+                direct_members: BTreeMap::new(),
+                contained_relationships: self.contained_relationships,
+                inherited_relationships: self.inherited_relationships,
+                userset_member_relation: self.userset_member_relation,
+                deny_relationships: self.deny_relationships,
+                description: self.description,
+                expected_member_kind: self.expected_member_kind,
+                direct_only: self.direct_only,
+                caveated_members: BTreeMap::new(),
+            },
+        );
+
+        rid
+    }
+}
+
+///
+/// Used to add rules to a relationship that may already be configured.  See
+/// [`MiniZBuilder::set_entry()`].
+///
+pub struct SetEntry<'a, O, U> {
+    rid: RelationshipId,
+    set: &'a mut Relationship<O, U>,
+}
+
+impl<'a, O, U> SetEntry<'a, O, U> {
+    ///
+    /// Specify that the relationship `subrid` implies this relationship
+    ///
+    /// See [`RelationshipBuilder::with_subset()`].
+    ///
+    pub fn with_subset(self, subrid: &RelationshipId) -> Self {
+        self.set.contained_relationships.insert(subrid.clone());
+        self
+    }
+
+    ///
+    /// Specify that this relationship includes users having relationship
+    /// `parent_relation` with any object that an object has relationship
+    /// `via` with
+    ///
+    /// See [`RelationshipBuilder::with_inherited_as()`].
+    ///
+    pub fn with_inherited_as(
+        self,
+        via: &RelationshipId,
+        parent_relation: &RelationshipId,
+    ) -> Self {
+        self.set
+            .inherited_relationships
+            .entry(via.clone())
+            .or_default()
+            .insert(parent_relation.clone());
+        self
+    }
+
+    ///
+    /// Specify that this relationship inherits itself through relationship
+    /// `rid`
+    ///
+    /// See [`RelationshipBuilder::with_inherited_set()`].
+    ///
+    pub fn with_inherited_set(self, rid: &RelationshipId) -> Self {
+        let this = self.id();
+        self.with_inherited_as(rid, &this)
+    }
+
+    /// Returns the [`RelationshipId`] of this relationship
+    pub fn id(&self) -> RelationshipId {
+        self.rid.clone()
+    }
+}
+
+///
+/// A toy in-memory implementation of the Zanzibar data model
+///
+/// In the Zanzibar data model, consumers statically configure relationships
+/// (like "parent" or "viewer").  At runtime, consumers add or remove specific
+/// relationships (e.g., X is a "parent" of Y).  In this API, the static
+/// configuration is provided via the [`MiniZBuilder`] (see
+/// [`MiniZ::builder()`]).  The runtime operations are provided by [`MiniZ`].
+///
+/// See the test case below for an example that corresponds to the one in the
+/// Zanzibar paper.
+///
+pub struct MiniZ<O, U> {
+    sets: BTreeMap<RelationshipId, Relationship<O, U>>,
+    caveats: BTreeMap<CaveatId, Caveat>,
+    memberships: BTreeMap<Member<O, U>, BTreeSet<Membership<O>>>,
+    /// number of changes made to the store since it was built
+    version: u64,
+    /// every change made to the store, if enabled (see `enable_log()`)
+    log: Option<Vec<LogEntry<O, U>>>,
+    /// results of previous checks, if enabled (see `enable_check_cache()`)
+    check_results: CheckResultCache<O, U>,
+    /// for each relationship, every relationship it includes as a subset
+    /// (directly or indirectly), if enabled (see
+    /// `MiniZBuilder::build_optimized()`)
+    subset_closures: Option<BTreeMap<RelationshipId, BTreeSet<RelationshipId>>>,
+    /// for each relationship, every relationship reachable from it (see
+    /// `relation_reachable()`)
+    reachability: ReachabilityCache,
+    /// where each object's memberships in each relationship start in the
+    /// reverse index, if enabled (see `enable_inherited_index()`)
+    inherited_index: InheritedIndex<O>,
+}
+
+/*
+ * The check cache (if enabled) is cloned too, so the clone starts out just as
+ * warm as the original.
+ */
+impl<O: Clone + Ord, U: Clone + Ord> Clone for MiniZ<O, U> {
+    fn clone(&self) -> Self {
+        MiniZ {
+            sets: self.sets.clone(),
+            caveats: self.caveats.clone(),
+            memberships: self.memberships.clone(),
+            version: self.version,
+            log: self.log.clone(),
+            check_results: self.check_results.clone(),
+            subset_closures: self.subset_closures.clone(),
+            reachability: self.reachability.clone(),
+            inherited_index: self.inherited_index.clone(),
+        }
+    }
+}
+
+///
+/// Two stores are equal if they have the same relationships (configured the
+/// same way) and the same tuples.  The version and the change log are not
+/// compared.
+///
+impl<O: PartialEq, U: PartialEq> PartialEq for MiniZ<O, U> {
+    fn eq(&self, other: &Self) -> bool {
+        self.sets == other.sets
+            && self.caveats == other.caveats
+            && self.memberships == other.memberships
+    }
+}
+
+impl<O: Eq, U: Eq> Eq for MiniZ<O, U> {}
+
+impl<O: Hash, U: Hash> Hash for MiniZ<O, U> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.sets.hash(state);
+        self.caveats.hash(state);
+        self.memberships.hash(state);
+    }
+}
+
+///
+/// Summarizes the store: each relationship's rules and how many tuples it
+/// has, plus the size of the reverse index
+///
+/// Tuples themselves are not printed, so the output stays small even for a
+/// large store.  Use [`MiniZ::iter_tuples()`] to see them.
+///
+impl<O, U> fmt::Debug for MiniZ<O, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MiniZ")
+            .field("relationships", &DebugRelationships { sets: &self.sets })
+            .field("num_members", &self.memberships.len())
+            .field("version", &self.version)
+            .finish()
+    }
+}
+
+/// Helper for `MiniZ`'s `Debug` impl that summarizes each relationship
+struct DebugRelationships<'a, O, U> {
+    sets: &'a BTreeMap<RelationshipId, Relationship<O, U>>,
+}
+
+impl<'a, O, U> fmt::Debug for DebugRelationships<'a, O, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (rid, set) in self.sets {
+            map.entry(&format_args!("{}", rid), &DebugRelationship { set });
+        }
+        map.finish()
+    }
+}
+
+/// Helper for `MiniZ`'s `Debug` impl that summarizes one relationship
+struct DebugRelationship<'a, O, U> {
+    set: &'a Relationship<O, U>,
+}
+
+impl<'a, O, U> fmt::Debug for DebugRelationship<'a, O, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let set = self.set;
+        let mut summary = f.debug_struct("Relationship");
+        summary
+            .field("subsets", &set.contained_relationships)
+            .field("inherited", &set.inherited_relationships);
+        if let Some(rid) = &set.userset_member_relation {
+            summary.field("userset_member_relation", rid);
+        }
+        if !set.deny_relationships.is_empty() {
+            summary.field("deny", &set.deny_relationships);
+        }
+        if set.direct_only {
+            summary.field("direct_only", &true);
+        }
+        let num_tuples: usize =
+            set.direct_members.values().map(|members| members.len()).sum();
+        summary.field("num_tuples", &num_tuples).finish()
+    }
+}
+
+impl<O, U> MiniZ<O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    /// Return a builder used to configure relationships known to this instance
+    pub fn builder() -> MiniZBuilder<O, U> {
+        MiniZBuilder {
+            relationships: BTreeMap::new(),
+            caveats: BTreeMap::new(),
+        }
+    }
+
+    /*
+     * Write operations
+     */
+
+    ///
+    /// Specify that object `child` directly has the `rid` relationship to
+    /// object `parent`
+    ///
+    /// (The relationship need not be hierarchical like "parent" is, but it's
+    /// easier to talk about the two objects with concrete names.)
+    ///
+    /// `parent` and `child` may be the same object, and more generally, tuples
+    /// may form cycles (e.g., "dir1" is the parent of "dir2", which is the
+    /// parent of "dir1").  These are accepted.  Checks and the other methods
+    /// that follow relationships between objects visit each object at most
+    /// once, so they always terminate, and an object in a cycle is simply
+    /// reachable from itself.
+    ///
+    /// Returns the [`Membership`] recorded for `child` in the reverse index.
+    ///
+    pub fn write_object(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        child: O,
+    ) -> Membership<O> {
+        self.write_member(rid, parent, Member::Object(child), None)
+    }
+
+    ///
+    /// Specify that user `child` directly has the `rid` relationship to object
+    /// `parent`
+    ///
+    /// (The relationship need not be hierarchical like "parent" is, but it's
+    /// easier to talk about the two objects with concrete names.)
+    ///
+    /// Returns the [`Membership`] recorded for `child` in the reverse index.
+    ///
+    pub fn write_user(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        child: U,
+    ) -> Membership<O> {
+        self.write_member(rid, parent, Member::User(child), None)
+    }
+
+    ///
+    /// Specify that each of `users` directly has the `rid` relationship to
+    /// object `parent`
+    ///
+    /// This is equivalent to calling [`MiniZ::write_user()`] for each user,
+    /// except that users who already have the relationship (including ones
+    /// that appear more than once in `users`) are skipped rather than causing
+    /// a panic, and `parent`'s members are looked up only once.  Returns the
+    /// number of tuples that were actually written.
+    ///
+    pub fn write_users(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        users: impl IntoIterator<Item = U>,
+    ) -> usize {
+        let set = self.sets.get_mut(rid).expect("no such set");
+        let new_values: Vec<_> = users.into_iter().map(Member::User).collect();
+        if new_values.is_empty() {
+            return 0;
+        }
+        for new_value in &new_values {
+            set.check_member_kind(rid, new_value);
+        }
+        let members = set.direct_members.entry(parent.clone()).or_default();
+        let added: Vec<_> = new_values
+            .into_iter()
+            .filter(|new_value| members.insert(new_value.clone()))
+            .collect();
+
+        /* Update the reverse index and the log for each new tuple. */
+        let count = added.len();
+        for new_value in added {
+            let membership =
+                Membership { rid: rid.clone(), object: parent.clone() };
+            self.memberships
+                .entry(new_value.clone())
+                .or_default()
+                .insert(membership);
+            self.record(LogOp::Write(Tuple {
+                rid: rid.clone(),
+                parent: parent.clone(),
+                member: new_value,
+            }));
+        }
+        count
+    }
+
+    ///
+    /// Write the direct relationship described by `tuple`
+    ///
+    /// This is equivalent to [`MiniZ::write_object()`] or
+    /// [`MiniZ::write_user()`], depending on the kind of member in the tuple.
+    ///
+    pub fn write_tuple(&mut self, tuple: Tuple<O, U>) -> Membership<O> {
+        self.write_member(&tuple.rid, tuple.parent, tuple.member, None)
+    }
+
+    ///
+    /// Like [`MiniZ::write_user()`], but the tuple only counts when `caveat`
+    /// is satisfied
+    ///
+    /// Caveats are evaluated against the context passed to
+    /// [`MiniZ::check_member_with_context()`].  Everything else (including
+    /// [`MiniZ::check_member()`]) behaves as though the context were empty.
+    ///
+    pub fn write_user_caveated(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        child: U,
+        caveat: &CaveatId,
+    ) -> Membership<O> {
+        self.write_member(rid, parent, Member::User(child), Some(caveat))
+    }
+
+    ///
+    /// Specify that every user having relationship `relation` with object
+    /// `object` directly has the `rid` relationship to object `parent`
+    ///
+    /// This writes the userset `object#relation` (see
+    /// [`Member::ObjectRelation`]) as a member of `rid` on `parent`.  Checks
+    /// of `rid` on `parent` then resolve `relation` on `object`, so users who
+    /// gain or lose that relationship later gain or lose this one, too.
+    ///
+    /// Returns the [`Membership`] recorded for the userset in the reverse
+    /// index.
+    ///
+    pub fn write_object_relation(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        object: O,
+        relation: &RelationshipId,
+    ) -> Membership<O> {
+        self.write_member(
+            rid,
+            parent,
+            Member::object_relation(object, relation),
+            None,
+        )
+    }
+
+    ///
+    /// Like [`MiniZ::write_object()`], but the tuple only counts when `caveat`
+    /// is satisfied
+    ///
+    /// See [`MiniZ::write_user_caveated()`].
+    ///
+    pub fn write_object_caveated(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        child: O,
+        caveat: &CaveatId,
+    ) -> Membership<O> {
+        self.write_member(rid, parent, Member::Object(child), Some(caveat))
+    }
+
+    ///
+    /// Write all of the tuples in `tuples`
+    ///
+    /// This produces the same result as calling [`MiniZ::write_tuple()`] for
+    /// each tuple, but it's faster for large batches because the reverse index
+    /// is built in one pass after all of the direct members have been
+    /// inserted.
+    ///
+    pub fn load_tuples<I: IntoIterator<Item = Tuple<O, U>>>(
+        &mut self,
+        tuples: I,
+    ) {
+        let mut reverse = Vec::new();
+        for tuple in tuples {
+            self.check_member_relation(&tuple.member);
+            let set = self.sets.get_mut(&tuple.rid).expect("no such set");
+            set.check_member_kind(&tuple.rid, &tuple.member);
+            let members =
+                set.direct_members.entry(tuple.parent.clone()).or_default();
+            assert!(members.insert(tuple.member.clone()));
+            reverse.push((
+                tuple.member.clone(),
+                Membership {
+                    rid: tuple.rid.clone(),
+                    object: tuple.parent.clone(),
+                },
+            ));
+            self.record(LogOp::Write(tuple));
+        }
+        self.extend_reverse_index(reverse);
+    }
+
+    ///
+    /// Write all of the tuples in `tuples`, sorting them first
+    ///
+    /// This produces the same store as [`MiniZ::load_tuples()`], but it
+    /// sorts the tuples and inserts the members of each relationship on each
+    /// object as a batch.  Each object's set of members in each relationship
+    /// is then built in one go from sorted members (when the object has none
+    /// yet), which is faster than inserting them one at a time for very large
+    /// loads.  The change log (if enabled) records the writes in sorted order
+    /// rather than the order given.
+    ///
+    pub fn load_sorted(&mut self, mut tuples: Vec<Tuple<O, U>>) {
+        tuples.sort();
+        let mut reverse = Vec::with_capacity(tuples.len());
+        let mut tuples = tuples.into_iter().peekable();
+        while let Some(first) = tuples.next() {
+            let mut group = vec![first];
+            while let Some(tuple) = tuples.next_if(|next| {
+                next.rid == group[0].rid && next.parent == group[0].parent
+            }) {
+                group.push(tuple);
+            }
+
+            for tuple in &group {
+                self.check_member_relation(&tuple.member);
+            }
+            let Tuple { rid, parent, .. } = &group[0];
+            let set = self.sets.get_mut(rid).expect("no such set");
+            for tuple in &group {
+                set.check_member_kind(rid, &tuple.member);
+            }
+            let new_members = group.iter().map(|tuple| tuple.member.clone());
+            match set.direct_members.entry(parent.clone()) {
+                Entry::Vacant(entry) => {
+                    let members: BTreeSet<_> = new_members.collect();
+                    assert_eq!(members.len(), group.len());
+                    entry.insert(members);
+                }
+                Entry::Occupied(mut entry) => {
+                    for member in new_members {
+                        assert!(entry.get_mut().insert(member));
+                    }
+                }
+            }
+
+            for tuple in group {
+                reverse.push((
+                    tuple.member.clone(),
+                    Membership {
+                        rid: tuple.rid.clone(),
+                        object: tuple.parent.clone(),
+                    },
+                ));
+                self.record(LogOp::Write(tuple));
+            }
+        }
+        self.extend_reverse_index(reverse);
+    }
+
+    /// Add a batch of new entries to the reverse index
+    fn extend_reverse_index(
+        &mut self,
+        mut reverse: Vec<(Member<O, U>, Membership<O>)>,
+    ) {
+        /*
+         * Sort the new reverse index entries so that each member's entry in the
+         * index only needs to be looked up once.
+         */
+        reverse.sort();
+        let mut reverse = reverse.into_iter().peekable();
+        while let Some((member, membership)) = reverse.next() {
+            let mut new_memberships = vec![membership];
+            while let Some((_, membership)) =
+                reverse.next_if(|(next, _)| *next == member)
+            {
+                new_memberships.push(membership);
+            }
+            self.memberships.entry(member).or_default().extend(new_memberships);
+        }
+    }
+
+    ///
+    /// Remove the direct `rid` relationship that object `child` has to object
+    /// `parent`
+    ///
+    /// Returns whether the relationship existed.
+    ///
+    pub fn remove_object(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        child: O,
+    ) -> bool {
+        self.remove_member(rid, parent, Member::Object(child))
+    }
+
+    ///
+    /// Remove the direct `rid` relationship that user `child` has to object
+    /// `parent`
+    ///
+    /// Returns whether the relationship existed.
+    ///
+    pub fn remove_user(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        child: U,
+    ) -> bool {
+        self.remove_member(rid, parent, Member::User(child))
+    }
+
+    ///
+    /// Undo [`MiniZ::write_object_relation()`]
+    ///
+    /// Returns whether the relationship existed.
+    ///
+    pub fn remove_object_relation(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        object: O,
+        relation: &RelationshipId,
+    ) -> bool {
+        self.remove_member(
+            rid,
+            parent,
+            Member::object_relation(object, relation),
+        )
+    }
+
+    ///
+    /// Remove every tuple that mentions object `object`, either as the object
+    /// on which a relationship is written or as a member of some other
+    /// object's relationship (by itself or as part of a
+    /// [`Member::ObjectRelation`])
+    ///
+    /// This is what you'd use when the object itself is deleted.  Returns the
+    /// number of tuples removed.
+    ///
+    pub fn delete_object(&mut self, object: &O) -> usize {
+        let mut tuples = Vec::new();
+        for (rid, set) in &self.sets {
+            if let Some(members) = set.direct_members.get(object) {
+                tuples.extend(members.iter().map(|member| Tuple {
+                    rid: rid.clone(),
+                    parent: object.clone(),
+                    member: member.clone(),
+                }));
+            }
+        }
+
+        let as_member = Member::Object(object.clone());
+        let as_usersets = self
+            .memberships
+            .range(
+                Member::object_relation(
+                    object.clone(),
+                    &RelationshipId(String::new()),
+                )..,
+            )
+            .take_while(|(member, _)| {
+                matches!(member, Member::ObjectRelation { object: o, .. }
+                    if o == object)
+            });
+        for (member, memberships) in self
+            .memberships
+            .get_key_value(&as_member)
+            .into_iter()
+            .chain(as_usersets)
+        {
+            tuples.extend(memberships.iter().map(|membership| Tuple {
+                rid: membership.rid.clone(),
+                parent: membership.object.clone(),
+                member: member.clone(),
+            }));
+        }
+
+        tuples
+            .into_iter()
+            .filter(|tuple| {
+                self.remove_member(
+                    &tuple.rid,
+                    tuple.parent.clone(),
+                    tuple.member.clone(),
+                )
+            })
+            .count()
+    }
+
+    ///
+    /// Remove every tuple that grants user `user` a relationship with any
+    /// object
+    ///
+    /// This is what you'd use when the user itself is deleted.  Returns the
+    /// number of tuples removed.
+    ///
+    pub fn delete_user(&mut self, user: &U) -> usize {
+        let memberships = self
+            .user_lookup_memberships_ref(user)
+            .into_iter()
+            .cloned()
+            .collect::<Vec<_>>();
+        memberships
+            .into_iter()
+            .filter(|membership| {
+                self.remove_user(
+                    &membership.rid,
+                    membership.object.clone(),
+                    user.clone(),
+                )
+            })
+            .count()
+    }
+
+    ///
+    /// Apply all of `writes` in order, or none of them
+    ///
+    /// If any write is invalid (because it refers to a relationship that
+    /// doesn't exist or would add a member of a kind that its relationship
+    /// doesn't allow), this returns the first such error and the store is
+    /// unchanged.  Otherwise, this returns, for each write, whether it changed
+    /// anything: inserting a tuple that already exists and deleting one that
+    /// doesn't are not errors, but they return `false`.
+    ///
+    pub fn apply_writes(
+        &mut self,
+        writes: &[Write<O, U>],
+    ) -> Result<Vec<bool>, WriteError> {
+        for write in writes {
+            self.validate_write(write)?;
+        }
+        Ok(writes.iter().map(|write| self.apply_write(write)).collect())
+    }
+
+    ///
+    /// Apply each of `writes` in order, skipping any that are invalid
+    ///
+    /// Unlike [`MiniZ::apply_writes()`], an invalid write doesn't prevent the
+    /// others from being applied.  This returns the outcome of each write:
+    /// whether it changed anything (as with `apply_writes()`) or why it's
+    /// invalid.  This is useful for bulk imports that might include tuples
+    /// for relationships that no longer exist.
+    ///
+    pub fn apply_writes_besteffort(
+        &mut self,
+        writes: &[Write<O, U>],
+    ) -> Vec<Result<bool, WriteError>> {
+        writes
+            .iter()
+            .map(|write| {
+                self.validate_write(write)?;
+                Ok(self.apply_write(write))
+            })
+            .collect()
+    }
+
+    /// Returns an error if `write` can't be applied to this store
+    fn validate_write(&self, write: &Write<O, U>) -> Result<(), WriteError> {
+        let tuple = match write {
+            Write::Insert(tuple) | Write::Delete(tuple) => tuple,
+        };
+        let set = self
+            .sets
+            .get(&tuple.rid)
+            .ok_or_else(|| WriteError::NoSuchRelationship(tuple.rid.clone()))?;
+        if let Write::Insert(_) = write {
+            if !set.accepts_member(&tuple.member) {
+                return Err(WriteError::UnexpectedMemberKind(
+                    tuple.rid.clone(),
+                ));
+            }
+            if let Member::ObjectRelation { relation, .. } = &tuple.member {
+                if !self.sets.contains_key(relation) {
+                    return Err(WriteError::NoSuchRelationship(
+                        relation.clone(),
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies `write`, which must be valid, returning whether it changed
+    /// anything
+    fn apply_write(&mut self, write: &Write<O, U>) -> bool {
+        match write {
+            Write::Insert(tuple) => {
+                let set = &self.sets[&tuple.rid];
+                if set
+                    .direct_members
+                    .get(&tuple.parent)
+                    .is_some_and(|members| members.contains(&tuple.member))
+                {
+                    return false;
+                }
+                self.write_tuple(tuple.clone());
+                true
+            }
+            Write::Delete(tuple) => self.remove_member(
+                &tuple.rid,
+                tuple.parent.clone(),
+                tuple.member.clone(),
+            ),
+        }
+    }
+
+    ///
+    /// Rename relationship `old` to `new_name`, returning its new id
+    ///
+    /// Every reference to the relationship is updated: the relationship itself,
+    /// other relationships' subsets, inherited sets, userset member
+    /// relationships, and deny relationships, [`Member::ObjectRelation`]
+    /// members, the memberships in the reverse index, and the change log, if
+    /// any.  Afterwards, `old` no longer identifies any relationship.  This
+    /// fails if there's already a relationship called `new_name`.
+    ///
+    pub fn rename_relation<S: AsRef<str>>(
+        &mut self,
+        old: &RelationshipId,
+        new_name: S,
+    ) -> Result<RelationshipId, RenameError> {
+        let new = RelationshipId(new_name.as_ref().to_owned());
+        if self.sets.contains_key(&new) {
+            return Err(RenameError::AlreadyExists(new));
+        }
+        let set = self
+            .sets
+            .remove(old)
+            .ok_or_else(|| RenameError::NoSuchRelationship(old.clone()))?;
+        self.sets.insert(new.clone(), set);
+
+        let rename = |rid: &mut RelationshipId| {
+            if rid == old {
+                *rid = new.clone();
+            }
+        };
+
+        for set in self.sets.values_mut() {
+            if set.contained_relationships.remove(old) {
+                set.contained_relationships.insert(new.clone());
+            }
+            set.inherited_relationships =
+                core::mem::take(&mut set.inherited_relationships)
+                    .into_iter()
+                    .map(|(mut via, parent_rids)| {
+                        rename(&mut via);
+                        let parent_rids = parent_rids
+                            .into_iter()
+                            .map(|mut parent_rid| {
+                                rename(&mut parent_rid);
+                                parent_rid
+                            })
+                            .collect();
+                        (via, parent_rids)
+                    })
+                    .collect();
+            if let Some(member_rid) = &mut set.userset_member_relation {
+                rename(member_rid);
+            }
+            if set.deny_relationships.remove(old) {
+                set.deny_relationships.insert(new.clone());
+            }
+        }
+
+        /*
+         * Memberships are sorted by relationship id, so we have to rebuild
+         * each set that refers to the old one.
+         */
+        for memberships in self.memberships.values_mut() {
+            if memberships.iter().any(|m| m.rid == *old) {
+                *memberships = core::mem::take(memberships)
+                    .into_iter()
+                    .map(|mut m| {
+                        rename(&mut m.rid);
+                        m
+                    })
+                    .collect();
+            }
+        }
+
+        /*
+         * Usersets of the old relationship have to be renamed wherever they
+         * appear as members.  Members are sorted by relationship id, too, so
+         * again we rebuild whatever contains them.
+         */
+        let rename_member = |member: &mut Member<O, U>| {
+            if let Member::ObjectRelation { relation, .. } = member {
+                rename(relation);
+            }
+        };
+        let mentions_old = |member: &Member<O, U>| {
+            matches!(member, Member::ObjectRelation { relation, .. }
+                if relation == old)
+        };
+        for set in self.sets.values_mut() {
+            for members in set.direct_members.values_mut() {
+                if members.iter().any(mentions_old) {
+                    *members = core::mem::take(members)
+                        .into_iter()
+                        .map(|mut member| {
+                            rename_member(&mut member);
+                            member
+                        })
+                        .collect();
+                }
+            }
+            for caveated in set.caveated_members.values_mut() {
+                if caveated.keys().any(mentions_old) {
+                    *caveated = core::mem::take(caveated)
+                        .into_iter()
+                        .map(|(mut member, caveat_id)| {
+                            rename_member(&mut member);
+                            (member, caveat_id)
+                        })
+                        .collect();
+                }
+            }
+        }
+        let renamed_members: Vec<_> = self
+            .memberships
+            .keys()
+            .filter(|m| mentions_old(m))
+            .cloned()
+            .collect();
+        for mut member in renamed_members {
+            let memberships = self.memberships.remove(&member).unwrap();
+            rename_member(&mut member);
+            self.memberships.insert(member, memberships);
+        }
+        self.inherited_index.clear();
+
+        for entry in self.log.iter_mut().flatten() {
+            match &mut entry.op {
+                LogOp::Write(tuple)
+                | LogOp::WriteCaveated(tuple, _)
+                | LogOp::Remove(tuple) => {
+                    rename(&mut tuple.rid);
+                    rename_member(&mut tuple.member);
+                }
+            }
+        }
+
+        self.schema_changed();
+        Ok(new)
+    }
+
+    ///
+    /// Add relationship `subrid` as a subset of relationship `rid`, as though
+    /// `rid` had been configured with [`RelationshipBuilder::with_subset()`]
+    ///
+    /// This changes the schema of a live store.  It takes effect immediately
+    /// for all objects.  Returns whether the rule was newly added.
+    ///
+    pub fn add_subset(
+        &mut self,
+        rid: &RelationshipId,
+        subrid: &RelationshipId,
+    ) -> bool {
+        assert!(self.sets.contains_key(subrid), "no such set");
+        let set = self.sets.get_mut(rid).expect("no such set");
+        let added = set.contained_relationships.insert(subrid.clone());
+        if added {
+            self.reachability.edge_added(rid, subrid);
+        }
+        self.rules_changed();
+        added
+    }
+
+    ///
+    /// Remove relationship `subrid` from the subsets of relationship `rid`
+    ///
+    /// This undoes [`MiniZ::add_subset()`] or
+    /// [`RelationshipBuilder::with_subset()`].  Tuples are unaffected: only
+    /// the rule changes.  Returns whether the rule existed.
+    ///
+    pub fn remove_subset(
+        &mut self,
+        rid: &RelationshipId,
+        subrid: &RelationshipId,
+    ) -> bool {
+        let set = self.sets.get_mut(rid).expect("no such set");
+        let removed = set.contained_relationships.remove(subrid);
+        if removed {
+            self.reachability.edges_removed(&self.sets, rid);
+        }
+        self.rules_changed();
+        removed
+    }
+
+    ///
+    /// Make relationship `rid` inherit itself via relationship `via`, as
+    /// though it had been configured with
+    /// [`RelationshipBuilder::with_inherited_set()`]
+    ///
+    /// Returns whether the rule was newly added.
+    ///
+    pub fn add_inherited_set(
+        &mut self,
+        rid: &RelationshipId,
+        via: &RelationshipId,
+    ) -> bool {
+        self.add_inherited_as(rid, via, rid)
+    }
+
+    ///
+    /// Undo [`MiniZ::add_inherited_set()`] or
+    /// [`RelationshipBuilder::with_inherited_set()`]
+    ///
+    /// Returns whether the rule existed.
+    ///
+    pub fn remove_inherited_set(
+        &mut self,
+        rid: &RelationshipId,
+        via: &RelationshipId,
+    ) -> bool {
+        self.remove_inherited_as(rid, via, rid)
+    }
+
+    ///
+    /// Make relationship `rid` include relationship `parent_relation` on the
+    /// objects reached via relationship `via`, as though it had been
+    /// configured with [`RelationshipBuilder::with_inherited_as()`]
+    ///
+    /// Returns whether the rule was newly added.
+    ///
+    pub fn add_inherited_as(
+        &mut self,
+        rid: &RelationshipId,
+        via: &RelationshipId,
+        parent_relation: &RelationshipId,
+    ) -> bool {
+        assert!(self.sets.contains_key(via), "no such set");
+        assert!(self.sets.contains_key(parent_relation), "no such set");
+        let set = self.sets.get_mut(rid).expect("no such set");
+        let added = set
+            .inherited_relationships
+            .entry(via.clone())
+            .or_default()
+            .insert(parent_relation.clone());
+        if added {
+            self.reachability.edge_added(rid, via);
+            self.reachability.edge_added(rid, parent_relation);
+        }
+        self.rules_changed();
+        added
+    }
+
+    ///
+    /// Undo [`MiniZ::add_inherited_as()`] or
+    /// [`RelationshipBuilder::with_inherited_as()`]
+    ///
+    /// Tuples are unaffected: only the rule changes.  Returns whether the rule
+    /// existed.
+    ///
+    pub fn remove_inherited_as(
+        &mut self,
+        rid: &RelationshipId,
+        via: &RelationshipId,
+        parent_relation: &RelationshipId,
+    ) -> bool {
+        let set = self.sets.get_mut(rid).expect("no such set");
+        let removed = match set.inherited_relationships.get_mut(via) {
+            Some(parent_rids) => {
+                let removed = parent_rids.remove(parent_relation);
+                if parent_rids.is_empty() {
+                    set.inherited_relationships.remove(via);
+                }
+                removed
+            }
+            None => false,
+        };
+        if removed {
+            self.reachability.edges_removed(&self.sets, rid);
+        }
+        self.rules_changed();
+        removed
+    }
+
+    ///
+    /// Replace the whole schema (relationships and caveats) with the one
+    /// configured in `new_schema`, keeping all existing tuples
+    ///
+    /// Every relationship that has tuples must exist in the new schema and
+    /// accept the kinds of members it has, and every caveat that a tuple was
+    /// written with must exist, too.  If not, this returns an error describing
+    /// the first problem found and the store is unchanged.  Relationships
+    /// without tuples may be dropped, unless a tuple has a
+    /// [`Member::ObjectRelation`] member that refers to them.  As with
+    /// [`MiniZ::add_subset()`], the change log (if enabled) does not record
+    /// schema changes.
+    ///
+    pub fn replace_schema(
+        &mut self,
+        new_schema: MiniZBuilder<O, U>,
+    ) -> Result<(), SchemaSwapError> {
+        new_schema.validate().map_err(SchemaSwapError::Build)?;
+        for (rid, set) in &self.sets {
+            if set.direct_members.is_empty() {
+                continue;
+            }
+
+            let new_set =
+                new_schema.relationships.get(rid).ok_or_else(|| {
+                    SchemaSwapError::MissingRelationship(rid.clone())
+                })?;
+            if !set
+                .direct_members
+                .values()
+                .flatten()
+                .all(|member| new_set.accepts_member(member))
+            {
+                return Err(SchemaSwapError::UnexpectedMemberKind(rid.clone()));
+            }
+            for member in set.direct_members.values().flatten() {
+                if let Member::ObjectRelation { relation, .. } = member {
+                    if !new_schema.relationships.contains_key(relation) {
+                        return Err(SchemaSwapError::MissingRelationship(
+                            relation.clone(),
+                        ));
+                    }
+                }
+            }
+            for caveat_id in
+                set.caveated_members.values().flat_map(|c| c.values())
+            {
+                if !new_schema.caveats.contains_key(caveat_id) {
+                    return Err(SchemaSwapError::MissingCaveat(
+                        caveat_id.clone(),
+                    ));
+                }
+            }
+        }
+
+        let mut old_sets = core::mem::take(&mut self.sets);
+        self.sets = new_schema.relationships;
+        for (rid, set) in self.sets.iter_mut() {
+            if let Some(old_set) = old_sets.remove(rid) {
+                set.direct_members = old_set.direct_members;
+                set.caveated_members = old_set.caveated_members;
+            }
+        }
+        self.caveats = new_schema.caveats;
+        self.schema_changed();
+        Ok(())
+    }
+
+    /// Update whatever is derived from the schema after it changes
+    fn schema_changed(&mut self) {
+        self.reachability = ReachabilityCache::new(&self.sets);
+        self.rules_changed();
+    }
+
+    ///
+    /// Like `schema_changed()`, but for callers that have already updated
+    /// `self.reachability` themselves
+    ///
+    /// That's cheaper than recomputing it when only a few edges have changed.
+    ///
+    fn rules_changed(&mut self) {
+        self.check_results.clear();
+        if let Some(closures) = &mut self.subset_closures {
+            closures.clear();
+            for rid in self.sets.keys() {
+                /*
+                 * Checks have to visit sets with deny relationships themselves
+                 * to apply the denial, so we don't skip past them.  Nor do we
+                 * skip past direct-only sets, whose subsets don't count.
+                 */
+                let mut closure = BTreeSet::new();
+                let mut stack = vec![rid];
+                while let Some(rid) = stack.pop() {
+                    for subrid in &self.sets[rid].contained_relationships {
+                        let subset = &self.sets[subrid];
+                        if closure.insert(subrid.clone())
+                            && subset.deny_relationships.is_empty()
+                            && !subset.direct_only
+                        {
+                            stack.push(subrid);
+                        }
+                    }
+                }
+                closures.insert(rid.clone(), closure);
+            }
+        }
+    }
+
+    /// Panics if `member` is a userset of a relationship that doesn't exist
+    fn check_member_relation(&self, member: &Member<O, U>) {
+        if let Member::ObjectRelation { relation, .. } = member {
+            assert!(self.sets.contains_key(relation), "no such set");
+        }
+    }
+
+    fn write_member(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        new_value: Member<O, U>,
+        caveat: Option<&CaveatId>,
+    ) -> Membership<O> {
+        if let Some(caveat) = caveat {
+            assert!(self.caveats.contains_key(caveat), "no such caveat");
+        }
+        self.check_member_relation(&new_value);
+        let set = self.sets.get_mut(rid).expect("no such set");
+        set.check_member_kind(rid, &new_value);
+        let members = set.direct_members.entry(parent.clone()).or_default();
+        assert!(!members.contains(&new_value));
+        assert!(members.insert(new_value.clone()));
+        if let Some(caveat) = caveat {
+            set.caveated_members
+                .entry(parent.clone())
+                .or_default()
+                .insert(new_value.clone(), caveat.clone());
+        }
+
+        /* Update the reverse index. */
+        let membership =
+            Membership { rid: rid.clone(), object: parent.clone() };
+        let memberships =
+            self.memberships.entry(new_value.clone()).or_default();
+        memberships.insert(membership.clone());
+
+        let tuple = Tuple { rid: rid.clone(), parent, member: new_value };
+        self.record(match caveat {
+            None => LogOp::Write(tuple),
+            Some(caveat) => LogOp::WriteCaveated(tuple, caveat.clone()),
+        });
+        membership
+    }
+
+    fn remove_member(
+        &mut self,
+        rid: &RelationshipId,
+        parent: O,
+        old_value: Member<O, U>,
+    ) -> bool {
+        let set = self.sets.get_mut(rid).expect("no such set");
+        let members = match set.direct_members.get_mut(&parent) {
+            Some(members) => members,
+            None => return false,
+        };
+        if !members.remove(&old_value) {
+            return false;
+        }
+        if members.is_empty() {
+            set.direct_members.remove(&parent);
+        }
+        if let Some(caveated) = set.caveated_members.get_mut(&parent) {
+            caveated.remove(&old_value);
+            if caveated.is_empty() {
+                set.caveated_members.remove(&parent);
+            }
+        }
+
+        /* Update the reverse index. */
+        let memberships = self
+            .memberships
+            .get_mut(&old_value)
+            .expect("reverse index missing membership");
+        assert!(memberships
+            .remove(&Membership { rid: rid.clone(), object: parent.clone() }));
+        if memberships.is_empty() {
+            self.memberships.remove(&old_value);
+        }
+
+        self.record(LogOp::Remove(Tuple {
+            rid: rid.clone(),
+            parent,
+            member: old_value,
+        }));
+        true
+    }
+
+    /// Bump the store's version and record `op` in the log, if enabled
+    fn record(&mut self, op: LogOp<O, U>) {
+        self.version += 1;
+        let tuple = match &op {
+            LogOp::Write(tuple)
+            | LogOp::WriteCaveated(tuple, _)
+            | LogOp::Remove(tuple) => tuple,
+        };
+        if self.check_results.enabled() {
+            let nodes = self.tuple_dependents(tuple);
+            self.check_results.invalidate(nodes);
+        }
+        if let Member::Object(child) = &tuple.member {
+            self.inherited_index.invalidate(child);
+        }
+        if let Some(log) = &mut self.log {
+            log.push(LogEntry { version: self.version, op });
+        }
+    }
+
+    ///
+    /// Returns the (relationship, object) pairs whose resolution looks at
+    /// `tuple`
+    ///
+    /// That's the tuple's relationship on its object, plus, if the member is
+    /// an object, every relationship that inherits through the tuple's
+    /// relationship on that object (because the tuple is an edge out of it).
+    /// A check that visits none of these can't be affected by writing or
+    /// removing the tuple.
+    ///
+    fn tuple_dependents(
+        &self,
+        tuple: &Tuple<O, U>,
+    ) -> Vec<(RelationshipId, O)> {
+        let mut nodes = vec![(tuple.rid.clone(), tuple.parent.clone())];
+        if let Member::Object(child) = &tuple.member {
+            nodes.extend(
+                self.sets
+                    .iter()
+                    .filter(|(_, set)| {
+                        set.inherited_relationships.contains_key(&tuple.rid)
+                    })
+                    .map(|(rid, _)| (rid.clone(), child.clone())),
+            );
+        }
+        nodes
+    }
+
+    /*
+     * Change log
+     */
+
+    ///
+    /// Start recording every write and remove in an append-only log
+    ///
+    /// With the log enabled, [`MiniZ::replay_to()`] can reconstruct the store
+    /// as it was at any version since it was built.  The log is kept in
+    /// memory and is never truncated.  Since replaying starts from an empty
+    /// store, the log must be enabled before anything is written.
+    ///
+    /// Enabling the log when it's already enabled has no effect.
+    ///
+    pub fn enable_log(&mut self) {
+        if self.log.is_none() {
+            assert_eq!(
+                self.version, 0,
+                "log must be enabled before the store is changed"
+            );
+            self.log = Some(Vec::new());
+        }
+    }
+
+    /// Returns the current version of the store
+    ///
+    /// The version starts at 0 and increases by one with every tuple written
+    /// or removed.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Returns the log of changes, if it's enabled
+    pub fn log(&self) -> Option<&[LogEntry<O, U>]> {
+        self.log.as_deref()
+    }
+
+    ///
+    /// Returns a copy of this store as it was at version `version`
+    ///
+    /// The copy has the same relationships as this store (which are assumed
+    /// not to have changed) and the tuples that were present at `version`.  Its
+    /// log contains the entries up to that version.  Versions newer than the
+    /// current one produce a copy of the current store.
+    ///
+    /// Panics if the log is not enabled.
+    ///
+    pub fn replay_to(&self, version: u64) -> MiniZ<O, U> {
+        let log = self.log.as_ref().expect("log is not enabled");
+        let mut miniz = MiniZ {
+            sets: self
+                .sets
+                .iter()
+                .map(|(rid, set)| (rid.clone(), set.clone_schema()))
+                .collect(),
+            caveats: self.caveats.clone(),
+            memberships: BTreeMap::new(),
+            version: 0,
+            log: Some(Vec::new()),
+            check_results: CheckResultCache::new(),
+            subset_closures: self.subset_closures.clone(),
+            reachability: self.reachability.clone(),
+            inherited_index: InheritedIndex::new(),
+        };
+
+        for entry in log.iter().take_while(|entry| entry.version <= version) {
+            match &entry.op {
+                LogOp::Write(tuple) => {
+                    miniz.write_tuple(tuple.clone());
+                }
+                LogOp::WriteCaveated(tuple, caveat) => {
+                    miniz.write_member(
+                        &tuple.rid,
+                        tuple.parent.clone(),
+                        tuple.member.clone(),
+                        Some(caveat),
+                    );
+                }
+                LogOp::Remove(tuple) => {
+                    miniz.remove_member(
+                        &tuple.rid,
+                        tuple.parent.clone(),
+                        tuple.member.clone(),
+                    );
+                }
+            }
+        }
+
+        miniz
+    }
+
+    /*
+     * Read operations
+     */
+
+    ///
+    /// Returns whether object `child` has relationship `rid` with object
+    /// `parent` _directly_.  To check for indirect relationships, see
+    /// [`check_member()`].
+    ///
+    pub fn set_contains_object_directly(
+        &self,
+        rid: &RelationshipId,
+        parent: &O,
+        child: O,
+    ) -> bool {
+        let set = self.sets.get(rid).expect("no such set");
+        match set.direct_members.get(parent) {
+            Some(members) => members.contains(&Member::Object(child)),
+            None => false,
+        }
+    }
+
+    ///
+    /// Returns whether user `child` has relationship `rid` with object
+    /// `parent` _directly_.  To check for indirect relationships, see
+    /// [`check_member()`].
+    ///
+    pub fn set_contains_user_directly(
+        &self,
+        rid: &RelationshipId,
+        parent: &O,
+        child: U,
+    ) -> bool {
+        let set = self.sets.get(rid).expect("no such set");
+        match set.direct_members.get(parent) {
+            Some(members) => members.contains(&Member::User(child)),
+            None => false,
+        }
+    }
+
+    ///
+    /// Returns every relationship that object `child` has with object `parent`
+    /// _directly_
+    ///
+    /// For example, in a store where "dir1" is the "parent" of "doc123",
+    /// `relations_between(&dir1, &doc123)` returns "parent".  The
+    /// relationships are sorted.  Relationships in the other direction (from
+    /// `child` to `parent`) are not included.
+    ///
+    pub fn relations_between(
+        &self,
+        parent: &O,
+        child: &O,
+    ) -> Vec<RelationshipId> {
+        let child = Member::Object(child.clone());
+        self.sets
+            .iter()
+            .filter(|(_, set)| {
+                set.direct_members
+                    .get(parent)
+                    .is_some_and(|members| members.contains(&child))
+            })
+            .map(|(rid, _)| rid.clone())
+            .collect()
+    }
+
+    /// List the users and objects having a direct relationship with `parent`
+    pub fn set_list_direct_members(
+        &self,
+        rid: &RelationshipId,
+        parent: &O,
+    ) -> Vec<&Member<O, U>> {
+        let set = self.sets.get(rid).expect("no such set");
+        match set.direct_members.get(parent) {
+            Some(members) => members.iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// List only the objects having a direct relationship with `parent`
+    pub fn set_list_direct_object_members(
+        &self,
+        rid: &RelationshipId,
+        parent: &O,
+    ) -> Vec<&O> {
+        self.set_list_direct_members(rid, parent)
+            .into_iter()
+            .filter_map(|member| match member {
+                Member::Object(object) => Some(object),
+                Member::User(_) | Member::ObjectRelation { .. } => None,
+            })
+            .collect()
+    }
+
+    /// List only the users having a direct relationship with `parent`
+    pub fn set_list_direct_user_members(
+        &self,
+        rid: &RelationshipId,
+        parent: &O,
+    ) -> Vec<&U> {
+        self.set_list_direct_members(rid, parent)
+            .into_iter()
+            .filter_map(|member| match member {
+                Member::User(user) => Some(user),
+                Member::Object(_) | Member::ObjectRelation { .. } => None,
+            })
+            .collect()
+    }
+
+    ///
+    /// List every (object, member) pair in relationship `rid`, across all
+    /// objects
+    ///
+    /// Pairs are sorted by object, then member.
+    ///
+    pub fn relation_tuples(
+        &self,
+        rid: &RelationshipId,
+    ) -> Vec<(&O, &Member<O, U>)> {
+        let set = self.sets.get(rid).expect("no such set");
+        set.direct_members
+            .iter()
+            .flat_map(|(parent, members)| {
+                members.iter().map(move |member| (parent, member))
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns an iterator over every tuple in the store
+    ///
+    /// Tuples are produced lazily, sorted by relationship, then object, then
+    /// member.
+    ///
+    pub fn iter_tuples(&self) -> impl Iterator<Item = Tuple<&O, &U>> + '_ {
+        self.sets.iter().flat_map(|(rid, set)| {
+            set.direct_members.iter().flat_map(move |(parent, members)| {
+                members.iter().map(move |member| Tuple {
+                    rid: rid.clone(),
+                    parent,
+                    member: member.as_ref(),
+                })
+            })
+        })
+    }
+
+    /// List the objects that this object has a direct relationship with
+    pub fn object_lookup_memberships(&self, object: O) -> Vec<&Membership<O>> {
+        self.object_lookup_memberships_ref(&object)
+    }
+
+    /// List the objects that this user has a direct relationship with
+    pub fn user_lookup_memberships(&self, user: U) -> Vec<&Membership<O>> {
+        self.user_lookup_memberships_ref(&user)
+    }
+
+    /// Like [`MiniZ::object_lookup_memberships()`], but borrows `object`
+    pub fn object_lookup_memberships_ref(
+        &self,
+        object: &O,
+    ) -> Vec<&Membership<O>> {
+        match self.memberships.get(&Member::Object(object.clone())) {
+            Some(memberships) => memberships.iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Like [`MiniZ::user_lookup_memberships()`], but borrows `user`
+    pub fn user_lookup_memberships_ref(&self, user: &U) -> Vec<&Membership<O>> {
+        match self.memberships.get(&Member::User(user.clone())) {
+            Some(memberships) => memberships.iter().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    ///
+    /// Returns an iterator over the whole reverse index: every member paired
+    /// with each of its direct memberships
+    ///
+    /// This is the same information as [`MiniZ::object_lookup_memberships()`]
+    /// and [`MiniZ::user_lookup_memberships()`] for every member at once, for
+    /// building an index outside the store.  Pairs are produced lazily,
+    /// sorted by member, then membership.
+    ///
+    pub fn iter_memberships(
+        &self,
+    ) -> impl Iterator<Item = (&Member<O, U>, &Membership<O>)> + '_ {
+        self.memberships.iter().flat_map(|(member, memberships)| {
+            memberships.iter().map(move |membership| (member, membership))
+        })
+    }
+
+    ///
+    /// Returns every object that `object` is transitively a member of through
+    /// relationship `rid`
+    ///
+    /// With a "parent" relationship, these are the object's ancestors: its
+    /// parents, their parents, and so on.  Nearer ancestors come first.  This
+    /// terminates even if the relationship forms a cycle, and `object` itself
+    /// is only included if it's its own ancestor.
+    ///
+    pub fn ancestors(&self, rid: &RelationshipId, object: &O) -> Vec<O> {
+        let mut ancestors = Vec::new();
+        let mut seen = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(object);
+        while let Some(object) = queue.pop_front() {
+            let memberships = self
+                .memberships
+                .get(&Member::Object(object.clone()))
+                .into_iter()
+                .flatten()
+                .filter(|m| m.rid == *rid);
+            for membership in memberships {
+                if seen.insert(&membership.object) {
+                    ancestors.push(membership.object.clone());
+                    queue.push_back(&membership.object);
+                }
+            }
+        }
+        ancestors
+    }
+
+    ///
+    /// Returns every object that is transitively a member of `object` through
+    /// relationship `rid`
+    ///
+    /// This is the counterpart of [`MiniZ::ancestors()`]: with a "parent"
+    /// relationship, these are everything under `object`.  Nearer descendants
+    /// come first.  This terminates even if the relationship forms a cycle,
+    /// and `object` itself is only included if it's its own descendant.
+    ///
+    pub fn descendants(&self, rid: &RelationshipId, object: &O) -> Vec<O> {
+        let set = self.sets.get(rid).expect("no such set");
+        let mut descendants = Vec::new();
+        let mut seen = BTreeSet::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(object);
+        while let Some(object) = queue.pop_front() {
+            for member in set.direct_members.get(object).into_iter().flatten() {
+                if let Member::Object(child) = member {
+                    if seen.insert(child) {
+                        descendants.push(child.clone());
+                        queue.push_back(child);
+                    }
+                }
+            }
+        }
+        descendants
+    }
+
+    ///
+    /// Returns the direct memberships of user `user`, grouped by relationship
+    ///
+    /// The objects for each relationship are sorted.  This is the same
+    /// information as [`MiniZ::user_lookup_memberships()`] in a form that's
+    /// handy for display (e.g., "alice is owner of dir1 and editor of doc123
+    /// and doc456").
+    ///
+    pub fn user_memberships_grouped(
+        &self,
+        user: &U,
+    ) -> BTreeMap<RelationshipId, Vec<&O>> {
+        let mut grouped: BTreeMap<RelationshipId, Vec<&O>> = BTreeMap::new();
+        for membership in self
+            .memberships
+            .get(&Member::User(user.clone()))
+            .into_iter()
+            .flatten()
+        {
+            grouped
+                .entry(membership.rid.clone())
+                .or_default()
+                .push(&membership.object);
+        }
+        grouped
+    }
+
+    ///
+    /// Returns the direct memberships of each of `members`
+    ///
+    /// This is equivalent to calling [`MiniZ::object_lookup_memberships()`] or
+    /// [`MiniZ::user_lookup_memberships()`] for each member, except that
+    /// duplicates in `members` are looked up only once.  Every requested
+    /// member appears in the result, with an empty list if it has no
+    /// memberships.
+    ///
+    pub fn lookup_memberships_for(
+        &self,
+        members: &[Member<O, U>],
+    ) -> BTreeMap<Member<O, U>, Vec<&Membership<O>>> {
+        let mut result = BTreeMap::new();
+        for member in members {
+            if let Entry::Vacant(entry) = result.entry(member.clone()) {
+                entry.insert(
+                    self.memberships
+                        .get(member)
+                        .into_iter()
+                        .flatten()
+                        .collect(),
+                );
+            }
+        }
+        result
+    }
+
+    ///
+    /// List objects known to the store in sorted order, starting after
+    /// `after` (if given) and returning at most `limit` of them
+    ///
+    /// An object is known to the store if it has members in any relationship
+    /// or is itself a member of any relationship.  To page through all
+    /// objects, pass the last object from each page as `after` for the next
+    /// one.
+    ///
+    pub fn list_objects(&self, after: Option<&O>, limit: usize) -> Vec<O> {
+        let start = match after {
+            Some(after) => Bound::Excluded(after.clone()),
+            None => Bound::Unbounded,
+        };
+
+        /*
+         * Each of the underlying maps is sorted, so we only need to look at
+         * the first `limit` objects after `after` in each one.
+         */
+        let mut objects = BTreeSet::new();
+        for set in self.sets.values() {
+            objects.extend(
+                set.direct_members
+                    .range((start.clone(), Bound::Unbounded))
+                    .take(limit)
+                    .map(|(object, _)| object.clone()),
+            );
+        }
+        objects.extend(
+            self.memberships
+                .range((start.map(Member::Object), Bound::Unbounded))
+                .map_while(|(member, _)| match member {
+                    Member::Object(object) => Some(object.clone()),
+                    Member::User(_) | Member::ObjectRelation { .. } => None,
+                })
+                .take(limit),
+        );
+
+        objects.into_iter().take(limit).collect()
+    }
+
+    ///
+    /// Returns whether relationship `to` is reachable from relationship `from`
+    /// in the graph of relationship definitions
+    ///
+    /// Relationship R has an edge to each relationship it's configured with
+    /// as a subset, an inherited set, a userset member relationship, or a deny
+    /// relationship.  If `to` is reachable from `from`, then changes to `to`
+    /// may affect the results of checking `from`.  Every relationship is
+    /// reachable from itself.  Only the schema is considered: a
+    /// [`Member::ObjectRelation`] member written with
+    /// [`MiniZ::write_object_relation()`] also leads checks from its
+    /// relationship to the userset's relationship, but that's not reflected
+    /// here.
+    ///
+    /// The answers are precomputed for every pair of relationships and kept
+    /// up to date as the schema changes, so this is cheap to call often.
+    ///
+    pub fn relation_reachable(
+        &self,
+        from: &RelationshipId,
+        to: &RelationshipId,
+    ) -> bool {
+        self.reachability.reachable(from, to)
+    }
+
+    ///
+    /// Returns the relationships that appear to be unused: those that have no
+    /// direct members and that no other relationship refers to
+    ///
+    /// A relationship refers to another if it's configured with it as a subset,
+    /// an inherited set, a userset member relationship, or a deny relationship
+    /// (as in [`MiniZ::relation_reachable()`]).  A relationship that's part of
+    /// a [`Member::ObjectRelation`] member of any tuple is referenced, too.  A
+    /// relationship that refers only to itself is still unused.  Such
+    /// relationships can't affect any check other than checks of the
+    /// relationship itself, which always fail, so they're likely candidates for
+    /// removing from the schema.
+    ///
+    pub fn unused_relations(&self) -> Vec<RelationshipId> {
+        let mut referenced = BTreeSet::new();
+        for (rid, set) in &self.sets {
+            referenced.extend(set.references().filter(|other| *other != rid));
+        }
+        referenced.extend(self.memberships.keys().filter_map(|member| {
+            match member {
+                Member::ObjectRelation { relation, .. } => Some(relation),
+                Member::Object(_) | Member::User(_) => None,
+            }
+        }));
+
+        self.sets
+            .iter()
+            .filter(|(rid, set)| {
+                set.direct_members.is_empty() && !referenced.contains(rid)
+            })
+            .map(|(rid, _)| rid.clone())
+            .collect()
+    }
+
+    /// Returns the description configured for relationship `rid`, if any
+    pub fn set_description(&self, rid: &RelationshipId) -> Option<&str> {
+        let set = self.sets.get(rid).expect("no such set");
+        set.description.as_deref()
+    }
+
+    /// Report summary statistics about the size of this store
+    pub fn stats(&self) -> StoreStats {
+        let num_tuples = self
+            .sets
+            .values()
+            .flat_map(|set| set.direct_members.values())
+            .map(|members| members.len())
+            .sum();
+        let num_objects_with_memberships = self
+            .memberships
+            .keys()
+            .filter(|member| matches!(member, Member::Object(_)))
+            .count();
+        StoreStats {
+            num_sets: self.sets.len(),
+            num_tuples,
+            num_objects_with_memberships,
+        }
+    }
+
+    ///
+    /// Count the direct members of relationship `rid` of each kind, across
+    /// all objects
+    ///
+    /// This is useful for auditing a schema: for example, a "parent"
+    /// relationship that has any user members was probably written to by
+    /// mistake.
+    ///
+    pub fn relation_member_breakdown(
+        &self,
+        rid: &RelationshipId,
+    ) -> MemberBreakdown {
+        let set = self.sets.get(rid).expect("no such set");
+        let mut breakdown =
+            MemberBreakdown { objects: 0, users: 0, object_relations: 0 };
+        for member in set.direct_members.values().flatten() {
+            match member {
+                Member::Object(_) => breakdown.objects += 1,
+                Member::User(_) => breakdown.users += 1,
+                Member::ObjectRelation { .. } => {
+                    breakdown.object_relations += 1
+                }
+            }
+        }
+        breakdown
+    }
+
+    ///
+    /// Verify that the reverse index exactly matches the direct members of
+    /// every relationship
+    ///
+    /// This is intended for use in tests, particularly after bulk operations.
+    /// It walks the entire store, so it's not cheap.
+    ///
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantError<O, U>>> {
+        let mut errors = Vec::new();
+
+        for (rid, set) in &self.sets {
+            for (parent, members) in &set.direct_members {
+                if members.is_empty() {
+                    errors.push(InvariantError::EmptyDirectMembers {
+                        rid: rid.clone(),
+                        parent: parent.clone(),
+                    });
+                }
+
+                for member in members {
+                    let membership =
+                        Membership { rid: rid.clone(), object: parent.clone() };
+                    let found = self
+                        .memberships
+                        .get(member)
+                        .map(|memberships| memberships.contains(&membership))
+                        .unwrap_or(false);
+                    if !found {
+                        errors.push(InvariantError::MissingMembership {
+                            rid: rid.clone(),
+                            parent: parent.clone(),
+                            member: member.clone(),
+                        });
+                    }
+                }
+            }
+        }
+
+        for (member, memberships) in &self.memberships {
+            if memberships.is_empty() {
+                errors.push(InvariantError::EmptyMemberships {
+                    member: member.clone(),
+                });
+            }
+
+            for membership in memberships {
+                let found = self
+                    .sets
+                    .get(&membership.rid)
+                    .and_then(|set| set.direct_members.get(&membership.object))
+                    .map(|members| members.contains(member))
+                    .unwrap_or(false);
+                if !found {
+                    errors.push(InvariantError::MissingDirectMember {
+                        member: member.clone(),
+                        membership: membership.clone(),
+                    });
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    ///
+    /// Like [`MiniZ::check_member()`], but distinguishes a denial for an
+    /// object that the store knows about from one for an object that appears
+    /// nowhere in the store
+    ///
+    pub fn check_member_detailed(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> CheckResult {
+        if self.check_member(rid, object.clone(), user) {
+            CheckResult::Allowed
+        } else if self.object_known(&object) {
+            CheckResult::Denied
+        } else {
+            CheckResult::ObjectUnknown
+        }
+    }
+
+    /// Returns whether `object` appears anywhere in the store
+    fn object_known(&self, object: &O) -> bool {
+        self.sets.values().any(|set| set.direct_members.contains_key(object))
+            || self.memberships.contains_key(&Member::Object(object.clone()))
+    }
+
+    ///
+    /// Check whether the user `user` has relationship `rid` with object
+    /// `object`, either directly or through a combination of implied or
+    /// inherited relationships
+    ///
+    /// Tuples written with a caveat don't count here.  See
+    /// [`MiniZ::check_member_with_context()`].
+    ///
+    pub fn check_member(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> bool {
+        self.check_member_consistency(rid, object, user, Consistency::Strong)
+    }
+
+    ///
+    /// Answer a check if that can be done without following any of the
+    /// relationship's rules
+    ///
+    /// This returns `Some(true)` if user `user` is a direct member of
+    /// relationship `rid` on object `object` (by way of a tuple without a
+    /// caveat), and the relationship has no deny relationships that could
+    /// override that.  Otherwise, it returns `None`, and only
+    /// [`MiniZ::check_member()`] can give the answer.  That's the common case
+    /// of a direct grant, which `check_member()` itself tries first.
+    ///
+    pub fn check_member_fast(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+    ) -> Option<bool> {
+        let set = self.sets.get(rid).expect("no such set");
+        if set.deny_relationships.is_empty()
+            && self.has_direct_user(rid, object, user, &EMPTY_CONTEXT)
+        {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    ///
+    /// Like [`MiniZ::check_member()`], but returns an error instead of
+    /// panicking if relationship `rid` does not exist
+    ///
+    /// A relationship that doesn't exist usually means a bug in the caller,
+    /// while an object that has no members is an ordinary denial, so the
+    /// latter is still `Ok(false)`.
+    ///
+    pub fn try_check_member(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> Result<bool, CheckError> {
+        if !self.sets.contains_key(rid) {
+            return Err(CheckError::NoSuchRelationship(rid.clone()));
+        }
+        Ok(self.check_member(rid, object, user))
+    }
+
+    ///
+    /// Like [`MiniZ::check_member()`], but lets the caller accept a stale
+    /// answer from the check cache in exchange for not recomputing it
+    ///
+    /// See [`Consistency`].  If the check cache is not enabled (see
+    /// [`MiniZ::enable_check_cache()`]), the answer is always computed from
+    /// scratch, so both options behave the same way.
+    ///
+    pub fn check_member_consistency(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+        consistency: Consistency,
+    ) -> bool {
+        if let Some(found) = self.check_member_fast(rid, &object, &user) {
+            return found;
+        }
+        let key = (rid.clone(), object, user);
+        if let Some(entry) = self.check_results.get(&key) {
+            if !entry.stale || consistency == Consistency::MinimizeLatency {
+                return entry.found;
+            }
+        }
+        let (rid, object, user) = &key;
+        let mut cache = CheckCache::new(&EMPTY_CONTEXT);
+        let found = self.check_cached(rid, object, user, &mut cache);
+        if self.check_results.enabled() {
+            self.check_results.insert(key, found, cache.dependencies());
+        }
+        found
+    }
+
+    ///
+    /// Like [`MiniZ::check_member()`], but tuples written with a caveat count
+    /// only if `context` satisfies the caveat
+    ///
+    /// Tuples written without a caveat always count.  (`check_member()` is the
+    /// same as this with an empty context.)  Results of these checks are never
+    /// cached by [`MiniZ::enable_check_cache()`].
+    ///
+    pub fn check_member_with_context(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+        context: &Context,
+    ) -> bool {
+        self.check_cached(rid, &object, &user, &mut CheckCache::new(context))
+    }
+
+    ///
+    /// Start remembering the results of [`MiniZ::check_member()`] across calls
+    ///
+    /// This speeds up checks that are made over and over.  Each result is
+    /// remembered along with the (relationship, object) pairs that were
+    /// visited to compute it.  Writing or removing a tuple invalidates only
+    /// the results that could depend on it: those that visited the tuple's
+    /// relationship on its object, or (for a tuple whose member is an object)
+    /// that visited a relationship inheriting through the tuple's relationship
+    /// on the member object.  `check_member()` never uses an invalidated
+    /// result, so its results are never stale.
+    /// [`MiniZ::check_member_consistency()`] can be asked to use invalidated
+    /// results, too.  Changing the schema (e.g., renaming a relationship)
+    /// forgets everything that's been remembered.  Nothing is ever evicted
+    /// otherwise, so the cache grows with the number of distinct checks made
+    /// since the schema last changed.
+    ///
+    /// This is only available with the `std` feature because the cache must
+    /// be protected by a lock to be updated by `check_member()`, which only
+    /// takes `&self`.
+    ///
+    #[cfg(any(feature = "std", test))]
+    pub fn enable_check_cache(&mut self) {
+        if self.check_results.results.is_none() {
+            self.check_results.results =
+                Some(std::sync::Mutex::new(CheckResults::new()));
+        }
+    }
+
+    ///
+    /// Speed up checks that inherit relationships from objects with many
+    /// memberships
+    ///
+    /// To find the objects that a relationship inherits through (e.g., an
+    /// object's "parent"), a check normally looks at every direct membership
+    /// of the object and skips the ones in other relationships.  That's
+    /// wasteful for an object that's a direct member of many relationships.
+    /// With this enabled, the store remembers, for each object that a check
+    /// has looked at this way, where its memberships in each relationship
+    /// are, so later checks go straight to the relevant ones.  What's
+    /// remembered about an object is forgotten whenever a tuple having the
+    /// object as its member is written or removed.  Answers are the same
+    /// either way.
+    ///
+    /// Like [`MiniZ::enable_check_cache()`], this is only available with the
+    /// `std` feature.
+    ///
+    #[cfg(any(feature = "std", test))]
+    pub fn enable_inherited_index(&mut self) {
+        if self.inherited_index.starts.is_none() {
+            self.inherited_index.starts =
+                Some(std::sync::Mutex::new(BTreeMap::new()));
+        }
+    }
+
+    ///
+    /// Check whether everyone in the userset `subject_rid` on `subject_object`
+    /// has relationship `rid` with object `object`
+    ///
+    /// This is a check whose subject is a userset (like "the members of
+    /// group1") rather than a single user.  It succeeds if resolving `rid` on
+    /// `object` leads to `subject_rid` on `subject_object`, which includes the
+    /// case where they're the same.  For example, if "viewer" follows object
+    /// members as usersets of "member" and "group1" is a direct viewer of
+    /// "doc123", then "member" on "group1" is included in "viewer" on
+    /// "doc123".
+    ///
+    /// This doesn't look at which users are actually in the subject userset,
+    /// so it may be false even when every current member of the subject
+    /// userset happens to have the relationship some other way.
+    ///
+    pub fn check_userset(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        subject_rid: &RelationshipId,
+        subject_object: &O,
+    ) -> bool {
+        struct Finder<'a, O> {
+            rid: &'a RelationshipId,
+            object: &'a O,
+        }
+        impl<'a, O: PartialEq> Finder<'a, O> {
+            fn enter(&self, rid: &RelationshipId, object: &O) -> WalkAction {
+                if rid == self.rid && object == self.object {
+                    WalkAction::Stop
+                } else {
+                    WalkAction::Continue
+                }
+            }
+        }
+        impl<'a, O: PartialEq, U> CheckVisitor<O, U> for Finder<'a, O> {
+            fn enter_subset(
+                &mut self,
+                _rid: &RelationshipId,
+                object: &O,
+                subrid: &RelationshipId,
+            ) -> WalkAction {
+                self.enter(subrid, object)
+            }
+
+            fn enter_userset_member(
+                &mut self,
+                _rid: &RelationshipId,
+                _object: &O,
+                group: &O,
+                member_rid: &RelationshipId,
+            ) -> WalkAction {
+                self.enter(member_rid, group)
+            }
+
+            fn enter_inherited(
+                &mut self,
+                _rid: &RelationshipId,
+                _object: &O,
+                _via: &RelationshipId,
+                parent: &O,
+                parent_rid: &RelationshipId,
+            ) -> WalkAction {
+                self.enter(parent_rid, parent)
+            }
+        }
+
+        if rid == subject_rid && object == subject_object {
+            return true;
+        }
+
+        let mut finder = Finder { rid: subject_rid, object: subject_object };
+        !self.walk(rid, object, &mut finder)
+    }
+
+    ///
+    /// Check whether `subject` has relationship `rid` with object `object`
+    ///
+    /// For a [`Member::User`], this is the same as [`MiniZ::check_member()`].
+    /// For a [`Member::Object`] (like a group), this checks whether the object
+    /// itself is a member of any set that resolving `rid` on `object` visits,
+    /// i.e., whether it was granted the relationship directly or through a
+    /// combination of implied or inherited relationships.  This lets callers
+    /// treat users and groups as principals the same way.  For a
+    /// [`Member::ObjectRelation`], this is the same as
+    /// [`MiniZ::check_userset()`].
+    ///
+    pub fn check_member_subject(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        subject: &Member<O, U>,
+    ) -> bool {
+        struct Finder<'a, O> {
+            object: &'a O,
+        }
+        impl<'a, O: PartialEq, U> CheckVisitor<O, U> for Finder<'a, O> {
+            fn visit_direct_member(
+                &mut self,
+                _rid: &RelationshipId,
+                _object: &O,
+                member: &Member<O, U>,
+            ) -> WalkAction {
+                match member {
+                    Member::Object(object) if object == self.object => {
+                        WalkAction::Stop
+                    }
+                    _ => WalkAction::Continue,
+                }
+            }
+        }
+
+        match subject {
+            Member::User(user) => {
+                self.check_member(rid, object.clone(), user.clone())
+            }
+            Member::Object(subject_object) => {
+                let mut finder = Finder { object: subject_object };
+                !self.walk(rid, object, &mut finder)
+            }
+            Member::ObjectRelation { object: subject_object, relation } => {
+                self.check_userset(rid, object, relation, subject_object)
+            }
+        }
+    }
+
+    ///
+    /// Preview the effect of `changes` on the checks described by `probes`
+    ///
+    /// This applies `changes` to a copy of the store and returns, for each
+    /// (relationship, object, user) probe, the result of
+    /// [`MiniZ::check_member()`] before and after the changes.  The store
+    /// itself is not modified.  Since this copies the whole store, it's best
+    /// suited to previewing a migration rather than to frequent use.
+    ///
+    pub fn simulate<F: FnOnce(&mut MiniZ<O, U>)>(
+        &self,
+        changes: F,
+        probes: &[(RelationshipId, O, U)],
+    ) -> Vec<(bool, bool)> {
+        let mut after = self.clone();
+        changes(&mut after);
+        probes
+            .iter()
+            .map(|(rid, object, user)| {
+                (
+                    self.check_member(rid, object.clone(), user.clone()),
+                    after.check_member(rid, object.clone(), user.clone()),
+                )
+            })
+            .collect()
+    }
+
+    ///
+    /// Compare [`MiniZ::check_member()`] against a simple reference
+    /// implementation for each of `probes`, returning the checks for which
+    /// they disagree
+    ///
+    /// Each probe is a (relationship, object, user) check.  The reference
+    /// implementation follows the same rules as `check_member()` but in the
+    /// most straightforward way: it finds every (relationship, object) pair
+    /// reachable from the one being checked, without using the reverse
+    /// index, the check cache, precomputed subset closures, or any other
+    /// shortcut.  It's slow, but easy to convince yourself it's right, which
+    /// makes this useful for testing changes to how checks are done.  An
+    /// empty result means they agreed on every probe.
+    ///
+    pub fn verify_against_naive(
+        &self,
+        probes: &[(RelationshipId, O, U)],
+    ) -> Vec<Discrepancy<O, U>> {
+        probes
+            .iter()
+            .filter_map(|(rid, object, user)| {
+                let expected = self.naive_check(rid, object, user, true);
+                let actual =
+                    self.check_member(rid, object.clone(), user.clone());
+                (expected != actual).then(|| Discrepancy {
+                    rid: rid.clone(),
+                    object: object.clone(),
+                    user: user.clone(),
+                    expected,
+                    actual,
+                })
+            })
+            .collect()
+    }
+
+    /// Reference implementation of `check_member()` used by
+    /// `verify_against_naive()`
+    fn naive_check(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        apply_denials: bool,
+    ) -> bool {
+        let mut seen = BTreeSet::new();
+        let mut todo = vec![(rid.clone(), object.clone())];
+        while let Some(node) = todo.pop() {
+            if !seen.insert(node.clone()) {
+                continue;
+            }
+            let (rid, object) = node;
+            let set = &self.sets[&rid];
+
+            /* Nothing counts for a user that's denied this relationship. */
+            if apply_denials
+                && set
+                    .deny_relationships
+                    .iter()
+                    .any(|deny| self.naive_check(deny, &object, user, false))
+            {
+                continue;
+            }
+
+            let counts = |parent: &O, member: &Member<O, U>| {
+                self.caveat_satisfied(
+                    set.caveat_for(parent, member),
+                    &EMPTY_CONTEXT,
+                )
+            };
+            let members = set.direct_members.get(&object).into_iter().flatten();
+            for member in members.filter(|member| counts(&object, member)) {
+                match member {
+                    Member::User(member_user) if member_user == user => {
+                        return true;
+                    }
+                    Member::User(_) => (),
+                    Member::Object(group) if !set.direct_only => {
+                        if let Some(member_rid) = &set.userset_member_relation {
+                            todo.push((member_rid.clone(), group.clone()));
+                        }
+                    }
+                    Member::ObjectRelation { object: group, relation }
+                        if !set.direct_only =>
+                    {
+                        todo.push((relation.clone(), group.clone()));
+                    }
+                    Member::Object(_) | Member::ObjectRelation { .. } => (),
+                }
+            }
+            if set.direct_only {
+                continue;
+            }
+
+            for subrid in &set.contained_relationships {
+                todo.push((subrid.clone(), object.clone()));
+            }
+
+            /*
+             * Find the objects that this one inherits from by looking at every
+             * tuple of each relationship it inherits through.
+             */
+            let child = Member::Object(object.clone());
+            for (via, parent_rids) in &set.inherited_relationships {
+                let via_set = &self.sets[via];
+                for (parent, members) in &via_set.direct_members {
+                    if !members.contains(&child)
+                        || !self.caveat_satisfied(
+                            via_set.caveat_for(parent, &child),
+                            &EMPTY_CONTEXT,
+                        )
+                    {
+                        continue;
+                    }
+                    for parent_rid in parent_rids {
+                        todo.push((parent_rid.clone(), parent.clone()));
+                    }
+                }
+            }
+        }
+        false
+    }
+
+    ///
+    /// Check each of `assertions` and report which ones don't hold
+    ///
+    /// This is intended for data-driven tests of a policy: a list of which
+    /// users should and shouldn't have which relationships with which
+    /// objects.  All assertions are checked, even after one fails.
+    ///
+    pub fn run_assertions(
+        &self,
+        assertions: &[Assertion<O, U>],
+    ) -> AssertionReport<O, U> {
+        let mut report = AssertionReport { passed: 0, failures: Vec::new() };
+        for (index, assertion) in assertions.iter().enumerate() {
+            let actual = self.check_member(
+                &assertion.rid,
+                assertion.object.clone(),
+                assertion.user.clone(),
+            );
+            if actual == assertion.expected {
+                report.passed += 1;
+            } else {
+                report.failures.push(AssertionFailure {
+                    index,
+                    assertion: assertion.clone(),
+                    actual,
+                });
+            }
+        }
+        report
+    }
+
+    ///
+    /// Like [`MiniZ::check_member()`], but also reports how much work the
+    /// check did
+    ///
+    pub fn check_member_instrumented(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> (bool, CheckMetrics) {
+        let mut cache = CheckCache::new(&EMPTY_CONTEXT);
+        let found = self.check_cached(rid, &object, &user, &mut cache);
+        (found, cache.metrics)
+    }
+
+    ///
+    /// Explain what could be done to give user `user` relationship `rid` with
+    /// object `object`
+    ///
+    /// If the user already has the relationship, this returns nothing.
+    /// Otherwise, this returns every (relationship, object) pair such that
+    /// making the user a direct member of that relationship on that object
+    /// would grant the user relationship `rid` with `object`.  These are
+    /// ordered from the most direct to the least direct, starting with `rid`
+    /// on `object` itself: by the number of steps from `rid` on `object` (as
+    /// in [`MiniZ::shortest_grant_path()`]), then by relationship, then by
+    /// object.
+    ///
+    pub fn explain_denied(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> Vec<GrantSuggestion<O>> {
+        if self.check_member(rid, object.clone(), user) {
+            return Vec::new();
+        }
+
+        /*
+         * This is a breadth-first search that finishes each level (sets at the
+         * same distance) before sorting it and moving on to the next.
+         */
+        let mut suggestions = Vec::new();
+        let mut visited = BTreeSet::new();
+        let mut level = vec![(rid, &object)];
+        visited.insert((rid, &object));
+        while !level.is_empty() {
+            let mut next_level = Vec::new();
+            for (rid, object) in level {
+                suggestions.push(GrantSuggestion {
+                    rid: rid.clone(),
+                    object: object.clone(),
+                });
+                for edge in self.edges(rid, object, &EMPTY_CONTEXT) {
+                    if visited.insert((edge.rid, edge.object)) {
+                        next_level.push((edge.rid, edge.object));
+                    }
+                }
+            }
+            next_level.sort();
+            level = next_level;
+        }
+
+        suggestions
+    }
+
+    ///
+    /// Check whether user `user` has relationship `rid` with object `object`,
+    /// recording everything explored along the way
+    ///
+    /// This is a debugging tool for figuring out why a check doesn't resolve
+    /// the way you'd expect.  The check explores the same sets as
+    /// [`MiniZ::check_member()`] in the same order (without using any cached
+    /// results or the shortcuts taken by a store built with
+    /// [`MiniZBuilder::build_optimized()`]) and stops as soon as it finds the
+    /// user.  Each set explored contributes a [`TraceOutcome::DirectMiss`] (or
+    /// `DirectHit`), then a step for each set it descends into (each followed
+    /// by that set's own steps), then a [`TraceOutcome::DeadEnd`] if none of
+    /// them had the user.  Sets that were already explored are not descended
+    /// into again.
+    ///
+    pub fn trace_check(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> CheckTrace<O> {
+        let mut steps = Vec::new();
+        let mut visited = BTreeSet::new();
+        visited.insert((rid, &object));
+        if self.trace_direct(rid, &object, &user, &mut steps) {
+            return CheckTrace { allowed: true, steps };
+        }
+
+        let mut stack =
+            vec![(rid, &object, self.edges(rid, &object, &EMPTY_CONTEXT))];
+        while let Some((rid, object, edges)) = stack.last_mut() {
+            let (rid, object) = (*rid, *object);
+            let edge = match edges.next() {
+                Some(edge) => edge,
+                None => {
+                    steps.push(TraceStep {
+                        rid: rid.clone(),
+                        object: object.clone(),
+                        outcome: TraceOutcome::DeadEnd,
+                    });
+                    stack.pop();
+                    continue;
+                }
+            };
+            if !visited.insert((edge.rid, edge.object)) {
+                continue;
+            }
+
+            let outcome = match edge.kind {
+                EdgeKind::Subset => {
+                    TraceOutcome::SubsetDescended { subrid: edge.rid.clone() }
+                }
+                EdgeKind::UsersetMember => TraceOutcome::UsersetDescended {
+                    group: edge.object.clone(),
+                    member_rid: edge.rid.clone(),
+                },
+                EdgeKind::Inherited { via } => {
+                    TraceOutcome::InheritedDescended {
+                        via: via.clone(),
+                        parent: edge.object.clone(),
+                        parent_rid: edge.rid.clone(),
+                    }
+                }
+            };
+            steps.push(TraceStep {
+                rid: rid.clone(),
+                object: object.clone(),
+                outcome,
+            });
+            if self.trace_direct(edge.rid, edge.object, &user, &mut steps) {
+                return CheckTrace { allowed: true, steps };
+            }
+            stack.push((
+                edge.rid,
+                edge.object,
+                self.edges(edge.rid, edge.object, &EMPTY_CONTEXT),
+            ));
+        }
+
+        CheckTrace { allowed: false, steps }
+    }
+
+    /// Records whether `user` is a direct member of `rid` on `object` for
+    /// [`MiniZ::trace_check()`], returning whether they are
+    fn trace_direct(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        steps: &mut Vec<TraceStep<O>>,
+    ) -> bool {
+        let found = self.has_direct_user(rid, object, user, &EMPTY_CONTEXT);
+        steps.push(TraceStep {
+            rid: rid.clone(),
+            object: object.clone(),
+            outcome: if found {
+                TraceOutcome::DirectHit
+            } else {
+                TraceOutcome::DirectMiss
+            },
+        });
+        found
+    }
+
+    ///
+    /// Returns the shortest chain of steps by which user `user` has
+    /// relationship `rid` with object `object`, or `None` if they don't
+    ///
+    /// The first step describes `rid` on `object` and the last is always a
+    /// [`CheckStep::Direct`] membership.  "Shortest" means the fewest steps.
+    /// If there are several shortest paths, which one is returned is
+    /// unspecified but deterministic: it depends only on the schema and
+    /// tuples, not on how the store was built or on previous queries.
+    ///
+    pub fn shortest_grant_path(
+        &self,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> Option<Vec<CheckStep<O>>> {
+        /*
+         * Do a breadth-first search, remembering how we first got to each node
+         * so that we can reconstruct the path when we find the user.
+         */
+        let mut predecessors = BTreeMap::new();
+        let mut queue = VecDeque::new();
+        let start = (rid, &object);
+        predecessors.insert(start, None);
+        queue.push_back(start);
+        while let Some(node) = queue.pop_front() {
+            let (rid, object) = node;
+            if !self.has_direct_user(rid, object, &user, &EMPTY_CONTEXT) {
+                for edge in self.edges(rid, object, &EMPTY_CONTEXT) {
+                    let next = (edge.rid, edge.object);
+                    if let Entry::Vacant(entry) = predecessors.entry(next) {
+                        entry.insert(Some((node, edge.kind)));
+                        queue.push_back(next);
+                    }
+                }
+                continue;
+            }
+
+            let mut path = vec![CheckStep::Direct {
+                rid: rid.clone(),
+                object: object.clone(),
+            }];
+            let mut next = node;
+            while let Some(Some((prev, kind))) = predecessors.get(&next) {
+                let rid = prev.0.clone();
+                let object = prev.1.clone();
+                let to_rid = next.0.clone();
+                let to_object = next.1.clone();
+                path.push(match kind {
+                    EdgeKind::Subset => {
+                        CheckStep::Subset { rid, object, subrid: to_rid }
+                    }
+                    EdgeKind::UsersetMember => CheckStep::UsersetMember {
+                        rid,
+                        object,
+                        group: to_object,
+                        member_rid: to_rid,
+                    },
+                    EdgeKind::Inherited { via } => CheckStep::Inherited {
+                        rid,
+                        object,
+                        via: (*via).clone(),
+                        parent: to_object,
+                        parent_rid: to_rid,
+                    },
+                });
+                next = *prev;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        None
+    }
+
+    ///
+    /// Returns every user having relationship `rid` with object `object`,
+    /// either directly or through a combination of implied or inherited
+    /// relationships
+    ///
+    /// The users are sorted, regardless of the order in which they're found.
+    /// These are exactly the users for which [`MiniZ::check_member()`] would
+    /// return `true`: users that are denied the relationship (see
+    /// [`RelationshipBuilder::with_deny_relation()`]) are left out.
+    ///
+    pub fn list_effective_users(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> BTreeSet<U> {
+        let mut collector = self.user_collector(rid, object, usize::MAX);
+        self.walk(rid, object, &mut collector);
+        collector.users
+    }
+
+    ///
+    /// Returns the users having both relationship `a` and relationship `b`
+    /// with object `object`
+    ///
+    /// See [`MiniZ::list_effective_users()`].
+    ///
+    pub fn relation_intersection(
+        &self,
+        a: &RelationshipId,
+        b: &RelationshipId,
+        object: &O,
+    ) -> BTreeSet<U> {
+        let users_a = self.list_effective_users(a, object);
+        let users_b = self.list_effective_users(b, object);
+        users_a.intersection(&users_b).cloned().collect()
+    }
+
+    ///
+    /// Returns the users having relationship `a` or relationship `b` (or both)
+    /// with object `object`
+    ///
+    /// See [`MiniZ::list_effective_users()`].
+    ///
+    pub fn relation_union(
+        &self,
+        a: &RelationshipId,
+        b: &RelationshipId,
+        object: &O,
+    ) -> BTreeSet<U> {
+        let mut users = self.list_effective_users(a, object);
+        users.extend(self.list_effective_users(b, object));
+        users
+    }
+
+    ///
+    /// Returns the users having relationship `a` but not relationship `b` with
+    /// object `object`
+    ///
+    /// For example, the editors of a document that aren't also its owners.
+    /// See [`MiniZ::list_effective_users()`].
+    ///
+    pub fn relation_difference(
+        &self,
+        a: &RelationshipId,
+        b: &RelationshipId,
+        object: &O,
+    ) -> BTreeSet<U> {
+        let users_a = self.list_effective_users(a, object);
+        let users_b = self.list_effective_users(b, object);
+        users_a.difference(&users_b).cloned().collect()
+    }
+
+    ///
+    /// Returns every object with which user `user` has relationship `rid`,
+    /// either directly or through a combination of implied or inherited
+    /// relationships
+    ///
+    /// This runs [`MiniZ::check_member()`] on each object known to the store
+    /// (see [`MiniZ::list_objects()`]), so it takes O(objects × check) time:
+    /// proportional to the size of the store times the cost of one check,
+    /// rather than to the number of objects found.
+    ///
+    pub fn list_objects_for_user(
+        &self,
+        rid: &RelationshipId,
+        user: &U,
+    ) -> BTreeSet<O> {
+        self.list_objects(None, usize::MAX)
+            .into_iter()
+            .filter(|object| {
+                self.check_member(rid, object.clone(), user.clone())
+            })
+            .collect()
+    }
+
+    ///
+    /// Returns the objects with which both `user_a` and `user_b` have
+    /// relationship `rid`
+    ///
+    /// For example, the documents that both alice and bob can edit.  Like
+    /// [`MiniZ::list_objects_for_user()`], this takes O(objects × check)
+    /// time: it walks every object in the store once, checking `user_a` and
+    /// then (only if that succeeds) `user_b` against each.
+    ///
+    pub fn common_objects(
+        &self,
+        rid: &RelationshipId,
+        user_a: &U,
+        user_b: &U,
+    ) -> BTreeSet<O> {
+        self.list_objects(None, usize::MAX)
+            .into_iter()
+            .filter(|object| {
+                self.check_member(rid, object.clone(), user_a.clone())
+                    && self.check_member(rid, object.clone(), user_b.clone())
+            })
+            .collect()
+    }
+
+    ///
+    /// Estimate how many users have relationship `rid` with object `object`
+    /// without necessarily finding all of them
+    ///
+    /// This does the same work as [`MiniZ::list_effective_users()`] except
+    /// that it gives up once it has found more than `limit` distinct users.
+    /// It returns `CountHint::Exact` if at most `limit` users have the
+    /// relationship and `CountHint::AtLeast(limit)` otherwise.  That's useful
+    /// for showing "shared with 5 people" vs. "shared with 100+" without
+    /// enumerating everyone that a widely-shared object is shared with.
+    ///
+    /// The caller supplies `limit` because it's the caller that knows where
+    /// the count stops mattering (e.g., the "100" in "100+").  Any fixed
+    /// threshold would be either too small to answer some callers' questions
+    /// or larger than others need, making them pay for finding users whose
+    /// count they'd never show.
+    ///
+    pub fn effective_user_count_hint(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        limit: usize,
+    ) -> CountHint {
+        /*
+         * Finding exactly `limit` users doesn't tell us whether there are
+         * more, so look for one more than that before giving up.
+         */
+        let mut collector =
+            self.user_collector(rid, object, limit.saturating_add(1));
+        if self.walk(rid, object, &mut collector) {
+            CountHint::Exact(collector.users.len())
+        } else {
+            CountHint::AtLeast(limit)
+        }
+    }
+
+    ///
+    /// Returns up to `n` of the users having relationship `rid` with object
+    /// `object`
+    ///
+    /// This is for showing a few example users (e.g., "shared with alice, bob,
+    /// and 40 others") without enumerating everyone: like
+    /// [`MiniZ::effective_user_count_hint()`], it gives up once it has found
+    /// `n` distinct users.  The users returned are some of the ones that
+    /// [`MiniZ::list_effective_users()`] would return, in sorted order, but
+    /// which ones is unspecified.
+    ///
+    pub fn sample_effective_users(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        n: usize,
+    ) -> Vec<U> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let mut collector = self.user_collector(rid, object, n);
+        self.walk(rid, object, &mut collector);
+        collector.users.into_iter().collect()
+    }
+
+    /// Returns a [`UserCollector`] for the users having relationship `rid`
+    /// with object `object` that stops once it has found `limit` of them
+    fn user_collector<'a>(
+        &'a self,
+        rid: &'a RelationshipId,
+        object: &'a O,
+        limit: usize,
+    ) -> UserCollector<'a, O, U> {
+        /*
+         * A walk doesn't apply deny relationships.  If there are any, each
+         * user the walk finds has to be confirmed with a check.
+         */
+        let any_denials =
+            self.sets.values().any(|set| !set.deny_relationships.is_empty());
+        let confirm = any_denials.then_some((self, rid, object));
+        UserCollector {
+            users: BTreeSet::new(),
+            rejected: BTreeSet::new(),
+            limit,
+            confirm,
+        }
+    }
+
+    ///
+    /// Returns the tree of sets that make up relationship `rid` on object
+    /// `object`
+    ///
+    /// This is Zanzibar's "Expand" operation.  Each set appears in the tree at
+    /// most once (where a check would first visit it), so this terminates even
+    /// if relationships form a cycle.
+    ///
+    pub fn expand(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> UsersetTree<O, U> {
+        /*
+         * Like walk(), this uses an explicit stack rather than recursion.
+         * Each entry on the stack is a tree we're still building, together
+         * with the edges out of its set that we haven't followed yet.
+         */
+        let mut visited = BTreeSet::new();
+        visited.insert((rid, object));
+        let mut stack = vec![(
+            self.expand_node(rid, object),
+            self.edges(rid, object, &EMPTY_CONTEXT),
+        )];
+        loop {
+            let (_, edges) = stack.last_mut().unwrap();
+            match edges.next() {
+                Some(edge) => {
+                    if visited.insert((edge.rid, edge.object)) {
+                        stack.push((
+                            self.expand_node(edge.rid, edge.object),
+                            self.edges(edge.rid, edge.object, &EMPTY_CONTEXT),
+                        ));
+                    }
+                }
+                None => {
+                    let (tree, _) = stack.pop().unwrap();
+                    match stack.last_mut() {
+                        Some((parent, _)) => parent.children.push(tree),
+                        None => return tree,
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the tree for relationship `rid` on object `object` with its
+    /// direct members filled in, but no children yet
+    fn expand_node(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> UsersetTree<O, U> {
+        let set = self.sets.get(rid).expect("no such set");
+        let members = set
+            .direct_members
+            .get(object)
+            .into_iter()
+            .flatten()
+            .filter(|member| {
+                let caveat = set.caveat_for(object, member);
+                self.caveat_satisfied(caveat, &EMPTY_CONTEXT)
+            })
+            .cloned()
+            .collect();
+        UsersetTree {
+            rid: rid.clone(),
+            object: object.clone(),
+            members,
+            children: Vec::new(),
+        }
+    }
+
+    ///
+    /// Walk the sets that make up relationship `rid` on object `object`,
+    /// invoking `visitor`'s callbacks along the way
+    ///
+    /// This is the same depth-first traversal that [`MiniZ::check_member()`]
+    /// does, except that it reports everything it finds instead of looking
+    /// for a particular user.  For each set, the visitor first sees its direct
+    /// members (in sorted order) and then each set that it descends into (in
+    /// a fixed order that depends only on the schema and tuples).  Each set is
+    /// descended into at most once, so this terminates even if relationships
+    /// form a cycle.
+    ///
+    /// Returns `false` if the visitor stopped the walk early and `true`
+    /// otherwise.
+    ///
+    pub fn walk<V: CheckVisitor<O, U>>(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        visitor: &mut V,
+    ) -> bool {
+        /*
+         * Like resolve(), this uses an explicit stack of the sets we're still
+         * working on and the edges out of them we haven't followed yet.
+         */
+        let mut visited = BTreeSet::new();
+        visited.insert((rid, object));
+        if !self.walk_direct_members(rid, object, visitor) {
+            return false;
+        }
+        let mut stack =
+            vec![(rid, object, self.edges(rid, object, &EMPTY_CONTEXT))];
+        while let Some((rid, object, edges)) = stack.last_mut() {
+            let (rid, object) = (*rid, *object);
+            let edge = match edges.next() {
+                Some(edge) => edge,
+                None => {
+                    stack.pop();
+                    continue;
+                }
+            };
+            if visited.contains(&(edge.rid, edge.object)) {
+                continue;
+            }
+
+            let action = match edge.kind {
+                EdgeKind::Subset => visitor.enter_subset(rid, object, edge.rid),
+                EdgeKind::UsersetMember => visitor.enter_userset_member(
+                    rid,
+                    object,
+                    edge.object,
+                    edge.rid,
+                ),
+                EdgeKind::Inherited { via } => visitor.enter_inherited(
+                    rid,
+                    object,
+                    via,
+                    edge.object,
+                    edge.rid,
+                ),
+            };
+            match action {
+                WalkAction::Continue => {
+                    visited.insert((edge.rid, edge.object));
+                    if !self.walk_direct_members(edge.rid, edge.object, visitor)
+                    {
+                        return false;
+                    }
+                    stack.push((
+                        edge.rid,
+                        edge.object,
+                        self.edges(edge.rid, edge.object, &EMPTY_CONTEXT),
+                    ));
+                }
+                WalkAction::Skip => (),
+                WalkAction::Stop => return false,
+            }
+        }
+
+        true
+    }
+
+    /// Shows `visitor` the direct members of `rid` on `object`, returning
+    /// `false` if it stops the walk
+    fn walk_direct_members<V: CheckVisitor<O, U>>(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        visitor: &mut V,
+    ) -> bool {
+        let set = self.sets.get(rid).expect("no such set");
+        set.direct_members
+            .get(object)
+            .into_iter()
+            .flatten()
+            .filter(|member| {
+                let caveat = set.caveat_for(object, member);
+                self.caveat_satisfied(caveat, &EMPTY_CONTEXT)
+            })
+            .all(|member| {
+                visitor.visit_direct_member(rid, object, member)
+                    != WalkAction::Stop
+            })
+    }
+
+    ///
+    /// Check whether the user `user` has _any_ of the relationships in `rids`
+    /// with object `object`
+    ///
+    /// This stops at the first relationship that the user has.  Work done
+    /// resolving one relationship is reused when resolving the others.
+    ///
+    pub fn check_any(
+        &self,
+        rids: &[RelationshipId],
+        object: &O,
+        user: &U,
+    ) -> bool {
+        if rids
+            .iter()
+            .any(|rid| self.check_member_fast(rid, object, user).is_some())
+        {
+            return true;
+        }
+        let mut cache = CheckCache::new(&EMPTY_CONTEXT);
+        rids.iter().any(|rid| self.check_cached(rid, object, user, &mut cache))
+    }
+
+    ///
+    /// Check whether the user `user` has _all_ of the relationships in `rids`
+    /// with object `object`
+    ///
+    /// This stops at the first relationship that the user does not have.  Work
+    /// done resolving one relationship is reused when resolving the others.
+    ///
+    pub fn check_all(
+        &self,
+        rids: &[RelationshipId],
+        object: &O,
+        user: &U,
+    ) -> bool {
+        let mut cache = CheckCache::new(&EMPTY_CONTEXT);
+        rids.iter().all(|rid| self.check_cached(rid, object, user, &mut cache))
+    }
+
+    ///
+    /// Check whether the user `user` has each of the relationships in `rids`
+    /// with each of the objects in `objects`
+    ///
+    /// The result has one row per relationship, in the order of `rids`, and
+    /// each row has one entry per object, in the order of `objects`: the
+    /// answer for `rids[i]` and `objects[j]` is `result[i][j]`.  This is
+    /// handy for rendering a table of what a user can do.  Work done for one
+    /// cell is reused for the others (e.g., finding that the user is a
+    /// viewer of a shared parent object).
+    ///
+    pub fn check_matrix(
+        &self,
+        rids: &[RelationshipId],
+        objects: &[O],
+        user: &U,
+    ) -> Vec<Vec<bool>> {
+        let mut cache = CheckCache::new(&EMPTY_CONTEXT);
+        rids.iter()
+            .map(|rid| {
+                objects
+                    .iter()
+                    .map(|object| {
+                        self.check_cached(rid, object, user, &mut cache)
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Check one relationship, recording what we learn in `cache`
+    fn check_cached(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        cache: &mut CheckCache<'_, O>,
+    ) -> bool {
+        cache.visited.clear();
+        let found = self.resolve(rid, object, user, cache);
+        if !found {
+            /*
+             * The user was not reachable from anything we visited, so none of
+             * those relationships include the user.
+             */
+            let visited = core::mem::take(&mut cache.visited);
+            for node in visited {
+                cache.results.insert(node, false);
+            }
+        }
+        found
+    }
+
+    ///
+    /// Returns whether `user` has relationship `rid` with object `object`
+    ///
+    /// This is a depth-first search of the graph described by
+    /// [`MiniZ::edges()`].  It uses an explicit stack rather than recursion so
+    /// that very deep hierarchies (e.g., thousands of nested directories)
+    /// don't overflow the call stack.
+    ///
+    fn resolve(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        cache: &mut CheckCache<'_, O>,
+    ) -> bool {
+        if let Some(found) = self.resolve_start(rid, object, user, cache) {
+            return found;
+        }
+
+        /*
+         * Each entry on the stack is a set that we're still working on,
+         * together with the edges out of it that we haven't followed yet.  The
+         * user isn't a direct member of any of them.
+         */
+        let context = cache.context;
+        let mut stack = vec![(
+            rid,
+            object,
+            self.resolve_edges(rid, object, context, false),
+        )];
+        while let Some((_, _, edges)) = stack.last_mut() {
+            let edge = match edges.next() {
+                Some(edge) => edge,
+                None => {
+                    /*
+                     * The user isn't a member of this set through any of its
+                     * edges, either.
+                     */
+                    stack.pop();
+                    cache.depth -= 1;
+                    continue;
+                }
+            };
+
+            match edge.kind {
+                EdgeKind::Subset => cache.metrics.subset_descents += 1,
+                EdgeKind::UsersetMember => cache.metrics.userset_descents += 1,
+                EdgeKind::Inherited { .. } => {
+                    cache.metrics.inherited_descents += 1
+                }
+            }
+
+            match self.resolve_start(edge.rid, edge.object, user, cache) {
+                Some(false) => (),
+                Some(true) => {
+                    /*
+                     * The user is a member of every set on the stack by way of
+                     * this one.
+                     */
+                    for (rid, object, _) in stack {
+                        cache.depth -= 1;
+                        cache
+                            .results
+                            .insert((rid.clone(), object.clone()), true);
+                    }
+                    return true;
+                }
+                None => stack.push((
+                    edge.rid,
+                    edge.object,
+                    self.resolve_edges(
+                        edge.rid,
+                        edge.object,
+                        context,
+                        matches!(edge.kind, EdgeKind::Subset),
+                    ),
+                )),
+            }
+        }
+
+        false
+    }
+
+    ///
+    /// Returns the edges that [`MiniZ::resolve()`] needs to follow out of the
+    /// set for relationship `rid` on object `object`
+    ///
+    /// This is usually the same as [`MiniZ::edges()`].  But if we've
+    /// precomputed every set contained (directly or not) in each set, then
+    /// we follow edges to all of those instead of just the direct subsets.
+    /// And if we got here from a set containing this one, then that set's
+    /// edges already include every set contained in this one, so we skip them
+    /// here.  (The exception is a set with deny relationships, past which the
+    /// closures don't extend.)
+    ///
+    /// Only checks take these shortcuts.  Everything that reports the path it
+    /// took (like [`MiniZ::walk()`]) uses [`MiniZ::edges()`], so that what it
+    /// reports doesn't depend on how the store was built.
+    ///
+    fn resolve_edges<'a>(
+        &'a self,
+        rid: &'a RelationshipId,
+        object: &'a O,
+        context: &'a Context,
+        via_subset: bool,
+    ) -> impl Iterator<Item = Edge<'a, O>> + 'a {
+        let set = self.sets.get(rid).expect("no such set");
+        let skip_subsets = via_subset
+            && self.subset_closures.is_some()
+            && set.deny_relationships.is_empty();
+        let subrids = match &self.subset_closures {
+            Some(closures) => &closures[rid],
+            None => &set.contained_relationships,
+        };
+        self.edges_with_subsets(rid, object, context, subrids).filter(
+            move |edge| {
+                !(skip_subsets && matches!(edge.kind, EdgeKind::Subset))
+            },
+        )
+    }
+
+    ///
+    /// Begin resolving relationship `rid` on object `object` for `user`
+    ///
+    /// Returns the answer if it can be determined without following any edges
+    /// out of this set.  Otherwise, returns `None`, and the caller is
+    /// responsible for following the edges (and decrementing `cache.depth`
+    /// when it's done).
+    ///
+    fn resolve_start(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        cache: &mut CheckCache<'_, O>,
+    ) -> Option<bool> {
+        let node = (rid.clone(), object.clone());
+        if let Some(found) = cache.results.get(&node) {
+            return Some(*found);
+        }
+
+        /*
+         * If we've already visited this node while answering this query,
+         * either we're still working on it (because there's a cycle) or we
+         * already found it doesn't include the user.  Either way, there's
+         * nothing more to learn here.
+         */
+        if !cache.visited.insert(node.clone()) {
+            return Some(false);
+        }
+
+        cache.depth += 1;
+        cache.metrics.max_depth = cache.metrics.max_depth.max(cache.depth);
+
+        /*
+         * A user in any of this set's deny relationships isn't in this set, no
+         * matter what else we'd find.
+         */
+        if cache.apply_denials && self.is_denied(rid, object, user, cache) {
+            cache.depth -= 1;
+            cache.results.insert(node, false);
+            return Some(false);
+        }
+
+        /*
+         * First, check if the user is a direct member of this set.  If not,
+         * the caller will check whether the user is a member (directly or
+         * otherwise) of any of the sets whose members are included in this
+         * one.
+         */
+        cache.metrics.direct_member_lookups += 1;
+        if self.has_direct_user(rid, object, user, cache.context) {
+            cache.depth -= 1;
+            cache.results.insert(node, true);
+            return Some(true);
+        }
+
+        None
+    }
+
+    /// Returns whether `user` has any of the deny relationships of `rid` with
+    /// `object`
+    fn is_denied(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        cache: &mut CheckCache<'_, O>,
+    ) -> bool {
+        let set = self.sets.get(rid).expect("no such set");
+        set.deny_relationships.iter().any(|deny| {
+            let mut deny_cache = CheckCache::new(cache.context);
+            deny_cache.apply_denials = false;
+            let denied = self.check_cached(deny, object, user, &mut deny_cache);
+            cache.denial_dependencies.extend(deny_cache.dependencies());
+            denied
+        })
+    }
+
+    /// Returns whether `user` is a direct member of `rid` for `object` by way
+    /// of a tuple that counts in `context`
+    fn has_direct_user(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+        user: &U,
+        context: &Context,
+    ) -> bool {
+        let set = self.sets.get(rid).expect("no such set");
+        let member = Member::User(user.clone());
+        match set.direct_members.get(object) {
+            Some(members) if members.contains(&member) => {
+                self.caveat_satisfied(set.caveat_for(object, &member), context)
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns whether a tuple with caveat `caveat` (if any) counts in
+    /// `context`
+    fn caveat_satisfied(
+        &self,
+        caveat: Option<&CaveatId>,
+        context: &Context,
+    ) -> bool {
+        match caveat {
+            None => true,
+            Some(caveat_id) => self.caveats[caveat_id].evaluate(context),
+        }
+    }
+
+    ///
+    /// Returns the edges out of the set for relationship `rid` on object
+    /// `object`
+    ///
+    /// Resolving a relationship for an object amounts to walking a graph whose
+    /// nodes are (relationship, object) pairs.  A user has relationship R with
+    /// object O if they're a direct member of R for O or if they have the
+    /// relationship described by any edge out of (R, O).  Edges that exist
+    /// only because of a caveated tuple are included only if `context`
+    /// satisfies the caveat.
+    ///
+    /// Edges come in a fixed order: subsets sorted by relationship id, then
+    /// object members followed as usersets sorted by object, then
+    /// [`Member::ObjectRelation`] members sorted by object and relationship,
+    /// then inherited relationships sorted by the relationship and object
+    /// through which they're inherited.
+    ///
+    fn edges<'a>(
+        &'a self,
+        rid: &'a RelationshipId,
+        object: &'a O,
+        context: &'a Context,
+    ) -> impl Iterator<Item = Edge<'a, O>> + 'a {
+        let set = self.sets.get(rid).expect("no such set");
+        self.edges_with_subsets(
+            rid,
+            object,
+            context,
+            &set.contained_relationships,
+        )
+    }
+
+    /// Like [`MiniZ::edges()`], but with subset edges to `subrids` instead of
+    /// this relationship's direct subsets
+    fn edges_with_subsets<'a>(
+        &'a self,
+        rid: &'a RelationshipId,
+        object: &'a O,
+        context: &'a Context,
+        subrids: &'a BTreeSet<RelationshipId>,
+    ) -> impl Iterator<Item = Edge<'a, O>> + 'a {
+        let set = self.sets.get(rid).expect("no such set");
+        let direct_members = set.direct_members.get(object);
+
+        /*
+         * A direct-only relationship has no edges out of it at all, since only
+         * its direct members count.
+         */
+        let follow = !set.direct_only;
+
+        /* Sets contained in this set. */
+        let subsets =
+            subrids.iter().filter(move |_| follow).map(move |subrid| Edge {
+                kind: EdgeKind::Subset,
+                rid: subrid,
+                object,
+            });
+
+        /*
+         * If this relationship is configured to follow object members as
+         * usersets, each object member.
+         */
+        let usersets = direct_members
+            .filter(|_| follow && set.userset_member_relation.is_some())
+            .into_iter()
+            .flatten()
+            .take_while(|member| {
+                !matches!(member, Member::ObjectRelation { .. })
+            })
+            .filter_map(move |member| {
+                let target = set.userset_edge(member)?;
+                self.caveat_satisfied(set.caveat_for(object, member), context)
+                    .then_some(target)
+            })
+            .map(|(rid, object)| Edge {
+                kind: EdgeKind::UsersetMember,
+                rid,
+                object,
+            });
+
+        /*
+         * Each member that's explicitly a userset.  These sort after every
+         * other kind of member, so we find them from the end rather than
+         * looking at every member.
+         */
+        let userset_subjects = direct_members
+            .filter(|_| follow)
+            .into_iter()
+            .flat_map(|members| {
+                let mut subjects: Vec<_> = members
+                    .iter()
+                    .rev()
+                    .take_while(|member| {
+                        matches!(member, Member::ObjectRelation { .. })
+                    })
+                    .collect();
+                subjects.reverse();
+                subjects
+            })
+            .filter_map(move |member| {
+                let target = set.userset_edge(member)?;
+                self.caveat_satisfied(set.caveat_for(object, member), context)
+                    .then_some(target)
+            })
+            .map(|(rid, object)| Edge {
+                kind: EdgeKind::UsersetMember,
+                rid,
+                object,
+            });
+
+        /*
+         * This is more expensive: every object O2 such that this object
+         * inherits O2's relationships.
+         */
+        let inherited = follow
+            .then(|| self.inherited_candidates(set, object))
+            .into_iter()
+            .flatten()
+            .filter(move |m| {
+                let via_set = &self.sets[&m.rid];
+                via_set.caveated_members.is_empty()
+                    || self.caveat_satisfied(
+                        via_set.caveat_for(
+                            &m.object,
+                            &Member::Object(object.clone()),
+                        ),
+                        context,
+                    )
+            })
+            .flat_map(move |m| {
+                set.inherited_edges(&m.rid).map(move |parent_rid| Edge {
+                    kind: EdgeKind::Inherited { via: &m.rid },
+                    rid: parent_rid,
+                    object: &m.object,
+                })
+            });
+
+        subsets.chain(usersets).chain(userset_subjects).chain(inherited)
+    }
+
+    ///
+    /// Returns the direct memberships of `object` that might be in
+    /// relationships that `set` inherits through
+    ///
+    /// Without the inherited index (see [`MiniZ::enable_inherited_index()`]),
+    /// that's all of them.  With it, it's exactly the ones in those
+    /// relationships.  Either way, they come out in the same order.
+    ///
+    fn inherited_candidates<'a>(
+        &'a self,
+        set: &'a Relationship<O, U>,
+        object: &O,
+    ) -> impl Iterator<Item = &'a Membership<O>> + 'a {
+        let memberships = self.memberships.get(&Member::Object(object.clone()));
+
+        /*
+         * The reverse index is sorted by relationship, so the memberships in
+         * each relationship are contiguous.  Each range starts at a given
+         * membership and continues for as long as the relationship matches,
+         * or (without the index) covers everything.
+         */
+        let ranges: Vec<(Bound<Membership<O>>, Option<RelationshipId>)> =
+            match memberships.and_then(|memberships| {
+                self.inherited_index.group_starts(
+                    object,
+                    memberships,
+                    set.inherited_relationships.keys(),
+                )
+            }) {
+                Some(starts) => starts
+                    .into_iter()
+                    .map(|start| {
+                        let rid = start.rid.clone();
+                        (Bound::Included(start), Some(rid))
+                    })
+                    .collect(),
+                None => vec![(Bound::Unbounded, None)],
+            };
+        ranges.into_iter().flat_map(move |(start, rid)| {
+            memberships
+                .into_iter()
+                .flat_map(move |memberships| {
+                    memberships.range((start.clone(), Bound::Unbounded))
+                })
+                .take_while(move |m| rid.as_ref().is_none_or(|r| m.rid == *r))
+        })
+    }
+}
+
+///
+/// Describes one edge in the graph walked when resolving a relationship
+///
+/// See [`MiniZ::edges()`].  `rid` and `object` identify the node that the edge
+/// points to.
+///
+struct Edge<'a, O> {
+    kind: EdgeKind<'a>,
+    rid: &'a RelationshipId,
+    object: &'a O,
+}
+
+/// Describes why an [`Edge`] exists
+#[derive(Clone, Copy)]
+enum EdgeKind<'a> {
+    /// the target relationship is a subset of the source relationship on the
+    /// same object
+    Subset,
+    /// the target object is a direct member of the source relationship, which
+    /// follows object members as usersets of the target relationship, or the
+    /// target (relationship, object) is a direct [`Member::ObjectRelation`]
+    /// member of the source relationship
+    UsersetMember,
+    /// the source object has relationship `via` with the target object, and
+    /// the source relationship inherits the target relationship through `via`
+    Inherited { via: &'a RelationshipId },
+}
+
+///
+/// [`CheckVisitor`] that collects the distinct users it finds, stopping once
+/// it has found `limit` of them
+///
+/// See `MiniZ::user_collector()`.
+///
+struct UserCollector<'a, O, U> {
+    users: BTreeSet<U>,
+    /// users found that a check said don't have the relationship
+    rejected: BTreeSet<U>,
+    limit: usize,
+    /// store, relationship, and object to check each user against before
+    /// collecting it, if any
+    confirm: Option<(&'a MiniZ<O, U>, &'a RelationshipId, &'a O)>,
+}
+
+impl<O, U> CheckVisitor<O, U> for UserCollector<'_, O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    fn visit_direct_member(
+        &mut self,
+        _rid: &RelationshipId,
+        _object: &O,
+        member: &Member<O, U>,
+    ) -> WalkAction {
+        if let Member::User(user) = member {
+            if self.users.contains(user) || self.rejected.contains(user) {
+                return WalkAction::Continue;
+            }
+            if let Some((miniz, rid, object)) = self.confirm {
+                if !miniz.check_member(rid, object.clone(), user.clone()) {
+                    self.rejected.insert(user.clone());
+                    return WalkAction::Continue;
+                }
+            }
+            self.users.insert(user.clone());
+            if self.users.len() >= self.limit {
+                return WalkAction::Stop;
+            }
+        }
+        WalkAction::Continue
+    }
+}
+
+/// Identifies a check: (relationship, object, user)
+type CheckKey<O, U> = (RelationshipId, O, U);
+
+/// A remembered check
+#[derive(Clone, Copy)]
+struct CheckEntry {
+    /// result of the check
+    found: bool,
+    /// whether the store has changed in a way that could affect the result
+    /// since it was computed
+    stale: bool,
+}
+
+///
+/// Results of checks remembered across calls to [`MiniZ::check_member()`]
+///
+/// See `MiniZ::enable_check_cache()`.  Without `std`, this never remembers
+/// anything.
+///
+struct CheckResultCache<O, U> {
+    #[cfg(any(feature = "std", test))]
+    results: Option<std::sync::Mutex<CheckResults<O, U>>>,
+    #[cfg(not(any(feature = "std", test)))]
+    results: core::marker::PhantomData<(O, U)>,
+}
+
+#[cfg(any(feature = "std", test))]
+impl<O: Clone + Ord, U: Clone + Ord> Clone for CheckResultCache<O, U> {
+    fn clone(&self) -> Self {
+        CheckResultCache {
+            results: self.results.as_ref().map(|results| {
+                std::sync::Mutex::new(results.lock().unwrap().clone())
+            }),
+        }
+    }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+impl<O, U> Clone for CheckResultCache<O, U> {
+    fn clone(&self) -> Self {
+        CheckResultCache::new()
+    }
+}
+
+/// Contents of an enabled [`CheckResultCache`]
+#[cfg(any(feature = "std", test))]
+#[derive(Clone)]
+struct CheckResults<O, U> {
+    entries: BTreeMap<CheckKey<O, U>, CheckEntry>,
+    /// for each (relationship, object) pair, the checks whose results depend
+    /// on it
+    ///
+    /// This may also list checks that have since been recomputed without
+    /// visiting the pair, which just means they'll be invalidated
+    /// unnecessarily.
+    dependents: BTreeMap<(RelationshipId, O), BTreeSet<CheckKey<O, U>>>,
+}
+
+#[cfg(any(feature = "std", test))]
+impl<O: Ord, U: Ord> CheckResults<O, U> {
+    fn new() -> CheckResults<O, U> {
+        CheckResults { entries: BTreeMap::new(), dependents: BTreeMap::new() }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl<O: Clone + Ord, U: Clone + Ord> CheckResultCache<O, U> {
+    fn new() -> CheckResultCache<O, U> {
+        CheckResultCache { results: None }
+    }
+
+    fn enabled(&self) -> bool {
+        self.results.is_some()
+    }
+
+    fn get(&self, key: &CheckKey<O, U>) -> Option<CheckEntry> {
+        let results = self.results.as_ref()?;
+        let results = results.lock().unwrap();
+        results.entries.get(key).copied()
+    }
+
+    fn insert(
+        &self,
+        key: CheckKey<O, U>,
+        found: bool,
+        dependencies: BTreeSet<(RelationshipId, O)>,
+    ) {
+        if let Some(results) = &self.results {
+            let mut results = results.lock().unwrap();
+            for node in dependencies {
+                results.dependents.entry(node).or_default().insert(key.clone());
+            }
+            results.entries.insert(key, CheckEntry { found, stale: false });
+        }
+    }
+
+    /// Mark stale every result that depends on any of `nodes`
+    fn invalidate(&mut self, nodes: Vec<(RelationshipId, O)>) {
+        if let Some(results) = &mut self.results {
+            let results = results.get_mut().unwrap();
+            for node in nodes {
+                for key in
+                    results.dependents.remove(&node).into_iter().flatten()
+                {
+                    if let Some(entry) = results.entries.get_mut(&key) {
+                        entry.stale = true;
+                    }
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        if let Some(results) = &mut self.results {
+            *results.get_mut().unwrap() = CheckResults::new();
+        }
+    }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+impl<O, U> CheckResultCache<O, U> {
+    fn new() -> CheckResultCache<O, U> {
+        CheckResultCache { results: core::marker::PhantomData }
+    }
+
+    fn enabled(&self) -> bool {
+        false
+    }
+
+    fn get(&self, _key: &CheckKey<O, U>) -> Option<CheckEntry> {
+        None
+    }
+
+    fn insert(
+        &self,
+        _key: CheckKey<O, U>,
+        _found: bool,
+        _dependencies: BTreeSet<(RelationshipId, O)>,
+    ) {
+    }
+
+    fn invalidate(&mut self, _nodes: Vec<(RelationshipId, O)>) {}
+
+    fn clear(&mut self) {}
+}
+
+///
+/// For each object, the first of its direct memberships in each relationship,
+/// if enabled (see [`MiniZ::enable_inherited_index()`])
+///
+/// Entries are computed when a check first needs them and removed when the
+/// object's memberships change.  Like [`CheckResultCache`], this needs a lock
+/// to be updated during a check, so without `std` it's never enabled.
+///
+struct InheritedIndex<O> {
+    #[cfg(any(feature = "std", test))]
+    starts: Option<std::sync::Mutex<MembershipStarts<O>>>,
+    #[cfg(not(any(feature = "std", test)))]
+    starts: core::marker::PhantomData<O>,
+}
+
+/// Contents of an enabled [`InheritedIndex`]
+#[cfg(any(feature = "std", test))]
+type MembershipStarts<O> = BTreeMap<O, BTreeMap<RelationshipId, Membership<O>>>;
+
+#[cfg(any(feature = "std", test))]
+impl<O: Clone + Ord> Clone for InheritedIndex<O> {
+    fn clone(&self) -> Self {
+        InheritedIndex {
+            starts: self.starts.as_ref().map(|starts| {
+                std::sync::Mutex::new(starts.lock().unwrap().clone())
+            }),
+        }
+    }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+impl<O> Clone for InheritedIndex<O> {
+    fn clone(&self) -> Self {
+        InheritedIndex::new()
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl<O: Clone + Ord> InheritedIndex<O> {
+    fn new() -> InheritedIndex<O> {
+        InheritedIndex { starts: None }
+    }
+
+    ///
+    /// Returns the first of `object`'s memberships (which are `memberships`)
+    /// in each of the relationships `rids`, skipping relationships that it
+    /// has no memberships in, or `None` if the index is not enabled
+    ///
+    fn group_starts<'r>(
+        &self,
+        object: &O,
+        memberships: &BTreeSet<Membership<O>>,
+        rids: impl Iterator<Item = &'r RelationshipId>,
+    ) -> Option<Vec<Membership<O>>> {
+        let starts = self.starts.as_ref()?;
+        let mut starts = starts.lock().unwrap();
+        let object_starts = starts.entry(object.clone()).or_insert_with(|| {
+            let mut object_starts = BTreeMap::new();
+            for membership in memberships {
+                object_starts
+                    .entry(membership.rid.clone())
+                    .or_insert_with(|| membership.clone());
+            }
+            object_starts
+        });
+        Some(rids.filter_map(|rid| object_starts.get(rid).cloned()).collect())
+    }
+
+    /// Forget what's known about `object`'s memberships
+    fn invalidate(&mut self, object: &O) {
+        if let Some(starts) = &mut self.starts {
+            starts.get_mut().unwrap().remove(object);
+        }
+    }
+
+    fn clear(&mut self) {
+        if let Some(starts) = &mut self.starts {
+            starts.get_mut().unwrap().clear();
+        }
+    }
+}
+
+#[cfg(not(any(feature = "std", test)))]
+impl<O> InheritedIndex<O> {
+    fn new() -> InheritedIndex<O> {
+        InheritedIndex { starts: core::marker::PhantomData }
+    }
+
+    fn group_starts<'r>(
+        &self,
+        _object: &O,
+        _memberships: &BTreeSet<Membership<O>>,
+        _rids: impl Iterator<Item = &'r RelationshipId>,
+    ) -> Option<Vec<Membership<O>>> {
+        None
+    }
+
+    fn invalidate(&mut self, _object: &O) {}
+
+    fn clear(&mut self) {}
+}
+
+///
+/// For each relationship, every relationship reachable from it in the graph of
+/// relationship definitions (see [`MiniZ::relation_reachable()`])
+///
+/// A relationship appears in its own closure only if it's part of a cycle.
+/// Adding an edge can only make more relationships reachable, so the closures
+/// are extended in place.  Removing one can disconnect relationships that were
+/// reachable only through it, so every closure that might have used it is
+/// recomputed from scratch.
+///
+#[derive(Clone, Debug)]
+struct ReachabilityCache {
+    closures: BTreeMap<RelationshipId, BTreeSet<RelationshipId>>,
+}
+
+impl ReachabilityCache {
+    fn new<O, U>(
+        sets: &BTreeMap<RelationshipId, Relationship<O, U>>,
+    ) -> ReachabilityCache {
+        ReachabilityCache {
+            closures: sets
+                .keys()
+                .map(|rid| (rid.clone(), reachable_from(sets, rid)))
+                .collect(),
+        }
+    }
+
+    fn reachable(&self, from: &RelationshipId, to: &RelationshipId) -> bool {
+        from == to || self.closures.get(from).expect("no such set").contains(to)
+    }
+
+    /// Update the closures after adding an edge from `from` to `to`
+    fn edge_added(&mut self, from: &RelationshipId, to: &RelationshipId) {
+        /*
+         * Whatever could reach `from` can now reach `to` and everything it
+         * reaches.
+         */
+        let mut newly_reachable = self.closures[to].clone();
+        newly_reachable.insert(to.clone());
+        for (rid, closure) in self.closures.iter_mut() {
+            if rid == from || closure.contains(from) {
+                closure.extend(newly_reachable.iter().cloned());
+            }
+        }
+    }
+
+    /// Update the closures after removing any number of edges from `from`
+    fn edges_removed<O, U>(
+        &mut self,
+        sets: &BTreeMap<RelationshipId, Relationship<O, U>>,
+        from: &RelationshipId,
+    ) {
+        for (rid, closure) in self.closures.iter_mut() {
+            if rid == from || closure.contains(from) {
+                *closure = reachable_from(sets, rid);
+            }
+        }
+    }
+}
+
+/// Returns every relationship reachable from `rid` by following at least one
+/// edge
+fn reachable_from<O, U>(
+    sets: &BTreeMap<RelationshipId, Relationship<O, U>>,
+    rid: &RelationshipId,
+) -> BTreeSet<RelationshipId> {
+    let mut closure = BTreeSet::new();
+    let mut stack = vec![rid];
+    while let Some(rid) = stack.pop() {
+        for next in sets.get(rid).expect("no such set").references() {
+            if closure.insert(next.clone()) {
+                stack.push(next);
+            }
+        }
+    }
+    closure
+}
+
+///
+/// Memoized results of resolving relationships for one particular user
+///
+/// This lets several related checks for the same user (e.g., with
+/// [`MiniZ::check_any()`]) share work.
+///
+struct CheckCache<'c, O> {
+    /// context against which to evaluate caveats
+    context: &'c Context,
+    /// whether to apply deny relationships (see
+    /// `RelationshipBuilder::with_deny_relation()`)
+    apply_denials: bool,
+    /// (relationship, object) pairs known to include (`true`) or not include
+    /// (`false`) the user
+    results: BTreeMap<(RelationshipId, O), bool>,
+    /// (relationship, object) pairs visited while answering the current query
+    visited: BTreeSet<(RelationshipId, O)>,
+    /// (relationship, object) pairs visited while checking deny relationships,
+    /// which use their own `CheckCache`
+    denial_dependencies: BTreeSet<(RelationshipId, O)>,
+    /// work done so far
+    metrics: CheckMetrics,
+    /// number of sets currently being resolved
+    depth: usize,
+}
+
+impl<'c, O: Ord> CheckCache<'c, O> {
+    fn new(context: &'c Context) -> CheckCache<'c, O> {
+        CheckCache {
+            context,
+            apply_denials: true,
+            results: BTreeMap::new(),
+            visited: BTreeSet::new(),
+            denial_dependencies: BTreeSet::new(),
+            metrics: CheckMetrics::default(),
+            depth: 0,
+        }
+    }
+
+    /// Returns every (relationship, object) pair that the results so far
+    /// depend on
+    fn dependencies(self) -> BTreeSet<(RelationshipId, O)> {
+        let mut dependencies = self.denial_dependencies;
+        dependencies.extend(self.results.into_keys());
+        dependencies.extend(self.visited);
+        dependencies
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::binary::BinaryId;
+    use super::binary::MAX_CAVEAT_DEPTH;
+    use super::intern::Interner;
+    use super::jsonl::JsonId;
+    use super::schema::ParseError;
+    use super::source::AsyncTupleSource;
+    use super::Assertion;
+    use super::AssertionFailure;
+    use super::AssertionReport;
+    use super::BuildError;
+    use super::Caveat;
+    use super::CaveatId;
+    use super::CheckError;
+    use super::CheckMetrics;
+    use super::CheckResult;
+    use super::CheckStep;
+    use super::CheckTrace;
+    use super::CheckVisitor;
+    use super::Consistency;
+    use super::Context;
+    use super::CountHint;
+    use super::Discrepancy;
+    use super::Error;
+    use super::GrantSuggestion;
+    use super::InvariantError;
+    use super::LogOp;
+    use super::Member;
+    use super::MemberBreakdown;
+    use super::Membership;
+    use super::MiniZ;
+    use super::MiniZBuilder;
+    use super::ReachabilityCache;
+    use super::RelationshipId;
+    use super::RenameError;
+    use super::SchemaSwapError;
+    use super::StoreStats;
+    use super::TraceOutcome;
+    use super::TraceStep;
+    use super::Tuple;
+    use super::UsersetRule;
+    use super::UsersetTree;
+    use super::WalkAction;
+    use super::Write;
+    use super::WriteError;
+    use crate::typed::TypedRelation;
+    use std::collections::hash_map::DefaultHasher;
+    use std::collections::BTreeSet;
+    use std::hash::Hash;
+    use std::hash::Hasher;
+
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+    struct ObjectId(&'static str);
+    #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord)]
+    struct UserId(&'static str);
+
+    /*
+     * The binary format can only produce owned strings, so we leak them to get
+     * the `&'static str`s that these test ids use.
+     */
+    fn decode_static_str<R: std::io::Read>(
+        r: &mut R,
+    ) -> std::io::Result<&'static str> {
+        String::decode(r).map(|s| &*Box::leak(s.into_boxed_str()))
+    }
+
+    impl BinaryId for ObjectId {
+        fn encode<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+            self.0.to_owned().encode(w)
+        }
+
+        fn decode<R: std::io::Read>(r: &mut R) -> std::io::Result<Self> {
+            decode_static_str(r).map(ObjectId)
+        }
+    }
+
+    impl BinaryId for UserId {
+        fn encode<W: std::io::Write>(&self, w: &mut W) -> std::io::Result<()> {
+            self.0.to_owned().encode(w)
+        }
+
+        fn decode<R: std::io::Read>(r: &mut R) -> std::io::Result<Self> {
+            decode_static_str(r).map(UserId)
+        }
+    }
+
+    impl JsonId for ObjectId {
+        fn to_json_string(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn from_json_string(s: String) -> Option<Self> {
+            Some(ObjectId(Box::leak(s.into_boxed_str())))
+        }
+    }
+
+    impl JsonId for UserId {
+        fn to_json_string(&self) -> String {
+            self.0.to_owned()
+        }
+
+        fn from_json_string(s: String) -> Option<Self> {
+            Some(UserId(Box::leak(s.into_boxed_str())))
+        }
+    }
+
+    /// The schema and tuples from Figure 1 of the Zanzibar paper
+    struct DocExample {
+        miniz: MiniZ<ObjectId, UserId>,
+        set_owner: RelationshipId,
+        set_parent: RelationshipId,
+        set_editor: RelationshipId,
+        set_viewer: RelationshipId,
+    }
+
+    fn doc_example_schema() -> DocExample {
+        doc_example_schema_built(MiniZBuilder::build)
+    }
+
+    /// Like `doc_example_schema()`, but lets the caller choose how to build
+    /// the `MiniZ`
+    fn doc_example_schema_built(
+        build: impl FnOnce(
+            MiniZBuilder<ObjectId, UserId>,
+        ) -> Result<MiniZ<ObjectId, UserId>, BuildError>,
+    ) -> DocExample {
+        /*
+         * The following block constructs a MiniZ instance with the same
+         * configuration as what's described in Figure 1 in the Zanzibar paper.
+         * It looks intended to describe the Google Docs authorization behavior.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_subset(&set_owner)
+            .build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_editor)
+            .with_inherited_set(&set_parent)
+            .build();
+        let miniz = build(miniz_builder).unwrap();
+
+        DocExample { miniz, set_owner, set_parent, set_editor, set_viewer }
+    }
+
+    fn doc_example() -> DocExample {
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = doc_example_schema();
+
+        /*
+         * Now demo it.  We'll construct this hierarchy of objects (defined by
+         * the "parent" relationship).
+         *
+         *    "dir1"                  owner: "alice"
+         *      |                     editor: "bob"
+         *      | "parent"            viewer: "carol"
+         *      v
+         *    "doc123"                owner: "dan"
+         *                            editor: "eve"
+         *                            viewer: "faythe"
+         *
+         *
+         *    "dir2"                  owner: "heidi"
+         *      |                     editor: "ivan"
+         *      |                     viewer: "judy"
+         *      v
+         *    "doc456"                owner: "oscar"
+         *
+         * (The names here are taken from
+         * https://en.wikipedia.org/wiki/Alice_and_Bob#Cast_of_characters.)
+         *
+         * TODO could add another parent directory of "dir1" to test recursion.
+         * TODO could add more levels of indirection, too?
+         */
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+        let user_dan = UserId("dan");
+        let user_eve = UserId("eve");
+        let user_faythe = UserId("faythe");
+        let user_heidi = UserId("heidi");
+        let user_ivan = UserId("ivan");
+        let user_judy = UserId("judy");
+        let user_oscar = UserId("oscar");
+
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_owner, dir1, user_alice);
+        miniz.write_user(&set_editor, dir1, user_bob);
+        miniz.write_user(&set_viewer, dir1, user_carol);
+
+        miniz.write_user(&set_owner, doc123, user_dan);
+        miniz.write_user(&set_editor, doc123, user_eve);
+        miniz.write_user(&set_viewer, doc123, user_faythe);
+
+        miniz.write_object(&set_parent, dir2, doc456);
+        miniz.write_user(&set_owner, dir2, user_heidi);
+        miniz.write_user(&set_editor, dir2, user_ivan);
+        miniz.write_user(&set_viewer, dir2, user_judy);
+        miniz.write_user(&set_owner, doc456, user_oscar);
+
+        DocExample { miniz, set_owner, set_parent, set_editor, set_viewer }
+    }
+
+    #[test]
+    fn test_example() {
+        /*
+         * The following block constructs a MiniZ instance with the same
+         * configuration as what's described in Figure 1 in the Zanzibar paper.
+         * It looks intended to describe the Google Docs authorization behavior.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_subset(&set_owner)
+            .build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_editor)
+            .with_inherited_set(&set_parent)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        /*
+         * Now demo it.  We'll construct this hierarchy of objects (defined by
+         * the "parent" relationship).
+         *
+         *    "dir1"                  owner: "alice"
+         *      |                     editor: "bob"
+         *      | "parent"            viewer: "carol"
+         *      v
+         *    "doc123"                owner: "dan"
+         *                            editor: "eve"
+         *                            viewer: "faythe"
+         *
+         *
+         *    "dir2"                  owner: "heidi"
+         *      |                     editor: "ivan"
+         *      |                     viewer: "judy"
+         *      v
+         *    "doc456"                owner: "oscar"
+         *
+         * (The names here are taken from
+         * https://en.wikipedia.org/wiki/Alice_and_Bob#Cast_of_characters.)
+         *
+         * TODO could add another parent directory of "dir1" to test recursion.
+         * TODO could add more levels of indirection, too?
+         */
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let dir3 = ObjectId("dir3");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let doc789 = ObjectId("doc789");
+
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+        let user_dan = UserId("dan");
+        let user_eve = UserId("eve");
+        let user_faythe = UserId("faythe");
+        let user_heidi = UserId("heidi");
+        let user_ivan = UserId("ivan");
+        let user_judy = UserId("judy");
+        let user_oscar = UserId("oscar");
+
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_owner, dir1, user_alice);
+        miniz.write_user(&set_editor, dir1, user_bob);
+        miniz.write_user(&set_viewer, dir1, user_carol);
+
+        miniz.write_user(&set_owner, doc123, user_dan);
+        miniz.write_user(&set_editor, doc123, user_eve);
+        miniz.write_user(&set_viewer, doc123, user_faythe);
+
+        miniz.write_object(&set_parent, dir2, doc456);
+        miniz.write_user(&set_owner, dir2, user_heidi);
+        miniz.write_user(&set_editor, dir2, user_ivan);
+        miniz.write_user(&set_viewer, dir2, user_judy);
+        miniz.write_user(&set_owner, doc456, user_oscar);
+
+        /* Contents of "dir1" */
+        assert!(miniz.set_contains_object_directly(&set_parent, &dir1, doc123));
+        assert!(!miniz.set_contains_object_directly(
+            &set_parent,
+            &dir1,
+            doc456
+        ));
+        /* Contents of "dir2" */
+        assert!(miniz.set_contains_object_directly(&set_parent, &dir2, doc456));
+        assert!(!miniz.set_contains_object_directly(
+            &set_parent,
+            &dir2,
+            doc123
+        ));
+        /* Non-existent document is contained nowhere. */
+        assert!(!miniz.set_contains_object_directly(
+            &set_parent,
+            &dir1,
+            doc789
+        ));
+        assert!(!miniz.set_contains_object_directly(
+            &set_parent,
+            &dir2,
+            doc789
+        ));
+        /* Non-existent set contains nothing */
+        assert!(!miniz.set_contains_object_directly(
+            &set_parent,
+            &dir3,
+            doc123
+        ));
+        assert!(!miniz.set_contains_object_directly(
+            &set_parent,
+            &dir3,
+            doc123
+        ));
+
+        /* User associations (direct associations) for "dir1" */
+        assert_eq!(
+            miniz.set_list_direct_members(&set_owner, &dir1),
+            vec![&Member::User(user_alice)]
+        );
+        assert!(miniz.set_contains_user_directly(&set_owner, &dir1, user_alice));
+        assert!(!miniz.set_contains_user_directly(&set_owner, &dir1, user_bob));
+        assert!(
+            !miniz.set_contains_user_directly(&set_owner, &dir1, user_carol)
+        );
+
+        assert_eq!(
+            miniz.set_list_direct_members(&set_editor, &dir1),
+            vec![&Member::User(user_bob)]
+        );
+        assert!(!miniz.set_contains_user_directly(
+            &set_editor,
+            &dir1,
+            user_alice
+        ));
+        assert!(miniz.set_contains_user_directly(&set_editor, &dir1, user_bob));
+        assert!(!miniz.set_contains_user_directly(
+            &set_editor,
+            &dir1,
+            user_carol
+        ));
+
+        assert_eq!(
+            miniz.set_list_direct_members(&set_viewer, &dir1),
+            vec![&Member::User(user_carol)]
+        );
+        assert!(!miniz.set_contains_user_directly(
+            &set_viewer,
+            &dir1,
+            user_alice
+        ));
+        assert!(!miniz.set_contains_user_directly(
+            &set_viewer,
+            &dir1,
+            user_bob
+        ));
+        assert!(miniz.set_contains_user_directly(
+            &set_viewer,
+            &dir1,
+            user_carol
+        ));
+
+        /* Reverse indexes */
+        assert_eq!(
+            miniz.object_lookup_memberships(dir1),
+            Vec::new() as Vec<&Membership<ObjectId>>,
+        );
+        assert_eq!(
+            miniz.object_lookup_memberships(dir2),
+            Vec::new() as Vec<&Membership<ObjectId>>,
+        );
+        assert_eq!(
+            miniz.object_lookup_memberships(doc123),
+            vec![&Membership { rid: set_parent.clone(), object: dir1 }]
+        );
+        assert_eq!(
+            miniz.user_lookup_memberships(user_alice),
+            vec![&Membership { rid: set_owner.clone(), object: dir1 }]
+        );
+
+        /* "Check" API */
+        assert!(miniz.check_member(&set_viewer, dir1, user_alice));
+        assert!(miniz.check_member(&set_viewer, dir1, user_bob));
+        assert!(miniz.check_member(&set_viewer, dir1, user_carol));
+        assert!(miniz.check_member(&set_editor, dir1, user_alice));
+        assert!(miniz.check_member(&set_editor, dir1, user_bob));
+        assert!(!miniz.check_member(&set_editor, dir1, user_carol));
+        assert!(miniz.check_member(&set_owner, dir1, user_alice));
+        assert!(!miniz.check_member(&set_owner, dir1, user_bob));
+        assert!(!miniz.check_member(&set_owner, dir1, user_carol));
+    }
+
+    #[test]
+    fn test_inherited_check() {
+        /*
+         * A user who has "viewer" (directly or via "owner") on a directory is
+         * a viewer of the documents in it.  The check on the parent must be
+         * for "viewer", not for the "parent" relationship we followed to get
+         * there.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_owner)
+            .with_inherited_set(&set_parent)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_carol = UserId("carol");
+        let user_dan = UserId("dan");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_owner, dir1, user_alice);
+        miniz.write_user(&set_viewer, dir1, user_carol);
+
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_carol));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_dan));
+        assert!(!miniz.check_member(&set_owner, doc123, user_alice));
+
+        /* Nothing is inherited in the other direction. */
+        miniz.write_user(&set_viewer, doc123, user_dan);
+        assert!(!miniz.check_member(&set_viewer, dir1, user_dan));
+    }
+
+    #[test]
+    fn test_computed_userset() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_editor = miniz_builder.new_relationship("editor").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_computed_userset(&set_editor)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+
+        /*
+         * Only an "editor" tuple exists.  Alice is a viewer by rule.
+         */
+        miniz.write_user(&set_editor, doc123, user_alice);
+        assert!(!miniz.set_contains_user_directly(
+            &set_viewer,
+            &doc123,
+            user_alice
+        ));
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_editor, doc123, user_alice));
+
+        /* The rule does not go the other way. */
+        miniz.write_user(&set_viewer, doc123, user_bob);
+        assert!(miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(!miniz.check_member(&set_editor, doc123, user_bob));
+    }
+
+    #[test]
+    fn test_stats() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        assert_eq!(
+            miniz.stats(),
+            StoreStats {
+                num_sets: 4,
+                num_tuples: 12,
+                num_objects_with_memberships: 2,
+            }
+        );
+
+        /* The tuple count agrees with the direct members of every object. */
+        let objects = ["dir1", "dir2", "doc123", "doc456"].map(ObjectId);
+        let num_listed: usize =
+            [&set_owner, &set_parent, &set_editor, &set_viewer]
+                .iter()
+                .map(|rid| {
+                    objects
+                        .iter()
+                        .map(|object| {
+                            miniz.set_list_direct_members(rid, object).len()
+                        })
+                        .sum::<usize>()
+                })
+                .sum();
+        assert_eq!(num_listed, 12);
+
+        let empty = MiniZ::<ObjectId, UserId>::builder().build().unwrap();
+        assert_eq!(
+            empty.stats(),
+            StoreStats {
+                num_sets: 0,
+                num_tuples: 0,
+                num_objects_with_memberships: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_userset_member_relation() {
+        let mut miniz_builder = MiniZ::builder();
+        let member_builder = miniz_builder.new_relationship("member");
+        let member_id = member_builder.id();
+        let set_member =
+            member_builder.with_userset_member_relation(&member_id).build();
+        assert_eq!(set_member, member_id);
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_userset_member_relation(&set_member)
+            .build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        /*
+         * "group1" is a member of "group2", which has the "viewer"
+         * relationship to "doc123".
+         */
+        let group1 = ObjectId("group1");
+        let group2 = ObjectId("group2");
+        let doc123 = ObjectId("doc123");
+        let dir1 = ObjectId("dir1");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+
+        miniz.write_user(&set_member, group1, user_alice);
+        miniz.write_object(&set_member, group2, group1);
+        miniz.write_user(&set_member, group2, user_bob);
+        miniz.write_object(&set_viewer, doc123, group2);
+
+        assert!(miniz.check_member(&set_member, group1, user_alice));
+        assert!(!miniz.check_member(&set_member, group1, user_bob));
+        assert!(miniz.check_member(&set_member, group2, user_alice));
+        assert!(miniz.check_member(&set_member, group2, user_bob));
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_carol));
+
+        /*
+         * Object members of relationships without a userset member
+         * relationship are not followed.
+         */
+        miniz.write_object(&set_parent, dir1, group1);
+        assert!(!miniz.check_member(&set_parent, dir1, user_alice));
+    }
+
+    #[test]
+    fn test_object_relation_members() {
+        let schema = || {
+            let mut set_member = None;
+            let ex = doc_example_schema_built(|mut miniz_builder| {
+                set_member =
+                    Some(miniz_builder.new_relationship("member").build());
+                miniz_builder.build()
+            });
+            (ex, set_member.unwrap())
+        };
+        let (ex, set_member) = schema();
+        let DocExample { mut miniz, set_editor, set_viewer, .. } = ex;
+        let doc123 = ObjectId("doc123");
+        let eng = ObjectId("eng");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+
+        /* Grant "viewer" on "doc123" to "member" on "eng". */
+        miniz.write_user(&set_member, eng, user_alice);
+        let membership =
+            miniz.write_object_relation(&set_viewer, doc123, eng, &set_member);
+        assert_eq!(
+            membership,
+            Membership { rid: set_viewer.clone(), object: doc123 }
+        );
+        let userset = Member::object_relation(eng, &set_member);
+        assert_eq!(
+            miniz.lookup_memberships_for(std::slice::from_ref(&userset))
+                [&userset],
+            vec![&membership]
+        );
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(!miniz.check_member(&set_editor, doc123, user_alice));
+        assert!(miniz.check_member_subject(&set_viewer, &doc123, &userset));
+        assert!(miniz.check_userset(&set_viewer, &doc123, &set_member, &eng));
+
+        /* The group's membership is resolved when checking. */
+        miniz.write_user(&set_member, eng, user_bob);
+        assert!(miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(miniz.remove_object_relation(
+            &set_viewer,
+            doc123,
+            eng,
+            &set_member
+        ));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_bob));
+        miniz.write_object_relation(&set_viewer, doc123, eng, &set_member);
+
+        /* Usersets of nonexistent relationships are rejected. */
+        let bogus = RelationshipId(String::from("bogus"));
+        assert_eq!(
+            miniz.apply_writes(&[Write::Insert(Tuple {
+                rid: set_viewer.clone(),
+                parent: doc123,
+                member: Member::object_relation(eng, &bogus),
+            })]),
+            Err(WriteError::NoSuchRelationship(bogus))
+        );
+
+        /* Both serialization formats preserve them. */
+        let mut bytes = Vec::new();
+        miniz.write_binary(&mut bytes).unwrap();
+        let loaded =
+            MiniZ::<ObjectId, UserId>::read_binary(bytes.as_slice()).unwrap();
+        assert!(loaded == miniz);
+        assert!(loaded.check_member(&set_viewer, doc123, user_alice));
+
+        let mut text = Vec::new();
+        miniz.export_jsonl(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains(
+            r#"{"object":"doc123","relation":"viewer","subject":"userset:eng#member"}"#
+        ));
+        let mut imported = schema().0.miniz;
+        assert_eq!(imported.import_jsonl(text.as_bytes()).unwrap(), 3);
+        assert!(imported == miniz);
+
+        /* Renaming the group's relationship renames the userset, too. */
+        let set_team = miniz.rename_relation(&set_member, "team").unwrap();
+        assert_eq!(
+            miniz.set_list_direct_members(&set_viewer, &doc123),
+            vec![&Member::object_relation(eng, &set_team)]
+        );
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        /* Deleting the group removes the tuple granting it access. */
+        assert_eq!(miniz.delete_object(&eng), 3);
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.iter_tuples().next().is_none());
+        assert_eq!(miniz.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_remove() {
+        let DocExample { mut miniz, set_owner, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_carol = UserId("carol");
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        assert!(miniz.remove_user(&set_owner, dir1, user_alice));
+        assert!(
+            !miniz.set_contains_user_directly(&set_owner, &dir1, user_alice)
+        );
+        assert!(!miniz.check_member(&set_owner, dir1, user_alice));
+        assert!(miniz.user_lookup_memberships(user_alice).is_empty());
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        /* Removing it again does nothing. */
+        assert!(!miniz.remove_user(&set_owner, dir1, user_alice));
+        /* Nor does removing a tuple for an object that has none. */
+        assert!(!miniz.remove_user(&set_owner, doc123, user_alice));
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        assert!(miniz.remove_object(&set_parent, dir1, doc123));
+        assert!(miniz.object_lookup_memberships(doc123).is_empty());
+        assert!(miniz.set_list_direct_members(&set_parent, &dir1).is_empty());
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        /* Interleave some more writes and removes. */
+        miniz.write_user(&set_owner, dir1, user_alice);
+        miniz.write_object(&set_parent, dir1, doc123);
+        assert_eq!(miniz.check_invariants(), Ok(()));
+        assert!(miniz.remove_user(&set_viewer, dir1, user_carol));
+        miniz.write_user(&set_viewer, doc123, user_carol);
+        assert!(miniz.remove_user(&set_viewer, doc123, user_carol));
+        assert_eq!(miniz.check_invariants(), Ok(()));
+        assert_eq!(miniz.stats().num_tuples, 11);
+    }
+
+    #[test]
+    fn test_check_invariants() {
+        let DocExample { mut miniz, set_owner, .. } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let user_alice = UserId("alice");
+
+        /*
+         * Corrupt the reverse index behind the store's back and make sure we
+         * notice.
+         */
+        let alice = Member::User(user_alice);
+        let membership = Membership { rid: set_owner.clone(), object: dir1 };
+        miniz.memberships.remove(&alice);
+        assert_eq!(
+            miniz.check_invariants(),
+            Err(vec![InvariantError::MissingMembership {
+                rid: set_owner.clone(),
+                parent: dir1,
+                member: alice.clone(),
+            }])
+        );
+
+        miniz.memberships.insert(alice.clone(), Default::default());
+        miniz.sets.get_mut(&set_owner).unwrap().direct_members.remove(&dir1);
+        assert_eq!(
+            miniz.check_invariants(),
+            Err(vec![InvariantError::EmptyMemberships {
+                member: alice.clone()
+            }])
+        );
+
+        miniz.memberships.get_mut(&alice).unwrap().insert(membership.clone());
+        assert_eq!(
+            miniz.check_invariants(),
+            Err(vec![InvariantError::MissingDirectMember {
+                member: alice,
+                membership
+            }])
+        );
+    }
+
+    #[test]
+    fn test_delete_object() {
+        let DocExample { mut miniz, set_owner, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_carol = UserId("carol");
+        let user_dan = UserId("dan");
+
+        /* Before the delete, "doc123" inherits viewers from "dir1". */
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_carol));
+
+        /*
+         * "dir1" has four tuples: one "parent" relationship to "doc123" and
+         * one user for each of "owner", "editor", and "viewer".
+         */
+        assert_eq!(miniz.delete_object(&dir1), 4);
+        assert_eq!(miniz.check_invariants(), Ok(()));
+        assert!(miniz.object_lookup_memberships(doc123).is_empty());
+        assert!(miniz.set_list_direct_members(&set_parent, &dir1).is_empty());
+        assert!(miniz.user_lookup_memberships(user_alice).is_empty());
+        assert!(!miniz.check_member(&set_owner, dir1, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_carol));
+
+        /* "doc123"'s own tuples are untouched, as is "dir2". */
+        assert!(miniz.check_member(&set_viewer, doc123, user_dan));
+        assert_eq!(miniz.stats().num_tuples, 8);
+        assert_eq!(miniz.delete_object(&dir1), 0);
+
+        /* Deleting an object that's a member removes it from its parent. */
+        assert_eq!(miniz.delete_object(&ObjectId("doc456")), 2);
+        assert!(miniz.set_list_direct_members(&set_parent, &dir2).is_empty());
+        assert_eq!(miniz.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_delete_user() {
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+
+        miniz.write_user(&set_viewer, doc123, user_alice);
+        assert!(miniz.check_member(&set_owner, dir1, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+
+        assert_eq!(miniz.delete_user(&user_alice), 2);
+        assert_eq!(miniz.check_invariants(), Ok(()));
+        assert!(
+            !miniz.set_contains_user_directly(&set_owner, &dir1, user_alice)
+        );
+        assert!(miniz.user_lookup_memberships(user_alice).is_empty());
+        for rid in &[&set_owner, &set_parent, &set_editor, &set_viewer] {
+            for object in &[dir1, doc123] {
+                assert!(!miniz.check_member(rid, *object, user_alice));
+            }
+        }
+
+        /* Other users are unaffected. */
+        assert!(miniz.check_member(&set_editor, dir1, user_bob));
+        assert_eq!(miniz.delete_user(&user_alice), 0);
+    }
+
+    /// The same tuples that `doc_example()` writes
+    fn doc_example_tuples(ex: &DocExample) -> Vec<Tuple<ObjectId, UserId>> {
+        let tuple = |rid: &RelationshipId, parent, member| Tuple {
+            rid: rid.clone(),
+            parent: ObjectId(parent),
+            member,
+        };
+        let object = |name| Member::Object(ObjectId(name));
+        let user = |name| Member::User(UserId(name));
+        vec![
+            tuple(&ex.set_parent, "dir1", object("doc123")),
+            tuple(&ex.set_owner, "dir1", user("alice")),
+            tuple(&ex.set_editor, "dir1", user("bob")),
+            tuple(&ex.set_viewer, "dir1", user("carol")),
+            tuple(&ex.set_owner, "doc123", user("dan")),
+            tuple(&ex.set_editor, "doc123", user("eve")),
+            tuple(&ex.set_viewer, "doc123", user("faythe")),
+            tuple(&ex.set_parent, "dir2", object("doc456")),
+            tuple(&ex.set_owner, "dir2", user("heidi")),
+            tuple(&ex.set_editor, "dir2", user("ivan")),
+            tuple(&ex.set_viewer, "dir2", user("judy")),
+            tuple(&ex.set_owner, "doc456", user("oscar")),
+        ]
+    }
+
+    ///
+    /// Asserts that `miniz` has the same tuples as the doc example and gives
+    /// the same answers to `check_member()`
+    ///
+    fn assert_matches_doc_example(miniz: &MiniZ<ObjectId, UserId>) {
+        let expected = doc_example();
+        assert_eq!(miniz.check_invariants(), Ok(()));
+        assert_eq!(miniz.stats(), expected.miniz.stats());
+        let rids = [
+            &expected.set_owner,
+            &expected.set_parent,
+            &expected.set_editor,
+            &expected.set_viewer,
+        ];
+        let objects = ["dir1", "dir2", "doc123", "doc456", "doc789"];
+        let users = ["alice", "bob", "carol", "dan", "eve", "oscar", "mallory"];
+        for rid in &rids {
+            for o in &objects {
+                assert_eq!(
+                    miniz.set_list_direct_members(rid, &ObjectId(o)),
+                    expected.miniz.set_list_direct_members(rid, &ObjectId(o))
+                );
+                for u in &users {
+                    assert_eq!(
+                        miniz.check_member(rid, ObjectId(o), UserId(u)),
+                        expected.miniz.check_member(
+                            rid,
+                            ObjectId(o),
+                            UserId(u)
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_write_tuple() {
+        let ex = doc_example_schema();
+        let tuples = doc_example_tuples(&ex);
+        let mut miniz = ex.miniz;
+        for t in tuples {
+            miniz.write_tuple(t);
+        }
+        assert_matches_doc_example(&miniz);
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_owner)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+        assert!(miniz.log().is_none());
+        miniz.enable_log();
+        assert_eq!(miniz.version(), 0);
+
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+
+        miniz.write_user(&set_owner, doc123, user_alice);
+        miniz.write_user(&set_viewer, doc123, user_bob);
+        let v_both = miniz.version();
+        assert_eq!(v_both, 2);
+        assert!(miniz.remove_user(&set_owner, doc123, user_alice));
+        let v_bob = miniz.version();
+        /* Removing a tuple that doesn't exist is not a change. */
+        assert!(!miniz.remove_user(&set_owner, doc123, user_alice));
+        assert_eq!(miniz.version(), v_bob);
+        assert_eq!(miniz.delete_user(&user_bob), 1);
+        let v_none = miniz.version();
+
+        let log = miniz.log().unwrap();
+        assert_eq!(log.len(), 4);
+        assert!(matches!(log[2].op, LogOp::Remove(_)));
+        assert_eq!(
+            log.iter().map(|e| e.version).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4]
+        );
+
+        let at_start = miniz.replay_to(0);
+        assert_eq!(at_start.stats().num_tuples, 0);
+        assert_eq!(at_start.version(), 0);
+
+        let at_first = miniz.replay_to(1);
+        assert!(at_first.check_member(&set_viewer, doc123, user_alice));
+        assert!(!at_first.check_member(&set_viewer, doc123, user_bob));
+
+        let at_both = miniz.replay_to(v_both);
+        assert!(at_both.check_member(&set_owner, doc123, user_alice));
+        assert!(at_both.check_member(&set_viewer, doc123, user_alice));
+        assert!(at_both.check_member(&set_viewer, doc123, user_bob));
+        assert_eq!(at_both.check_invariants(), Ok(()));
+        assert_eq!(at_both.log().unwrap(), &miniz.log().unwrap()[..2]);
+
+        let at_bob = miniz.replay_to(v_bob);
+        assert!(!at_bob.check_member(&set_viewer, doc123, user_alice));
+        assert!(at_bob.check_member(&set_viewer, doc123, user_bob));
+
+        let at_none = miniz.replay_to(v_none + 10);
+        assert_eq!(at_none.stats().num_tuples, 0);
+        assert_eq!(at_none.version(), v_none);
+        assert_eq!(at_none.check_invariants(), Ok(()));
+    }
+
+    #[test]
+    fn test_check_member_detailed() {
+        let DocExample { miniz, set_owner, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc456 = ObjectId("doc456");
+        let doc789 = ObjectId("doc789");
+        let user_alice = UserId("alice");
+        let user_carol = UserId("carol");
+
+        assert_eq!(
+            miniz.check_member_detailed(&set_viewer, dir1, user_alice),
+            CheckResult::Allowed
+        );
+        assert_eq!(
+            miniz.check_member_detailed(&set_owner, dir1, user_carol),
+            CheckResult::Denied
+        );
+        assert_eq!(
+            miniz.check_member_detailed(&set_viewer, doc789, user_alice),
+            CheckResult::ObjectUnknown
+        );
+
+        /*
+         * "doc456" has no "parent" members of its own, but it's known because
+         * it's a member of "dir2".
+         */
+        assert_eq!(
+            miniz.check_member_detailed(&set_parent, doc456, user_alice),
+            CheckResult::Denied
+        );
+    }
+
+    #[test]
+    fn test_description() {
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        let set_owner = miniz_builder
+            .new_relationship("owner")
+            .with_description("full control, including sharing")
+            .build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_subset(&set_owner)
+            .with_description(String::from("can modify contents"))
+            .build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_description("replaced below")
+            .with_subset(&set_editor)
+            .with_inherited_set(&set_parent)
+            .with_description("can read contents")
+            .build();
+        let miniz = miniz_builder.build().unwrap();
+
+        assert_eq!(
+            miniz.set_description(&set_owner),
+            Some("full control, including sharing")
+        );
+        assert_eq!(miniz.set_description(&set_parent), None);
+        assert_eq!(
+            miniz.set_description(&set_editor),
+            Some("can modify contents")
+        );
+        assert_eq!(
+            miniz.set_description(&set_viewer),
+            Some("can read contents")
+        );
+    }
+
+    #[test]
+    fn test_check_any_all() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+        let user_oscar = UserId("oscar");
+
+        let viewer_or_editor = [set_viewer.clone(), set_editor.clone()];
+        let owner_or_parent = [set_owner.clone(), set_parent.clone()];
+        assert!(miniz.check_any(&viewer_or_editor, &dir1, &user_carol));
+        assert!(miniz.check_any(&viewer_or_editor, &doc123, &user_bob));
+        assert!(!miniz.check_any(&viewer_or_editor, &dir1, &user_oscar));
+        assert!(miniz.check_any(&owner_or_parent, &dir1, &user_alice));
+        assert!(!miniz.check_any(&owner_or_parent, &dir1, &user_bob));
+        assert!(!miniz.check_any(&[], &dir1, &user_alice));
+
+        let all = [set_viewer.clone(), set_editor.clone(), set_owner.clone()];
+        assert!(miniz.check_all(&all, &dir1, &user_alice));
+        /* Only "viewer" is inherited from "dir1". */
+        assert!(!miniz.check_all(&all, &doc123, &user_alice));
+        assert!(miniz.check_any(&all, &doc123, &user_alice));
+        assert!(!miniz.check_all(&all, &dir1, &user_bob));
+        assert!(!miniz.check_all(&all, &dir1, &user_carol));
+        assert!(miniz.check_all(&viewer_or_editor, &dir1, &user_bob));
+        assert!(miniz.check_all(&[], &dir1, &user_oscar));
+
+        /* The answers match individual checks, in any order. */
+        for user in &[user_alice, user_bob, user_carol, user_oscar] {
+            for object in &[dir1, doc123] {
+                let each = all
+                    .iter()
+                    .map(|rid| miniz.check_member(rid, *object, *user))
+                    .collect::<Vec<_>>();
+                let mut rev = all.clone();
+                rev.reverse();
+                assert_eq!(
+                    miniz.check_any(&all, object, user),
+                    each.iter().any(|b| *b)
+                );
+                assert_eq!(
+                    miniz.check_any(&rev, object, user),
+                    each.iter().any(|b| *b)
+                );
+                assert_eq!(
+                    miniz.check_all(&rev, object, user),
+                    each.iter().all(|b| *b)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_list_objects() {
+        let DocExample { mut miniz, set_parent, .. } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+
+        assert_eq!(
+            miniz.list_objects(None, 10),
+            vec![dir1, dir2, doc123, doc456]
+        );
+        assert_eq!(miniz.list_objects(None, 0), Vec::<ObjectId>::new());
+
+        /* Page through two at a time. */
+        let page1 = miniz.list_objects(None, 2);
+        assert_eq!(page1, vec![dir1, dir2]);
+        let page2 = miniz.list_objects(page1.last(), 2);
+        assert_eq!(page2, vec![doc123, doc456]);
+        let page3 = miniz.list_objects(page2.last(), 2);
+        assert!(page3.is_empty());
+
+        /* The cursor need not be an object that's in the store. */
+        assert_eq!(
+            miniz.list_objects(Some(&ObjectId("dir3")), 1),
+            vec![doc123]
+        );
+
+        /*
+         * Objects that only appear as members (here, "doc789") are included.
+         * Objects that no longer appear anywhere are not.
+         */
+        let doc789 = ObjectId("doc789");
+        miniz.write_object(&set_parent, dir2, doc789);
+        assert_eq!(miniz.delete_object(&dir1), 4);
+        assert_eq!(
+            miniz.list_objects(None, 10),
+            vec![dir2, doc123, doc456, doc789]
+        );
+        assert_eq!(miniz.list_objects(Some(&doc123), 10), vec![doc456, doc789]);
+    }
+
+    #[test]
+    fn test_list_direct_members_by_kind() {
+        let DocExample { mut miniz, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir3 = ObjectId("dir3");
+        let doc123 = ObjectId("doc123");
+        let group1 = ObjectId("group1");
+        let user_alice = UserId("alice");
+        let user_carol = UserId("carol");
+
+        /* "viewer" on "dir1" now has both an object and two users. */
+        miniz.write_object(&set_viewer, dir1, group1);
+        miniz.write_user(&set_viewer, dir1, user_alice);
+        assert_eq!(
+            miniz.set_list_direct_members(&set_viewer, &dir1),
+            vec![
+                &Member::Object(group1),
+                &Member::User(user_alice),
+                &Member::User(user_carol),
+            ]
+        );
+        assert_eq!(
+            miniz.set_list_direct_object_members(&set_viewer, &dir1),
+            vec![&group1]
+        );
+        assert_eq!(
+            miniz.set_list_direct_user_members(&set_viewer, &dir1),
+            vec![&user_alice, &user_carol]
+        );
+
+        assert_eq!(
+            miniz.set_list_direct_object_members(&set_parent, &dir1),
+            vec![&doc123]
+        );
+        assert!(miniz
+            .set_list_direct_user_members(&set_parent, &dir1)
+            .is_empty());
+        assert!(miniz
+            .set_list_direct_object_members(&set_parent, &dir3)
+            .is_empty());
+        assert!(miniz
+            .set_list_direct_user_members(&set_parent, &dir3)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_write_users() {
+        let DocExample { mut miniz, set_viewer, .. } = doc_example();
+        let doc123 = ObjectId("doc123");
+        let users = [
+            UserId("grace"),
+            UserId("mallory"),
+            UserId("niaj"),
+            UserId("peggy"),
+            UserId("rupert"),
+        ];
+        for user in &users {
+            assert!(!miniz.check_member(&set_viewer, doc123, *user));
+        }
+
+        /* Duplicates in the input and existing tuples are skipped. */
+        let mut input = users.to_vec();
+        input.push(UserId("grace"));
+        input.push(UserId("faythe"));
+        let version = miniz.version();
+        assert_eq!(miniz.write_users(&set_viewer, doc123, input), 5);
+        assert_eq!(miniz.version(), version + 5);
+        for user in &users {
+            assert!(miniz.check_member(&set_viewer, doc123, *user));
+            assert!(miniz.user_lookup_memberships(*user).contains(
+                &&Membership { rid: set_viewer.clone(), object: doc123 }
+            ));
+        }
+        assert_eq!(
+            miniz.set_list_direct_user_members(&set_viewer, &doc123).len(),
+            6
+        );
+        assert_eq!(miniz.check_invariants(), Ok(()));
+
+        /* Writing them all again changes nothing. */
+        assert_eq!(miniz.write_users(&set_viewer, doc123, users), 0);
+        assert_eq!(miniz.version(), version + 5);
+    }
+
+    #[test]
+    fn test_typed_write() {
+        struct Dir(&'static str);
+        struct Doc(&'static str);
+        struct User(&'static str);
+
+        impl From<Dir> for ObjectId {
+            fn from(dir: Dir) -> ObjectId {
+                ObjectId(dir.0)
+            }
+        }
+        impl From<Doc> for ObjectId {
+            fn from(doc: Doc) -> ObjectId {
+                ObjectId(doc.0)
+            }
+        }
+        impl From<Doc> for Member<ObjectId, UserId> {
+            fn from(doc: Doc) -> Member<ObjectId, UserId> {
+                Member::Object(ObjectId(doc.0))
+            }
+        }
+        impl From<User> for Member<ObjectId, UserId> {
+            fn from(user: User) -> Member<ObjectId, UserId> {
+                Member::User(UserId(user.0))
+            }
+        }
+
+        let ex = doc_example_schema();
+        let parent: TypedRelation<Dir, Doc> =
+            TypedRelation::new(&ex.set_parent);
+        let doc_owner: TypedRelation<Doc, User> =
+            TypedRelation::new(&ex.set_owner);
+        let dir_owner: TypedRelation<Dir, User> =
+            TypedRelation::new(&ex.set_owner);
+        assert_eq!(parent.rid(), &ex.set_parent);
+
+        let mut miniz = ex.miniz;
+        let membership = miniz.typed_write(&parent, Dir("dir1"), Doc("doc123"));
+        assert_eq!(
+            membership,
+            Membership { rid: ex.set_parent.clone(), object: ObjectId("dir1") }
+        );
+        miniz.typed_write(&dir_owner, Dir("dir1"), User("alice"));
+        miniz.typed_write(&doc_owner.clone(), Doc("doc123"), User("dan"));
+
+        let doc123 = ObjectId("doc123");
+        assert!(miniz.check_member(&ex.set_viewer, doc123, UserId("alice")));
+        assert!(miniz.check_member(&ex.set_viewer, doc123, UserId("dan")));
+        assert_eq!(
+            miniz.set_list_direct_object_members(
+                &ex.set_parent,
+                &ObjectId("dir1")
+            ),
+            vec![&doc123]
+        );
+    }
+
+    #[test]
+    fn test_write_returns_membership() {
+        let DocExample { mut miniz, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+
+        let membership = miniz.write_user(&set_viewer, doc123, user_alice);
+        assert_eq!(
+            membership,
+            Membership { rid: set_viewer.clone(), object: doc123 }
+        );
+        assert!(miniz
+            .user_lookup_memberships(user_alice)
+            .contains(&&membership));
+
+        let membership = miniz.write_object(&set_parent, dir2, doc123);
+        assert_eq!(
+            membership,
+            Membership { rid: set_parent.clone(), object: dir2 }
+        );
+        assert_eq!(
+            miniz.object_lookup_memberships(doc123),
+            vec![
+                &Membership { rid: set_parent.clone(), object: dir1 },
+                &membership,
+            ]
+        );
+
+        let membership = miniz.write_tuple(Tuple {
+            rid: set_viewer.clone(),
+            parent: dir2,
+            member: Member::User(user_alice),
+        });
+        assert_eq!(membership, Membership { rid: set_viewer, object: dir2 });
+        assert!(miniz
+            .user_lookup_memberships(user_alice)
+            .contains(&&membership));
+    }
+
+    #[test]
+    fn test_relation_reachable() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+
+        assert!(miniz.relation_reachable(&set_viewer, &set_owner));
+        assert!(miniz.relation_reachable(&set_viewer, &set_editor));
+        assert!(miniz.relation_reachable(&set_viewer, &set_parent));
+        assert!(miniz.relation_reachable(&set_editor, &set_owner));
+        assert!(miniz.relation_reachable(&set_owner, &set_owner));
+        assert!(!miniz.relation_reachable(&set_owner, &set_viewer));
+        assert!(!miniz.relation_reachable(&set_owner, &set_editor));
+        assert!(!miniz.relation_reachable(&set_editor, &set_viewer));
+        assert!(!miniz.relation_reachable(&set_editor, &set_parent));
+        assert!(!miniz.relation_reachable(&set_parent, &set_viewer));
+
+        /* Userset member relationships are edges, too, including cycles. */
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        let member_builder = miniz_builder.new_relationship("member");
+        let member_id = member_builder.id();
+        let set_member =
+            member_builder.with_userset_member_relation(&member_id).build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_userset_member_relation(&set_member)
+            .build();
+        let miniz = miniz_builder.build().unwrap();
+        assert!(miniz.relation_reachable(&set_viewer, &set_member));
+        assert!(miniz.relation_reachable(&set_member, &set_member));
+        assert!(!miniz.relation_reachable(&set_member, &set_viewer));
+    }
+
+    #[test]
+    fn test_relation_reachable_updates() {
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        let set_d = miniz_builder.new_relationship("d").build();
+        let set_c =
+            miniz_builder.new_relationship("c").with_subset(&set_d).build();
+        let set_b =
+            miniz_builder.new_relationship("b").with_subset(&set_c).build();
+        let set_a = miniz_builder.new_relationship("a").build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        /* The incrementally-updated cache must match one built from scratch. */
+        let check_cache = |miniz: &MiniZ<ObjectId, UserId>| {
+            assert_eq!(
+                miniz.reachability.closures,
+                ReachabilityCache::new(&miniz.sets).closures
+            );
+        };
+
+        check_cache(&miniz);
+        assert!(miniz.relation_reachable(&set_b, &set_d));
+        assert!(!miniz.relation_reachable(&set_a, &set_d));
+
+        /* Adding an edge makes everything downstream reachable. */
+        assert!(miniz.add_subset(&set_a, &set_b));
+        check_cache(&miniz);
+        assert!(miniz.relation_reachable(&set_a, &set_b));
+        assert!(miniz.relation_reachable(&set_a, &set_d));
+
+        /* Removing it flips that back. */
+        assert!(miniz.remove_subset(&set_a, &set_b));
+        check_cache(&miniz);
+        assert!(!miniz.relation_reachable(&set_a, &set_b));
+        assert!(!miniz.relation_reachable(&set_a, &set_d));
+        assert!(miniz.relation_reachable(&set_b, &set_d));
+
+        /* Removing one of two paths leaves the other. */
+        assert!(miniz.add_subset(&set_a, &set_b));
+        assert!(miniz.add_inherited_as(&set_a, &set_c, &set_c));
+        check_cache(&miniz);
+        assert!(miniz.remove_subset(&set_a, &set_b));
+        check_cache(&miniz);
+        assert!(!miniz.relation_reachable(&set_a, &set_b));
+        assert!(miniz.relation_reachable(&set_a, &set_d));
+        assert!(miniz.remove_inherited_as(&set_a, &set_c, &set_c));
+        check_cache(&miniz);
+        assert!(!miniz.relation_reachable(&set_a, &set_d));
+
+        /* Breaking a cycle disconnects everything that went around it. */
+        assert!(miniz.add_subset(&set_d, &set_a));
+        assert!(miniz.add_subset(&set_a, &set_b));
+        check_cache(&miniz);
+        assert!(miniz.relation_reachable(&set_b, &set_a));
+        assert!(miniz.relation_reachable(&set_d, &set_c));
+        assert!(miniz.remove_subset(&set_c, &set_d));
+        check_cache(&miniz);
+        assert!(!miniz.relation_reachable(&set_b, &set_a));
+        assert!(!miniz.relation_reachable(&set_c, &set_d));
+        assert!(miniz.relation_reachable(&set_d, &set_c));
+
+        /* Other schema changes recompute the cache entirely. */
+        let set_e = miniz.rename_relation(&set_a, "e").unwrap();
+        check_cache(&miniz);
+        assert!(miniz.relation_reachable(&set_d, &set_e));
+    }
+
+    #[test]
+    fn test_unused_relations() {
+        /*
+         * Every relationship in the example is either referenced by another
+         * one or has members.
+         */
+        let DocExample { miniz, set_viewer, .. } = doc_example();
+        assert!(miniz.unused_relations().is_empty());
+
+        /* Without tuples, "viewer" is the only one not referenced. */
+        let DocExample { miniz, .. } = doc_example_schema();
+        assert_eq!(miniz.unused_relations(), vec![set_viewer]);
+
+        /* An orphaned relationship is reported, even if it refers to itself. */
+        let mut set_orphan = None;
+        let mut ex = doc_example_schema_built(|mut miniz_builder| {
+            let orphan_builder = miniz_builder.new_relationship("orphan");
+            let orphan_id = orphan_builder.id();
+            set_orphan = Some(orphan_builder.with_subset(&orphan_id).build());
+            miniz_builder.build()
+        });
+        for tuple in doc_example_tuples(&ex) {
+            ex.miniz.write_tuple(tuple);
+        }
+        let set_orphan = set_orphan.unwrap();
+        assert_eq!(ex.miniz.unused_relations(), vec![set_orphan.clone()]);
+
+        /* Once it has a member, it's no longer unused. */
+        ex.miniz.write_user(&set_orphan, ObjectId("dir1"), UserId("alice"));
+        assert!(ex.miniz.unused_relations().is_empty());
+    }
+
+    #[test]
+    fn test_trace_check() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let step = |rid: &RelationshipId, object, outcome| TraceStep {
+            rid: rid.clone(),
+            object,
+            outcome,
+        };
+
+        /*
+         * "alice" can't view "doc456": she's not in any of the sets that
+         * make up "viewer" on it or on its parent, "dir2".
+         */
+        let trace = miniz.trace_check(&set_viewer, doc456, UserId("alice"));
+        assert_eq!(
+            trace,
+            CheckTrace {
+                allowed: false,
+                steps: vec![
+                    step(&set_viewer, doc456, TraceOutcome::DirectMiss),
+                    step(
+                        &set_viewer,
+                        doc456,
+                        TraceOutcome::SubsetDescended {
+                            subrid: set_editor.clone()
+                        }
+                    ),
+                    step(&set_editor, doc456, TraceOutcome::DirectMiss),
+                    step(
+                        &set_editor,
+                        doc456,
+                        TraceOutcome::SubsetDescended {
+                            subrid: set_owner.clone()
+                        }
+                    ),
+                    step(&set_owner, doc456, TraceOutcome::DirectMiss),
+                    step(&set_owner, doc456, TraceOutcome::DeadEnd),
+                    step(&set_editor, doc456, TraceOutcome::DeadEnd),
+                    step(
+                        &set_viewer,
+                        doc456,
+                        TraceOutcome::InheritedDescended {
+                            via: set_parent.clone(),
+                            parent: dir2,
+                            parent_rid: set_viewer.clone(),
+                        }
+                    ),
+                    step(&set_viewer, dir2, TraceOutcome::DirectMiss),
+                    step(
+                        &set_viewer,
+                        dir2,
+                        TraceOutcome::SubsetDescended {
+                            subrid: set_editor.clone()
+                        }
+                    ),
+                    step(&set_editor, dir2, TraceOutcome::DirectMiss),
+                    step(
+                        &set_editor,
+                        dir2,
+                        TraceOutcome::SubsetDescended {
+                            subrid: set_owner.clone()
+                        }
+                    ),
+                    step(&set_owner, dir2, TraceOutcome::DirectMiss),
+                    step(&set_owner, dir2, TraceOutcome::DeadEnd),
+                    step(&set_editor, dir2, TraceOutcome::DeadEnd),
+                    step(&set_viewer, dir2, TraceOutcome::DeadEnd),
+                    step(&set_viewer, doc456, TraceOutcome::DeadEnd),
+                ],
+            }
+        );
+
+        /* A successful check stops where it finds the user. */
+        let trace = miniz.trace_check(&set_viewer, doc123, UserId("bob"));
+        assert!(trace.allowed);
+        assert_eq!(
+            trace.steps.last(),
+            Some(&step(&set_editor, dir1, TraceOutcome::DirectHit))
+        );
+        assert!(!trace
+            .steps
+            .iter()
+            .any(|s| s.rid == set_owner && s.object == dir1));
+        let trace = miniz.trace_check(&set_owner, dir1, UserId("alice"));
+        assert_eq!(
+            trace.steps,
+            vec![step(&set_owner, dir1, TraceOutcome::DirectHit)]
+        );
+    }
+
+    #[test]
+    fn test_explain_denied() {
+        let DocExample { mut miniz, set_owner, set_editor, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_oscar = UserId("oscar");
+
+        /* Nothing to explain if access would be granted. */
+        assert!(miniz
+            .explain_denied(&set_viewer, doc123, user_alice)
+            .is_empty());
+
+        let suggestion = |rid: &RelationshipId, object| GrantSuggestion {
+            rid: rid.clone(),
+            object,
+        };
+        let suggestions = miniz.explain_denied(&set_viewer, doc123, user_oscar);
+        assert_eq!(
+            suggestions,
+            vec![
+                suggestion(&set_viewer, doc123),
+                suggestion(&set_editor, doc123),
+                suggestion(&set_viewer, dir1),
+                suggestion(&set_editor, dir1),
+                suggestion(&set_owner, doc123),
+                suggestion(&set_owner, dir1),
+            ]
+        );
+
+        /* Following any one of the suggestions grants access. */
+        for s in &suggestions {
+            miniz.write_user(&s.rid, s.object, user_oscar);
+            assert!(miniz.check_member(&set_viewer, doc123, user_oscar));
+            assert!(miniz.remove_user(&s.rid, s.object, user_oscar));
+            assert!(!miniz.check_member(&set_viewer, doc123, user_oscar));
+        }
+
+        /* "owner" is not inherited, so there's only one way to get it. */
+        assert_eq!(
+            miniz.explain_denied(&set_owner, doc123, user_oscar),
+            vec![suggestion(&set_owner, doc123)]
+        );
+    }
+
+    #[test]
+    fn test_inherited_as() {
+        /*
+         * This is the same schema as the doc example, except that the viewers
+         * of an object are the _editors_ (not the viewers) of its parent.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_subset(&set_owner)
+            .build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_editor)
+            .with_inherited_as(&set_parent, &set_editor)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_owner, dir1, user_alice);
+        miniz.write_user(&set_editor, dir1, user_bob);
+        miniz.write_user(&set_viewer, dir1, user_carol);
+
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_carol));
+        assert!(!miniz.check_member(&set_editor, doc123, user_bob));
+        assert!(miniz.relation_reachable(&set_viewer, &set_parent));
+        assert!(miniz.relation_reachable(&set_viewer, &set_editor));
+
+        /* With same-name inheritance, viewers of the parent are viewers. */
+        let DocExample { miniz, set_viewer, .. } = doc_example();
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(miniz.check_member(&set_viewer, doc123, user_carol));
+    }
+
+    #[test]
+    fn test_inherited_as_pairs() {
+        /*
+         * "viewer" inherits the parent's "editor" via "parent", but the
+         * folder's "viewer" via "folder".  Each traversal relationship must
+         * only be paired with its own parent relationship.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_folder = miniz_builder.new_relationship("folder").build();
+        let set_editor = miniz_builder.new_relationship("editor").build();
+        let viewer_builder = miniz_builder.new_relationship("viewer");
+        let viewer_id = viewer_builder.id();
+        let set_viewer = viewer_builder
+            .with_inherited_as(&set_parent, &set_editor)
+            .with_inherited_as(&set_folder, &viewer_id)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let folder1 = ObjectId("folder1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+        let user_dan = UserId("dan");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_object(&set_folder, folder1, doc123);
+        miniz.write_user(&set_editor, dir1, user_alice);
+        miniz.write_user(&set_viewer, dir1, user_bob);
+        miniz.write_user(&set_editor, folder1, user_carol);
+        miniz.write_user(&set_viewer, folder1, user_dan);
+
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_carol));
+        assert!(miniz.check_member(&set_viewer, doc123, user_dan));
+    }
+
+    #[test]
+    fn test_deny_relation() {
+        for optimized in [false, true].iter().copied() {
+            let mut miniz_builder = MiniZ::builder();
+            let set_parent = miniz_builder.new_relationship("parent").build();
+            let set_banned = miniz_builder
+                .new_relationship("banned")
+                .with_inherited_set(&set_parent)
+                .build();
+            let set_owner = miniz_builder.new_relationship("owner").build();
+            let set_editor = miniz_builder
+                .new_relationship("editor")
+                .with_subset(&set_owner)
+                .build();
+            let set_viewer = miniz_builder
+                .new_relationship("viewer")
+                .with_subset(&set_editor)
+                .with_inherited_set(&set_parent)
+                .with_deny_relation(&set_banned)
+                .build();
+            let set_commenter = miniz_builder
+                .new_relationship("commenter")
+                .with_subset(&set_viewer)
+                .build();
+            let mut miniz = if optimized {
+                miniz_builder.build_optimized().unwrap()
+            } else {
+                miniz_builder.build().unwrap()
+            };
+
+            let dir1 = ObjectId("dir1");
+            let doc123 = ObjectId("doc123");
+            let alice = UserId("alice");
+            let bob = UserId("bob");
+            miniz.write_object(&set_parent, dir1, doc123);
+            miniz.write_user(&set_viewer, doc123, alice);
+            miniz.write_user(&set_owner, doc123, alice);
+            miniz.write_user(&set_viewer, dir1, bob);
+            assert!(miniz.check_member(&set_viewer, doc123, alice));
+            assert!(miniz.check_member(&set_commenter, doc123, alice));
+            assert!(miniz.check_member(&set_viewer, doc123, bob));
+
+            /*
+             * Banning "alice" from the folder denies her on the document in
+             * it, despite her direct grants, including where "viewer" is
+             * reached from another relationship.  It doesn't affect
+             * relationships that don't deny "banned" themselves.
+             */
+            miniz.write_user(&set_banned, dir1, alice);
+            assert!(!miniz.check_member(&set_viewer, dir1, alice));
+            assert!(!miniz.check_member(&set_viewer, doc123, alice));
+            assert!(!miniz.check_member(&set_commenter, doc123, alice));
+            assert!(miniz.check_member(&set_editor, doc123, alice));
+            assert!(miniz.check_member(&set_owner, doc123, alice));
+            assert!(miniz.check_member(&set_viewer, doc123, bob));
+            assert!(miniz.check_member(&set_commenter, doc123, bob));
+            assert!(miniz.relation_reachable(&set_viewer, &set_banned));
+
+            /* The denial survives saving and renaming. */
+            let mut bytes = Vec::new();
+            miniz.write_binary(&mut bytes).unwrap();
+            let mut loaded =
+                MiniZ::<ObjectId, UserId>::read_binary(bytes.as_slice())
+                    .unwrap();
+            assert!(loaded == miniz);
+            assert!(!loaded.check_member(&set_viewer, doc123, alice));
+            loaded.rename_relation(&set_banned, "blocked").unwrap();
+            assert!(!loaded.check_member(&set_viewer, doc123, alice));
+
+            /* Lifting the ban restores access. */
+            miniz.remove_user(&set_banned, dir1, alice);
+            assert!(miniz.check_member(&set_viewer, doc123, alice));
+            assert!(miniz.check_member(&set_commenter, doc123, alice));
+        }
+    }
+
+    #[test]
+    fn test_effective_users_denied() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_banned = miniz_builder
+            .new_relationship("banned")
+            .with_inherited_set(&set_parent)
+            .build();
+        let set_editor = miniz_builder.new_relationship("editor").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_editor)
+            .with_inherited_set(&set_parent)
+            .with_deny_relation(&set_banned)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        let carol = UserId("carol");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_viewer, doc123, alice);
+        miniz.write_user(&set_editor, doc123, alice);
+        miniz.write_user(&set_viewer, dir1, bob);
+        miniz.write_user(&set_editor, doc123, carol);
+        miniz.write_user(&set_banned, dir1, alice);
+
+        /*
+         * "alice" is banned from "doc123" by way of "dir1", so she's not among
+         * its viewers, even though she's a direct viewer and an editor.
+         */
+        let viewers = miniz.list_effective_users(&set_viewer, &doc123);
+        assert_eq!(viewers, BTreeSet::from([bob, carol]));
+        for user in [alice, bob, carol].iter().copied() {
+            assert_eq!(
+                viewers.contains(&user),
+                miniz.check_member(&set_viewer, doc123, user)
+            );
+        }
+        assert_eq!(
+            miniz.relation_intersection(&set_viewer, &set_editor, &doc123),
+            BTreeSet::from([carol])
+        );
+        assert_eq!(
+            miniz.relation_union(&set_viewer, &set_editor, &doc123),
+            BTreeSet::from([alice, bob, carol])
+        );
+        assert_eq!(
+            miniz.relation_difference(&set_editor, &set_viewer, &doc123),
+            BTreeSet::from([alice])
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 10),
+            CountHint::Exact(2)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 2),
+            CountHint::Exact(2)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 1),
+            CountHint::AtLeast(1)
+        );
+
+        /* Lifting the ban puts her back. */
+        miniz.remove_user(&set_banned, dir1, alice);
+        assert_eq!(
+            miniz.list_effective_users(&set_viewer, &doc123),
+            BTreeSet::from([alice, bob, carol])
+        );
+    }
+
+    #[test]
+    fn test_schema_str() {
+        let schema = "
+            # Figure 1 of the Zanzibar paper
+            owner
+            parent
+            editor: owner
+            viewer: editor | parent->viewer
+        ";
+        let mut miniz =
+            MiniZBuilder::<ObjectId, UserId>::from_schema_str(schema)
+                .unwrap()
+                .build()
+                .unwrap();
+        let ex = doc_example();
+        for tuple in doc_example_tuples(&ex) {
+            miniz.write_tuple(tuple);
+        }
+        assert!(miniz == ex.miniz);
+        assert_matches_doc_example(&miniz);
+
+        /* Forward references and spacing variations are fine. */
+        let miniz = MiniZBuilder::<ObjectId, UserId>::from_schema_str(
+            "viewer:editor|parent -> editor # comment\neditor:\nparent\n",
+        )
+        .unwrap()
+        .build()
+        .unwrap();
+        assert!(miniz.relation_reachable(
+            &RelationshipId("viewer".to_owned()),
+            &RelationshipId("editor".to_owned())
+        ));
+
+        let bad = [
+            ("viewer: editor |", ParseError::Syntax { line: 1 }),
+            ("viewer editor", ParseError::Syntax { line: 1 }),
+            ("a\n\nb: ->a", ParseError::Syntax { line: 3 }),
+            ("a: b-c", ParseError::Syntax { line: 1 }),
+            (
+                "a\na: a",
+                ParseError::Duplicate { line: 2, name: "a".to_owned() },
+            ),
+            (
+                "a\nb: a | c",
+                ParseError::UnknownRelationship {
+                    line: 2,
+                    name: "c".to_owned(),
+                },
+            ),
+            (
+                "a: c->a",
+                ParseError::UnknownRelationship {
+                    line: 1,
+                    name: "c".to_owned(),
+                },
+            ),
+        ];
+        for (schema, error) in bad.iter() {
+            assert_eq!(
+                MiniZBuilder::<ObjectId, UserId>::from_schema_str(schema)
+                    .unwrap_err(),
+                *error
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_writes() {
+        let DocExample { mut miniz, set_owner, set_parent, set_viewer, .. } =
+            doc_example_schema_built(|mut miniz_builder| {
+                miniz_builder
+                    .new_relationship("folder")
+                    .expect_objects()
+                    .build();
+                miniz_builder.build()
+            });
+        let set_folder = RelationshipId("folder".to_owned());
+        let set_missing = RelationshipId("missing".to_owned());
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        let tuple = |rid: &RelationshipId, parent, member| Tuple {
+            rid: rid.clone(),
+            parent,
+            member,
+        };
+
+        let writes = vec![
+            Write::Insert(tuple(&set_owner, dir1, Member::User(alice))),
+            Write::Insert(tuple(&set_missing, dir1, Member::User(alice))),
+            Write::Insert(tuple(&set_parent, dir1, Member::Object(doc123))),
+            Write::Insert(tuple(&set_owner, dir1, Member::User(alice))),
+            Write::Insert(tuple(&set_folder, dir1, Member::User(bob))),
+            Write::Delete(tuple(&set_viewer, dir1, Member::User(bob))),
+            Write::Delete(tuple(&set_missing, dir1, Member::User(bob))),
+            Write::Insert(tuple(&set_viewer, doc123, Member::User(bob))),
+            Write::Delete(tuple(&set_owner, dir1, Member::User(alice))),
+        ];
+
+        /* All-or-nothing: one bad write means nothing is written. */
+        let version = miniz.version();
+        assert_eq!(
+            miniz.apply_writes(&writes),
+            Err(WriteError::NoSuchRelationship(set_missing.clone()))
+        );
+        assert_eq!(miniz.version(), version);
+        assert_eq!(miniz.stats().num_tuples, 0);
+
+        /* Best effort: the valid writes are applied regardless. */
+        assert_eq!(
+            miniz.apply_writes_besteffort(&writes),
+            vec![
+                Ok(true),
+                Err(WriteError::NoSuchRelationship(set_missing.clone())),
+                Ok(true),
+                Ok(false),
+                Err(WriteError::UnexpectedMemberKind(set_folder.clone())),
+                Ok(false),
+                Err(WriteError::NoSuchRelationship(set_missing.clone())),
+                Ok(true),
+                Ok(true),
+            ]
+        );
+        assert!(miniz.check_invariants().is_ok());
+        assert_eq!(miniz.stats().num_tuples, 2);
+        assert!(!miniz.check_member(&set_owner, dir1, alice));
+        assert!(miniz.check_member(&set_viewer, doc123, bob));
+
+        /* Valid batches are applied in full. */
+        let valid = writes
+            .into_iter()
+            .filter(|write| miniz.validate_write(write).is_ok())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            miniz.apply_writes(&valid),
+            Ok(vec![true, false, false, false, false, true])
+        );
+        assert!(!miniz.check_member(&set_owner, dir1, alice));
+        assert_eq!(
+            miniz.set_list_direct_object_members(&set_parent, &dir1),
+            vec![&doc123]
+        );
+    }
+
+    #[test]
+    fn test_load_tuples() {
+        let ex = doc_example_schema();
+        let tuples = doc_example_tuples(&ex);
+        let mut miniz = ex.miniz;
+        miniz.enable_log();
+        miniz.load_tuples(tuples.iter().cloned());
+        assert_matches_doc_example(&miniz);
+        assert_eq!(miniz.version(), 12);
+
+        /* Loading on top of existing tuples merges with them. */
+        let ex = doc_example_schema();
+        let mut miniz = ex.miniz;
+        let (first, rest) = tuples.split_at(5);
+        miniz.load_tuples(first.iter().cloned());
+        assert_eq!(miniz.check_invariants(), Ok(()));
+        miniz.load_tuples(rest.iter().cloned());
+        assert_matches_doc_example(&miniz);
+
+        /* Loading nothing changes nothing. */
+        miniz.load_tuples(Vec::new());
+        assert_matches_doc_example(&miniz);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_load_tuples_duplicate() {
+        let ex = doc_example_schema();
+        let mut tuples = doc_example_tuples(&ex);
+        tuples.push(tuples[0].clone());
+        let mut miniz = ex.miniz;
+        miniz.load_tuples(tuples);
+    }
+
+    #[test]
+    fn test_eq() {
+        fn hash(miniz: &MiniZ<ObjectId, UserId>) -> u64 {
+            let mut hasher = DefaultHasher::new();
+            miniz.hash(&mut hasher);
+            hasher.finish()
+        }
+
+        /* MiniZ doesn't implement Debug, so we can't use assert_eq!() here. */
+        let DocExample { miniz: mut miniz1, set_owner, .. } = doc_example();
+        let DocExample { miniz: mut miniz2, .. } = doc_example();
+        assert!(miniz1 == miniz2);
+        assert_eq!(hash(&miniz1), hash(&miniz2));
+
+        /* The same tuples written in a different order are equal. */
+        let ex = doc_example_schema();
+        let mut tuples = doc_example_tuples(&ex);
+        tuples.reverse();
+        let mut miniz3 = ex.miniz;
+        miniz3.enable_log();
+        miniz3.load_tuples(tuples);
+        assert!(miniz1 == miniz3);
+        assert_eq!(hash(&miniz1), hash(&miniz3));
+
+        /* Changing the tuples makes them unequal... */
+        let dir1 = ObjectId("dir1");
+        let user_mallory = UserId("mallory");
+        miniz2.write_user(&set_owner, dir1, user_mallory);
+        assert!(miniz1 != miniz2);
+        assert_ne!(hash(&miniz1), hash(&miniz2));
+
+        /* ... until they're changed back, even though the versions differ. */
+        assert!(miniz2.remove_user(&set_owner, dir1, user_mallory));
+        assert!(miniz1 == miniz2);
+        assert_ne!(miniz1.version(), miniz2.version());
+
+        /* Relationships configured differently are unequal. */
+        miniz1 = MiniZ::builder().build().unwrap();
+        let mut miniz_builder = MiniZ::builder();
+        miniz_builder.new_relationship("owner").build();
+        miniz2 = miniz_builder.build().unwrap();
+        assert!(miniz1 != miniz2);
+        let mut miniz_builder = MiniZ::builder();
+        miniz_builder
+            .new_relationship("owner")
+            .with_description("owner")
+            .build();
+        miniz1 = miniz_builder.build().unwrap();
+        assert!(miniz1 != miniz2);
+    }
+
+    #[test]
+    fn test_shortest_grant_path() {
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_carol = UserId("carol");
+        let user_faythe = UserId("faythe");
+        let user_oscar = UserId("oscar");
+
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_oscar),
+            None
+        );
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_faythe),
+            Some(vec![CheckStep::Direct {
+                rid: set_viewer.clone(),
+                object: doc123
+            }])
+        );
+
+        /*
+         * Alice is an owner of "dir1", so she's a viewer of "doc123" by way of
+         * three steps: "viewer" is inherited from "dir1", "viewer" includes
+         * "editor", and "editor" includes "owner".
+         */
+        let long_path = vec![
+            CheckStep::Inherited {
+                rid: set_viewer.clone(),
+                object: doc123,
+                via: set_parent.clone(),
+                parent: dir1,
+                parent_rid: set_viewer.clone(),
+            },
+            CheckStep::Subset {
+                rid: set_viewer.clone(),
+                object: dir1,
+                subrid: set_editor.clone(),
+            },
+            CheckStep::Subset {
+                rid: set_editor.clone(),
+                object: dir1,
+                subrid: set_owner.clone(),
+            },
+            CheckStep::Direct { rid: set_owner.clone(), object: dir1 },
+        ];
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_alice),
+            Some(long_path.clone())
+        );
+
+        /* Making her an editor of "doc123" gives her a shorter path. */
+        miniz.write_user(&set_editor, doc123, user_alice);
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_alice),
+            Some(vec![
+                CheckStep::Subset {
+                    rid: set_viewer.clone(),
+                    object: doc123,
+                    subrid: set_editor.clone(),
+                },
+                CheckStep::Direct { rid: set_editor.clone(), object: doc123 },
+            ])
+        );
+        assert!(miniz.remove_user(&set_editor, doc123, user_alice));
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_alice),
+            Some(long_path)
+        );
+
+        /*
+         * Carol is a viewer of "dir1".  Make her an owner of "doc123", too.
+         * A depth-first search that tries subsets first would find the longer
+         * path through "owner", but the shortest one is inherited.
+         */
+        miniz.write_user(&set_owner, doc123, user_carol);
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_carol),
+            Some(vec![
+                CheckStep::Inherited {
+                    rid: set_viewer.clone(),
+                    object: doc123,
+                    via: set_parent.clone(),
+                    parent: dir1,
+                    parent_rid: set_viewer.clone(),
+                },
+                CheckStep::Direct { rid: set_viewer.clone(), object: dir1 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_shortest_grant_path_usersets() {
+        let mut miniz_builder = MiniZ::builder();
+        let member_builder = miniz_builder.new_relationship("member");
+        let member_id = member_builder.id();
+        let set_member =
+            member_builder.with_userset_member_relation(&member_id).build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_userset_member_relation(&set_member)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let group1 = ObjectId("group1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        miniz.write_user(&set_member, group1, user_alice);
+        miniz.write_object(&set_viewer, doc123, group1);
+        assert_eq!(
+            miniz.shortest_grant_path(&set_viewer, doc123, user_alice),
+            Some(vec![
+                CheckStep::UsersetMember {
+                    rid: set_viewer.clone(),
+                    object: doc123,
+                    group: group1,
+                    member_rid: set_member.clone(),
+                },
+                CheckStep::Direct { rid: set_member.clone(), object: group1 },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_walk() {
+        #[derive(Default)]
+        struct Counter {
+            direct_members: usize,
+            subsets: usize,
+            inherited: usize,
+            stop_after: Option<usize>,
+        }
+
+        impl CheckVisitor<ObjectId, UserId> for Counter {
+            fn visit_direct_member(
+                &mut self,
+                _rid: &RelationshipId,
+                _object: &ObjectId,
+                _member: &Member<ObjectId, UserId>,
+            ) -> WalkAction {
+                self.direct_members += 1;
+                if Some(self.direct_members) == self.stop_after {
+                    WalkAction::Stop
+                } else {
+                    WalkAction::Continue
+                }
+            }
+
+            fn enter_subset(
+                &mut self,
+                _rid: &RelationshipId,
+                _object: &ObjectId,
+                _subrid: &RelationshipId,
+            ) -> WalkAction {
+                self.subsets += 1;
+                WalkAction::Continue
+            }
+
+            fn enter_inherited(
+                &mut self,
+                _rid: &RelationshipId,
+                _object: &ObjectId,
+                _via: &RelationshipId,
+                _parent: &ObjectId,
+                _parent_rid: &RelationshipId,
+            ) -> WalkAction {
+                self.inherited += 1;
+                WalkAction::Skip
+            }
+        }
+
+        let DocExample { miniz, set_owner, set_viewer, .. } = doc_example();
+        let doc123 = ObjectId("doc123");
+
+        /*
+         * "viewer" on "doc123" descends into "editor" and "owner" on "doc123"
+         * and skips the inherited "viewer" on "dir1".  Each of the three sets
+         * visited has one direct member.
+         */
+        let mut counter = Counter::default();
+        assert!(miniz.walk(&set_viewer, &doc123, &mut counter));
+        assert_eq!(counter.direct_members, 3);
+        assert_eq!(counter.subsets, 2);
+        assert_eq!(counter.inherited, 1);
+
+        /* Stopping early skips everything after that point. */
+        let mut counter = Counter { stop_after: Some(2), ..Counter::default() };
+        assert!(!miniz.walk(&set_viewer, &doc123, &mut counter));
+        assert_eq!(counter.direct_members, 2);
+        assert_eq!(counter.subsets, 1);
+        assert_eq!(counter.inherited, 0);
+
+        /* "owner" has no subsets and inherits nothing. */
+        let mut counter = Counter::default();
+        assert!(miniz.walk(&set_owner, &doc123, &mut counter));
+        assert_eq!(counter.direct_members, 1);
+        assert_eq!(counter.subsets, 0);
+        assert_eq!(counter.inherited, 0);
+
+        /* Following the inherited set picks up all of the users. */
+        let users = miniz.list_effective_users(&set_viewer, &doc123);
+        let expected = ["alice", "bob", "carol", "dan", "eve", "faythe"];
+        assert_eq!(
+            users.into_iter().collect::<Vec<_>>(),
+            expected.iter().copied().map(UserId).collect::<Vec<_>>()
+        );
+        for user in expected.iter().copied() {
+            assert!(miniz.check_member(&set_viewer, doc123, UserId(user)));
+        }
+    }
+
+    #[test]
+    fn test_same_id_types() {
+        /*
+         * Groups and users share one id space here.  "alice" is both a user
+         * and the name of an object (say, her personal group), and "bob" is
+         * only the name of an object (a subgroup of "eng").
+         */
+        let mut miniz_builder = MiniZ::<String, String>::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_member = miniz_builder
+            .new_relationship("member")
+            .with_subset(&set_owner)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let eng = String::from("eng");
+        let alice = String::from("alice");
+        let bob = String::from("bob");
+        miniz.write_user(&set_member, eng.clone(), alice.clone());
+        miniz.write_user(&set_owner, eng.clone(), alice.clone());
+        miniz.write_object(&set_member, eng.clone(), bob.clone());
+
+        assert!(miniz.check_member(&set_member, eng.clone(), alice.clone()));
+        assert!(miniz.check_member(&set_owner, eng.clone(), alice.clone()));
+        assert!(!miniz.check_member(&set_member, eng.clone(), bob.clone()));
+        assert!(!miniz.set_contains_user_directly(
+            &set_member,
+            &eng,
+            bob.clone()
+        ));
+        assert!(miniz.set_contains_object_directly(
+            &set_member,
+            &eng,
+            bob.clone()
+        ));
+        assert!(!miniz.set_contains_object_directly(
+            &set_member,
+            &eng,
+            alice.clone()
+        ));
+
+        /* The reverse index keeps users and objects apart. */
+        assert_eq!(miniz.user_lookup_memberships(alice.clone()).len(), 2);
+        assert!(miniz.object_lookup_memberships(alice.clone()).is_empty());
+        assert!(miniz.user_lookup_memberships(bob.clone()).is_empty());
+        assert_eq!(
+            miniz.object_lookup_memberships(bob.clone()),
+            vec![&Membership { rid: set_member.clone(), object: eng.clone() }]
+        );
+
+        /*
+         * Removing the user "bob" does nothing, since only the object "bob" is
+         * a member.  Removing the user "alice" leaves her an owner (and so
+         * still a member).
+         */
+        let members = miniz.set_list_direct_members(&set_member, &eng);
+        assert!(members.contains(&&Member::user(alice.clone())));
+        assert!(members.contains(&&Member::object(bob.clone())));
+        assert!(!members.contains(&&Member::object(alice.clone())));
+        assert!(miniz.remove_user(&set_member, eng.clone(), alice.clone()));
+        assert!(!miniz.remove_user(&set_member, eng.clone(), bob.clone()));
+        assert!(miniz.check_member(&set_member, eng.clone(), alice));
+        assert!(miniz.set_contains_object_directly(&set_member, &eng, bob));
+        assert!(miniz.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_lookup_memberships_ref() {
+        let mut miniz_builder = MiniZ::<String, String>::builder();
+        let set_member = miniz_builder.new_relationship("member").build();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let mut miniz = miniz_builder.build().unwrap();
+        miniz.write_user(
+            &set_member,
+            String::from("eng"),
+            String::from("alice"),
+        );
+        miniz.write_user(
+            &set_owner,
+            String::from("eng"),
+            String::from("alice"),
+        );
+        miniz.write_object(
+            &set_member,
+            String::from("eng"),
+            String::from("web"),
+        );
+
+        let alice = String::from("alice");
+        let web = String::from("web");
+        let nobody = String::from("nobody");
+        let found = miniz.user_lookup_memberships_ref(&alice);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found, miniz.user_lookup_memberships(alice.clone()));
+        let found = miniz.object_lookup_memberships_ref(&web);
+        assert_eq!(
+            found,
+            vec![&Membership {
+                rid: set_member.clone(),
+                object: String::from("eng")
+            }]
+        );
+        assert_eq!(found, miniz.object_lookup_memberships(web.clone()));
+
+        /* Users and objects are still kept apart. */
+        assert!(miniz.object_lookup_memberships_ref(&alice).is_empty());
+        assert!(miniz.user_lookup_memberships_ref(&web).is_empty());
+        assert!(miniz.user_lookup_memberships_ref(&nobody).is_empty());
+    }
+
+    #[test]
+    fn test_rename_relation() {
+        let mut ex = doc_example_schema();
+        ex.miniz.enable_log();
+        for tuple in doc_example_tuples(&ex) {
+            ex.miniz.write_tuple(tuple);
+        }
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = ex;
+        let doc123 = ObjectId("doc123");
+        let user_carol = UserId("carol");
+        let user_eve = UserId("eve");
+        let user_faythe = UserId("faythe");
+        let user_grace = UserId("grace");
+        miniz.write_user(&set_viewer, doc123, user_grace);
+
+        assert_eq!(
+            miniz.rename_relation(&set_viewer, "editor"),
+            Err(RenameError::AlreadyExists(set_editor.clone()))
+        );
+        let set_reader = miniz.rename_relation(&set_viewer, "reader").unwrap();
+        assert_eq!(
+            miniz.rename_relation(&set_viewer, "viewer2"),
+            Err(RenameError::NoSuchRelationship(set_viewer.clone()))
+        );
+
+        /* Direct, implied, and inherited relationships all still work. */
+        assert!(miniz.check_member(&set_reader, doc123, user_faythe));
+        assert!(miniz.check_member(&set_reader, doc123, user_eve));
+        assert!(miniz.check_member(&set_reader, doc123, user_carol));
+        assert!(miniz.relation_reachable(&set_reader, &set_owner));
+        assert!(miniz
+            .user_lookup_memberships(user_faythe)
+            .iter()
+            .all(|m| m.rid == set_reader));
+        assert!(miniz.check_invariants().is_ok());
+
+        /* So do the relationships that are inherited through another one. */
+        let set_parent = miniz.rename_relation(&set_parent, "folder").unwrap();
+        assert!(miniz.check_member(&set_reader, doc123, user_carol));
+        assert_eq!(
+            miniz.object_lookup_memberships(doc123),
+            vec![&Membership { rid: set_parent, object: ObjectId("dir1") }]
+        );
+
+        /* The log refers to the new name, too. */
+        let replayed = miniz.replay_to(miniz.version());
+        assert!(replayed.check_member(&set_reader, doc123, user_grace));
+        assert!(replayed == miniz);
+    }
+
+    #[test]
+    #[should_panic(expected = "no such set")]
+    fn test_rename_relation_old_id() {
+        let DocExample { mut miniz, set_viewer, .. } = doc_example();
+        miniz.rename_relation(&set_viewer, "reader").unwrap();
+        miniz.check_member(&set_viewer, ObjectId("doc123"), UserId("faythe"));
+    }
+
+    #[test]
+    fn test_relation_algebra() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let users = |names: &[&'static str]| {
+            names.iter().copied().map(UserId).collect::<BTreeSet<_>>()
+        };
+
+        assert_eq!(
+            miniz.relation_difference(&set_editor, &set_owner, &dir1),
+            users(&["bob"])
+        );
+        assert_eq!(
+            miniz.relation_difference(&set_viewer, &set_editor, &doc123),
+            users(&["alice", "bob", "carol", "faythe"])
+        );
+        assert!(miniz
+            .relation_difference(&set_owner, &set_viewer, &doc123)
+            .is_empty());
+
+        assert_eq!(
+            miniz.relation_intersection(&set_viewer, &set_editor, &dir1),
+            users(&["alice", "bob"])
+        );
+        assert!(miniz
+            .relation_intersection(&set_owner, &set_parent, &dir1)
+            .is_empty());
+
+        assert_eq!(
+            miniz.relation_union(&set_owner, &set_editor, &doc123),
+            users(&["dan", "eve"])
+        );
+        assert_eq!(
+            miniz.relation_union(&set_owner, &set_parent, &dir1),
+            users(&["alice"])
+        );
+    }
+
+    #[test]
+    fn test_effective_user_count_hint() {
+        let DocExample { mut miniz, set_viewer, .. } = doc_example();
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+
+        /* "doc123" has six viewers: three direct and three inherited. */
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 100),
+            CountHint::Exact(6)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 7),
+            CountHint::Exact(6)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 6),
+            CountHint::Exact(6)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 5),
+            CountHint::AtLeast(5)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 3),
+            CountHint::AtLeast(3)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc123, 0),
+            CountHint::AtLeast(0)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &ObjectId("dir3"), 0),
+            CountHint::Exact(0)
+        );
+
+        /* Share "doc456" with a lot of people. */
+        let names = (0..500)
+            .map(|i| &*Box::leak(format!("user{}", i).into_boxed_str()))
+            .collect::<Vec<&'static str>>();
+        for name in &names {
+            miniz.write_user(&set_viewer, doc456, UserId(name));
+        }
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc456, 100),
+            CountHint::AtLeast(100)
+        );
+        assert_eq!(
+            miniz.effective_user_count_hint(&set_viewer, &doc456, 1000),
+            CountHint::Exact(504)
+        );
+        assert_eq!(miniz.list_effective_users(&set_viewer, &doc456).len(), 504);
+    }
+
+    #[test]
+    fn test_check_member_instrumented() {
+        let DocExample { miniz, set_owner, set_viewer, .. } = doc_example();
+        let doc123 = ObjectId("doc123");
+
+        /*
+         * Carol is a viewer of "dir1".  Finding that out means looking for her
+         * in "viewer", "editor", and "owner" on "doc123" (descending through
+         * two subsets to get there) and then in "viewer" on "dir1" (descending
+         * through the inherited set).  The deepest we get is "owner" on
+         * "doc123".
+         */
+        assert_eq!(
+            miniz.check_member_instrumented(
+                &set_viewer,
+                doc123,
+                UserId("carol")
+            ),
+            (
+                true,
+                CheckMetrics {
+                    direct_member_lookups: 4,
+                    subset_descents: 2,
+                    userset_descents: 0,
+                    inherited_descents: 1,
+                    max_depth: 3,
+                }
+            )
+        );
+
+        /*
+         * Looking for Alice also goes through "editor" and "owner" on "dir1"
+         * before finding her.
+         */
+        assert_eq!(
+            miniz.check_member_instrumented(
+                &set_viewer,
+                doc123,
+                UserId("alice")
+            ),
+            (
+                true,
+                CheckMetrics {
+                    direct_member_lookups: 6,
+                    subset_descents: 4,
+                    userset_descents: 0,
+                    inherited_descents: 1,
+                    max_depth: 4,
+                }
+            )
+        );
+
+        /* A direct member is found right away. */
+        assert_eq!(
+            miniz.check_member_instrumented(&set_owner, doc123, UserId("dan")),
+            (
+                true,
+                CheckMetrics {
+                    direct_member_lookups: 1,
+                    max_depth: 1,
+                    ..CheckMetrics::default()
+                }
+            )
+        );
+
+        /* A denial has to look everywhere. */
+        let (found, metrics) = miniz.check_member_instrumented(
+            &set_viewer,
+            doc123,
+            UserId("oscar"),
+        );
+        assert!(!found);
+        assert_eq!(metrics.direct_member_lookups, 6);
+    }
+
+    #[test]
+    fn test_union() {
+        /*
+         * Viewers of a document are its direct viewers plus the editors of
+         * its folder.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder.new_relationship("editor").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_union(vec![
+                UsersetRule::This,
+                UsersetRule::TupleToUserset {
+                    tupleset: set_parent.clone(),
+                    computed_userset: set_editor.clone(),
+                },
+            ])
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_bob = UserId("bob");
+        let user_carol = UserId("carol");
+        let user_faythe = UserId("faythe");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_editor, dir1, user_bob);
+        miniz.write_user(&set_viewer, dir1, user_carol);
+        miniz.write_user(&set_viewer, doc123, user_faythe);
+
+        assert!(miniz.check_member(&set_viewer, doc123, user_faythe));
+        assert!(miniz.check_member(&set_viewer, doc123, user_bob));
+        assert!(!miniz.check_member(&set_editor, doc123, user_bob));
+        /* Only "editor" is inherited from the folder, not "viewer". */
+        assert!(!miniz.check_member(&set_viewer, doc123, user_carol));
+        assert!(miniz.check_member(&set_viewer, dir1, user_carol));
+        assert!(!miniz.check_member(&set_viewer, dir1, user_bob));
+
+        /* A computed userset works the same as with_computed_userset(). */
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_union(vec![UsersetRule::ComputedUserset(set_owner.clone())])
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+        miniz.write_user(&set_owner, doc123, user_bob);
+        assert!(miniz.check_member(&set_editor, doc123, user_bob));
+        assert!(miniz.relation_reachable(&set_editor, &set_owner));
+    }
+
+    fn expected_kinds_schema(
+    ) -> (MiniZ<ObjectId, UserId>, RelationshipId, RelationshipId) {
+        let mut miniz_builder = MiniZ::builder();
+        let set_parent =
+            miniz_builder.new_relationship("parent").expect_objects().build();
+        let set_owner =
+            miniz_builder.new_relationship("owner").expect_users().build();
+        (miniz_builder.build().unwrap(), set_parent, set_owner)
+    }
+
+    #[test]
+    fn test_expected_member_kinds() {
+        let (mut miniz, set_parent, set_owner) = expected_kinds_schema();
+        miniz.write_object(&set_parent, ObjectId("dir1"), ObjectId("doc123"));
+        miniz.write_user(&set_owner, ObjectId("doc123"), UserId("dan"));
+        miniz.load_tuples(vec![Tuple {
+            rid: set_parent.clone(),
+            parent: ObjectId("dir2"),
+            member: Member::Object(ObjectId("doc456")),
+        }]);
+        assert_eq!(miniz.stats().num_tuples, 3);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "relationship \"parent\" only has object members"
+    )]
+    fn test_expect_objects() {
+        let (mut miniz, set_parent, _) = expected_kinds_schema();
+        miniz.write_user(&set_parent, ObjectId("dir1"), UserId("alice"));
+    }
+
+    #[test]
+    #[should_panic(expected = "relationship \"owner\" only has user members")]
+    fn test_expect_users() {
+        let (mut miniz, _, set_owner) = expected_kinds_schema();
+        miniz.load_tuples(vec![Tuple {
+            rid: set_owner,
+            parent: ObjectId("dir1"),
+            member: Member::Object(ObjectId("doc123")),
+        }]);
+    }
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        let mut bytes = Vec::new();
+        miniz.write_binary(&mut bytes).unwrap();
+        let loaded =
+            MiniZ::<ObjectId, UserId>::read_binary(bytes.as_slice()).unwrap();
+        assert!(loaded == miniz);
+        assert_matches_doc_example(&loaded);
+        assert!(loaded.check_invariants().is_ok());
+
+        let objects = miniz.list_objects(None, usize::MAX);
+        let users = ["alice", "bob", "carol", "dan", "eve", "faythe", "oscar"];
+        for rid in [&set_owner, &set_parent, &set_editor, &set_viewer] {
+            for object in &objects {
+                for user in users.iter().copied().map(UserId) {
+                    assert_eq!(
+                        loaded.check_member(rid, *object, user),
+                        miniz.check_member(rid, *object, user)
+                    );
+                }
+            }
+        }
+
+        /*
+         * The binary form should be smaller than even a terse textual listing
+         * of the tuples (which JSON would only make bigger).
+         */
+        let text = doc_example_tuples(&doc_example_schema())
+            .iter()
+            .map(|t| format!("{:?}#{:?}@{:?}\n", t.parent.0, t.rid, t.member))
+            .collect::<String>();
+        assert!(bytes.len() < text.len());
+
+        /* Bad input is rejected rather than producing a broken store. */
+        let err = MiniZ::<ObjectId, UserId>::read_binary(&b"MINIX\x01"[..])
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        for len in 0..bytes.len() {
+            assert!(
+                MiniZ::<ObjectId, UserId>::read_binary(&bytes[..len]).is_err()
+            );
+        }
+    }
+
+    #[test]
+    fn test_binary_nested_caveat() {
+        fn nested(depth: usize) -> Caveat {
+            (0..depth).fold(
+                Caveat::Equals { key: "k".to_owned(), value: "v".to_owned() },
+                |caveat, _| Caveat::AnyOf(vec![caveat]),
+            )
+        }
+
+        /* Caveats nested as deeply as allowed survive a round trip. */
+        let mut miniz_builder = MiniZ::builder();
+        miniz_builder.new_caveat("deep", nested(MAX_CAVEAT_DEPTH));
+        let miniz: MiniZ<ObjectId, UserId> = miniz_builder.build().unwrap();
+        let mut bytes = Vec::new();
+        miniz.write_binary(&mut bytes).unwrap();
+        let loaded =
+            MiniZ::<ObjectId, UserId>::read_binary(bytes.as_slice()).unwrap();
+        assert!(loaded == miniz);
+
+        /* Deeper ones can't be written... */
+        let mut miniz_builder = MiniZ::builder();
+        miniz_builder.new_caveat("deep", nested(MAX_CAVEAT_DEPTH + 1));
+        let miniz: MiniZ<ObjectId, UserId> = miniz_builder.build().unwrap();
+        let err = miniz.write_binary(&mut Vec::new()).err().unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+
+        /*
+         * ... and are rejected when read, even when nested far deeper than
+         * the stack could handle if we recursed all the way down.
+         */
+        let mut bytes = b"MINIZ".to_vec();
+        bytes.push(5);
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(b"deep");
+        for _ in 0..1_000_000 {
+            bytes.push(1);
+            bytes.extend_from_slice(&1u32.to_le_bytes());
+        }
+        let err = MiniZ::<ObjectId, UserId>::read_binary(bytes.as_slice())
+            .err()
+            .unwrap();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_jsonl_roundtrip() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        let mut text = Vec::new();
+        miniz.export_jsonl(&mut text).unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert_eq!(text.lines().count(), 12);
+        assert_eq!(
+            text.lines().next().unwrap(),
+            r#"{"object":"dir1","relation":"editor","subject":"user:bob"}"#
+        );
+        assert!(text.contains(
+            r#"{"object":"dir1","relation":"parent","subject":"object:doc123"}"#
+        ));
+
+        let mut imported = doc_example_schema().miniz;
+        assert_eq!(imported.import_jsonl(text.as_bytes()).unwrap(), 12);
+        assert!(imported == miniz);
+        assert!(imported.check_invariants().is_ok());
+        let objects = miniz.list_objects(None, usize::MAX);
+        let users = ["alice", "bob", "carol", "dan", "eve", "faythe", "oscar"];
+        for rid in [&set_owner, &set_parent, &set_editor, &set_viewer] {
+            for object in &objects {
+                for user in users.iter().copied().map(UserId) {
+                    assert_eq!(
+                        imported.check_member(rid, *object, user),
+                        miniz.check_member(rid, *object, user)
+                    );
+                }
+            }
+        }
+
+        /*
+         * Bad input is rejected without changing the store, even if earlier
+         * lines were fine.
+         */
+        let bad_inputs = [
+            (r#"{"object":"a","relation":"commenter","subject":"user:x"}"#, 1),
+            (r#"{"object":"a","relation":"owner","subject":"group:x"}"#, 1),
+            (r#"{"object":"a","relation":"owner"}"#, 1),
+            (
+                r#"{"object":"a","relation":"owner","subject":"user:x","x":""}"#,
+                1,
+            ),
+            (r#"{"object":"a","relation":"owner","subject":"user:x""#, 1),
+            (r#"["a","owner","user:x"]"#, 1),
+            (
+                r#"{"object":"dir1","relation":"owner","subject":"user:alice"}"#,
+                1,
+            ),
+            (
+                concat!(
+                    r#"{"object":"a","relation":"owner","subject":"user:x"}"#,
+                    "\n\n",
+                    r#"{"object":"a","relation":"owner","subject":"user:x"}"#,
+                ),
+                3,
+            ),
+        ];
+        for (input, line) in bad_inputs.iter() {
+            let error = imported.import_jsonl(input.as_bytes()).unwrap_err();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+            assert!(error.to_string().starts_with(&format!("line {}:", line)));
+            assert!(imported == miniz);
+        }
+
+        /* Ids that need escaping survive the round trip. */
+        let mut miniz_builder = MiniZ::<String, String>::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let mut miniz = miniz_builder.build().unwrap();
+        let odd =
+            "quote\" backslash\\ newline\n tab\t bell\u{7} snowman\u{2603}";
+        miniz.write_user(&set_owner, odd.to_owned(), "a:b".to_owned());
+        miniz.write_object(&set_parent, "doc".to_owned(), odd.to_owned());
+        let mut text = Vec::new();
+        miniz.export_jsonl(&mut text).unwrap();
+        let mut imported = miniz.clone();
+        imported.remove_user(&set_owner, odd.to_owned(), "a:b".to_owned());
+        imported.remove_object(&set_parent, "doc".to_owned(), odd.to_owned());
+        imported.import_jsonl(text.as_slice()).unwrap();
+        assert!(imported == miniz);
+
+        /* Escapes and whitespace that we don't produce are accepted, too. */
+        let input = concat!(
+            r#" { "subject" : "user:\u00e9\ud83d\ude00\/" , "#,
+            r#""relation":"owner", "object":"x" } "#
+        );
+        imported.import_jsonl(input.as_bytes()).unwrap();
+        assert!(imported.check_member(
+            &set_owner,
+            "x".to_owned(),
+            "\u{e9}\u{1f600}/".to_owned()
+        ));
+    }
+
+    #[test]
+    fn test_relation_tuples() {
+        let DocExample { miniz, set_owner, set_parent, .. } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+
+        assert_eq!(
+            miniz.relation_tuples(&set_parent),
+            vec![
+                (&dir1, &Member::Object(doc123)),
+                (&dir2, &Member::Object(doc456)),
+            ]
+        );
+        assert_eq!(
+            miniz.relation_tuples(&set_owner),
+            vec![
+                (&dir1, &Member::User(UserId("alice"))),
+                (&dir2, &Member::User(UserId("heidi"))),
+                (&doc123, &Member::User(UserId("dan"))),
+                (&doc456, &Member::User(UserId("oscar"))),
+            ]
+        );
+
+        let DocExample { miniz, set_owner, .. } = doc_example_schema();
+        assert!(miniz.relation_tuples(&set_owner).is_empty());
+    }
+
+    #[test]
+    fn test_approximate_memory_bytes() {
+        let mut miniz_builder = MiniZ::<String, String>::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let mut miniz = miniz_builder.build().unwrap();
+        let empty = miniz.approximate_memory_bytes();
+        assert!(empty >= std::mem::size_of_val(&miniz));
+
+        /* Every tuple added makes the estimate grow. */
+        let mut last = empty;
+        for i in 0..100 {
+            let dir = format!("dir{}", i / 10);
+            let doc = format!("doc{}", i);
+            miniz.write_object(&set_parent, dir, doc.clone());
+            miniz.write_user(&set_owner, doc, format!("user{}", i % 7));
+            let now = miniz.approximate_memory_bytes();
+            assert!(now > last);
+            last = now;
+        }
+
+        /* Longer ids use more memory, and so does the change log. */
+        let mut longer = miniz.clone();
+        longer.write_user(&set_owner, "doc0".to_owned(), "x".repeat(1000));
+        assert!(longer.approximate_memory_bytes() > last + 1000);
+        let mut logged = MiniZ::<String, String>::builder();
+        let set_owner = logged.new_relationship("owner").build();
+        let mut logged = logged.build().unwrap();
+        let mut unlogged = logged.clone();
+        logged.enable_log();
+        for store in [&mut logged, &mut unlogged].iter_mut() {
+            store.write_user(&set_owner, "doc0".to_owned(), "alice".to_owned());
+        }
+        assert!(
+            logged.approximate_memory_bytes()
+                > unlogged.approximate_memory_bytes()
+        );
+    }
+
+    #[test]
+    fn test_iter_tuples() {
+        let ex = doc_example();
+        let mut expected = doc_example_tuples(&ex);
+        expected.sort();
+        let found = ex
+            .miniz
+            .iter_tuples()
+            .map(|t| Tuple {
+                rid: t.rid,
+                parent: *t.parent,
+                member: match t.member {
+                    Member::Object(o) => Member::Object(*o),
+                    Member::User(u) => Member::User(*u),
+                    Member::ObjectRelation { object, relation } => {
+                        Member::ObjectRelation { object: *object, relation }
+                    }
+                },
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(found, expected);
+        assert_eq!(ex.miniz.iter_tuples().count(), ex.miniz.stats().num_tuples);
+
+        /* The iterator is lazy, so it's cheap to look at just a few. */
+        let dir1 = ObjectId("dir1");
+        assert_eq!(
+            ex.miniz
+                .iter_tuples()
+                .filter(|t| t.rid == ex.set_owner)
+                .take(2)
+                .map(|t| *t.parent)
+                .collect::<Vec<_>>(),
+            vec![dir1, ObjectId("dir2")]
+        );
+    }
+
+    #[test]
+    fn test_check_cache() {
+        let DocExample { mut miniz, set_owner, set_viewer, .. } = doc_example();
+        miniz.enable_check_cache();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+
+        /* Alice is a viewer of "doc123" because she owns "dir1". */
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_owner, doc123, user_alice));
+
+        /* Removing the tuple that granted that takes effect immediately. */
+        assert!(miniz.remove_user(&set_owner, dir1, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+
+        /* So do writes, including ones far from the relationship checked. */
+        miniz.write_user(&set_owner, doc123, user_alice);
+        assert!(miniz.check_member(&set_owner, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        miniz.delete_object(&doc123);
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+
+        /* Renaming relationships forgets results for the old names. */
+        miniz.write_user(&set_owner, doc123, user_alice);
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        let set_reader = miniz.rename_relation(&set_viewer, "reader").unwrap();
+        let set_viewer = miniz.rename_relation(&set_owner, "viewer").unwrap();
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_reader, doc123, user_alice));
+        assert!(miniz.remove_user(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_reader, doc123, user_alice));
+    }
+
+    #[test]
+    fn test_check_member_consistency() {
+        let DocExample { mut miniz, set_owner, set_viewer, .. } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let check = |miniz: &MiniZ<ObjectId, UserId>, consistency| {
+            miniz.check_member_consistency(
+                &set_viewer,
+                doc123,
+                user_alice,
+                consistency,
+            )
+        };
+
+        /* Without the cache, there's nothing stale to return. */
+        assert!(check(&miniz, Consistency::MinimizeLatency));
+        assert!(miniz.remove_user(&set_owner, dir1, user_alice));
+        assert!(!check(&miniz, Consistency::MinimizeLatency));
+        miniz.write_user(&set_owner, dir1, user_alice);
+
+        /* Alice is a viewer of "doc123" because she owns "dir1". */
+        miniz.enable_check_cache();
+        assert!(check(&miniz, Consistency::Strong));
+        assert!(check(&miniz, Consistency::MinimizeLatency));
+
+        /*
+         * After revoking that, a strong check reflects the change right away,
+         * but a check that minimizes latency may use the stale result.
+         */
+        assert!(miniz.remove_user(&set_owner, dir1, user_alice));
+        assert!(check(&miniz, Consistency::MinimizeLatency));
+        assert!(!check(&miniz, Consistency::Strong));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+
+        /* The strong check refreshed the cached result. */
+        assert!(!check(&miniz, Consistency::MinimizeLatency));
+
+        /* Changing the schema forgets cached results. */
+        miniz.write_user(&set_owner, dir1, user_alice);
+        assert!(!check(&miniz, Consistency::MinimizeLatency));
+        miniz.add_subset(&set_viewer, &set_owner);
+        assert!(check(&miniz, Consistency::MinimizeLatency));
+    }
+
+    #[test]
+    fn test_check_cache_invalidation() {
+        let DocExample {
+            mut miniz, set_parent, set_editor, set_viewer, ..
+        } = doc_example();
+        miniz.enable_check_cache();
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_dan = UserId("dan");
+        let user_heidi = UserId("heidi");
+        let is_stale = |miniz: &MiniZ<ObjectId, UserId>, object, user| {
+            miniz
+                .check_results
+                .get(&(set_viewer.clone(), object, user))
+                .expect("result was not cached")
+                .stale
+        };
+
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_dan));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_heidi));
+        assert!(miniz.check_member(&set_viewer, doc456, user_heidi));
+
+        /*
+         * Writing "editor" on "doc456" affects checks that looked at it, but
+         * none of the checks on "doc123", which never look at "doc456".
+         */
+        miniz.write_user(&set_editor, doc456, user_bob);
+        assert!(is_stale(&miniz, doc456, user_heidi));
+        assert!(!is_stale(&miniz, doc123, user_alice));
+        assert!(!is_stale(&miniz, doc123, user_dan));
+        assert!(!is_stale(&miniz, doc123, user_heidi));
+        assert!(miniz.check_member(&set_viewer, doc456, user_heidi));
+        assert!(!is_stale(&miniz, doc456, user_heidi));
+
+        /*
+         * Making "dir2" a parent of "doc123" affects "viewer" on "doc123",
+         * which inherits through "parent", even though no check visited
+         * "dir2".  Results for "doc456" are unaffected.
+         */
+        miniz.write_object(&set_parent, dir2, doc123);
+        assert!(is_stale(&miniz, doc123, user_alice));
+        assert!(is_stale(&miniz, doc123, user_dan));
+        assert!(is_stale(&miniz, doc123, user_heidi));
+        assert!(!is_stale(&miniz, doc456, user_heidi));
+        assert!(miniz.check_member(&set_viewer, doc123, user_heidi));
+
+        /* Unaffected results stay valid through a removal, too. */
+        assert!(miniz.remove_user(&set_editor, doc456, user_bob));
+        assert!(!is_stale(&miniz, doc123, user_heidi));
+        assert!(is_stale(&miniz, doc456, user_heidi));
+    }
+
+    #[test]
+    fn test_check_userset() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_member = miniz_builder.new_relationship("member").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_userset_member_relation(&set_member)
+            .with_inherited_set(&set_parent)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let group1 = ObjectId("group1");
+        let group2 = ObjectId("group2");
+        miniz.write_object(&set_viewer, doc123, group1);
+        miniz.write_object(&set_viewer, dir1, group2);
+        miniz.write_object(&set_parent, dir1, doc123);
+
+        /*
+         * "doc123" has viewers "group1#member" and (inherited)
+         * "group2#member".
+         */
+        assert!(miniz.check_userset(
+            &set_viewer,
+            &doc123,
+            &set_member,
+            &group1
+        ));
+        assert!(miniz.check_userset(
+            &set_viewer,
+            &doc123,
+            &set_member,
+            &group2
+        ));
+        assert!(miniz.check_userset(&set_viewer, &doc123, &set_viewer, &dir1));
+        assert!(miniz.check_userset(
+            &set_viewer,
+            &doc123,
+            &set_viewer,
+            &doc123
+        ));
+        assert!(!miniz.check_userset(&set_viewer, &dir1, &set_member, &group1));
+        assert!(!miniz.check_userset(
+            &set_viewer,
+            &doc456,
+            &set_member,
+            &group1
+        ));
+        assert!(!miniz.check_userset(
+            &set_member,
+            &group1,
+            &set_viewer,
+            &doc123
+        ));
+
+        /* This is consistent with checks for the users in the groups. */
+        let user_alice = UserId("alice");
+        miniz.write_user(&set_member, group1, user_alice);
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, dir1, user_alice));
+    }
+
+    #[test]
+    fn test_check_member_subject() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_member = miniz_builder.new_relationship("member").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_userset_member_relation(&set_member)
+            .build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_editor)
+            .with_userset_member_relation(&set_member)
+            .with_inherited_set(&set_parent)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let group1 = ObjectId("group1");
+        let group2 = ObjectId("group2");
+        let group3 = ObjectId("group3");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_object(&set_editor, doc123, group1);
+        miniz.write_object(&set_viewer, dir1, group2);
+        miniz.write_object(&set_member, group2, group3);
+        miniz.write_user(&set_member, group1, alice);
+        miniz.write_user(&set_viewer, doc123, bob);
+
+        let check =
+            |rid, subject| miniz.check_member_subject(rid, &doc123, &subject);
+
+        /* Users behave exactly as with check_member(). */
+        assert!(check(&set_viewer, Member::User(alice)));
+        assert!(check(&set_editor, Member::User(alice)));
+        assert!(check(&set_viewer, Member::User(bob)));
+        assert!(!check(&set_editor, Member::User(bob)));
+
+        /*
+         * Objects have the relationship if they were granted it, whether
+         * directly, via a subset, via inheritance, or via another group.
+         */
+        assert!(check(&set_editor, Member::Object(group1)));
+        assert!(check(&set_viewer, Member::Object(group1)));
+        assert!(check(&set_viewer, Member::Object(group2)));
+        assert!(check(&set_viewer, Member::Object(group3)));
+        assert!(!check(&set_editor, Member::Object(group2)));
+        assert!(!check(&set_viewer, Member::Object(dir1)));
+        assert!(!check(&set_viewer, Member::Object(doc123)));
+    }
+
+    #[test]
+    fn test_lookup_memberships_for() {
+        let DocExample { miniz, .. } = doc_example();
+        let users = ["alice", "carol", "dan", "nobody"];
+        let objects = ["doc123", "doc456", "dir1"];
+        let mut members = users
+            .iter()
+            .map(|u| Member::User(UserId(u)))
+            .chain(objects.iter().map(|o| Member::Object(ObjectId(o))))
+            .collect::<Vec<_>>();
+        members.push(Member::User(UserId("alice")));
+
+        let found = miniz.lookup_memberships_for(&members);
+        assert_eq!(found.len(), users.len() + objects.len());
+        for user in users.iter().copied().map(UserId) {
+            assert_eq!(
+                found[&Member::User(user)],
+                miniz.user_lookup_memberships(user)
+            );
+        }
+        for object in objects.iter().copied().map(ObjectId) {
+            assert_eq!(
+                found[&Member::Object(object)],
+                miniz.object_lookup_memberships(object)
+            );
+        }
+        assert!(found[&Member::User(UserId("nobody"))].is_empty());
+        assert!(found[&Member::Object(ObjectId("dir1"))].is_empty());
+        assert_eq!(found[&Member::User(UserId("carol"))].len(), 1);
+        assert!(miniz.lookup_memberships_for(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_deep_hierarchy() {
+        /*
+         * Build a chain of nested directories deep enough that following it
+         * with one stack frame (or several) per directory would overflow the
+         * stack.
+         */
+        const DEPTH: usize = 50_000;
+        let DocExample { mut miniz, set_parent, set_viewer, .. } =
+            doc_example_schema();
+        let dirs = (0..=DEPTH)
+            .map(|i| ObjectId(Box::leak(format!("dir{}", i).into_boxed_str())))
+            .collect::<Vec<_>>();
+        miniz.load_tuples(dirs.windows(2).map(|pair| Tuple {
+            rid: set_parent.clone(),
+            parent: pair[0],
+            member: Member::Object(pair[1]),
+        }));
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        miniz.write_user(&set_viewer, dirs[0], user_alice);
+
+        assert!(miniz.check_member(&set_viewer, dirs[DEPTH], user_alice));
+        assert!(!miniz.check_member(&set_viewer, dirs[DEPTH], user_bob));
+        let (found, metrics) = miniz.check_member_instrumented(
+            &set_viewer,
+            dirs[DEPTH],
+            user_alice,
+        );
+        assert!(found);
+        assert_eq!(metrics.inherited_descents, DEPTH);
+        /*
+         * The deepest point is "owner" (under "editor") on "dir1", just before
+         * following the last inherited edge to "viewer" on "dir0".
+         */
+        assert_eq!(metrics.max_depth, DEPTH + 2);
+        assert_eq!(
+            miniz.list_effective_users(&set_viewer, &dirs[DEPTH]).len(),
+            1
+        );
+
+        /*
+         * Each directory's "viewer" expands to its "editor" and then its
+         * parent's "viewer", so following the last child of each "viewer"
+         * takes us up the chain to "dir0", where "alice" is.
+         */
+        let tree = miniz.expand(&set_viewer, &dirs[DEPTH]);
+        let mut node = &tree;
+        for dir in dirs.iter().rev().skip(1) {
+            node = node.children.last().unwrap();
+            assert_eq!(node.rid, set_viewer);
+            assert_eq!(node.object, *dir);
+        }
+        assert_eq!(node.members, vec![Member::User(user_alice)]);
+    }
+
+    #[test]
+    fn test_user_memberships_grouped() {
+        let DocExample { mut miniz, set_owner, set_editor, .. } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let user_alice = UserId("alice");
+        miniz.write_user(&set_editor, doc456, user_alice);
+        miniz.write_user(&set_editor, doc123, user_alice);
+
+        let grouped = miniz.user_memberships_grouped(&user_alice);
+        assert_eq!(
+            grouped.into_iter().collect::<Vec<_>>(),
+            vec![
+                (set_editor.clone(), vec![&doc123, &doc456]),
+                (set_owner.clone(), vec![&dir1]),
+            ]
+        );
+        assert!(miniz.user_memberships_grouped(&UserId("nobody")).is_empty());
+    }
+
+    #[test]
+    fn test_run_assertions() {
+        let DocExample { miniz, set_owner, set_editor, set_viewer, .. } =
+            doc_example();
+        let assertion =
+            |rid: &RelationshipId, object, user, expected| Assertion {
+                rid: rid.clone(),
+                object: ObjectId(object),
+                user: UserId(user),
+                expected,
+            };
+        let assertions = vec![
+            assertion(&set_viewer, "doc123", "alice", true),
+            assertion(&set_owner, "doc123", "alice", true),
+            assertion(&set_editor, "doc123", "faythe", false),
+            assertion(&set_viewer, "doc456", "carol", false),
+            assertion(&set_viewer, "doc456", "judy", false),
+        ];
+
+        let report = miniz.run_assertions(&assertions);
+        assert!(!report.is_ok());
+        assert_eq!(
+            report,
+            AssertionReport {
+                passed: 3,
+                failures: vec![
+                    AssertionFailure {
+                        index: 1,
+                        assertion: assertions[1].clone(),
+                        actual: false,
+                    },
+                    AssertionFailure {
+                        index: 4,
+                        assertion: assertions[4].clone(),
+                        actual: true,
+                    },
+                ],
+            }
+        );
+
+        let report = miniz.run_assertions(&assertions[2..4]);
+        assert!(report.is_ok());
+        assert_eq!(report.passed, 2);
+        assert!(miniz.run_assertions(&[]).is_ok());
+    }
+
+    #[test]
+    fn test_add_remove_rules() {
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = doc_example();
+        miniz.enable_check_cache();
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_dan = UserId("dan");
+        let user_eve = UserId("eve");
+
+        /* Owners are no longer implicitly editors (or viewers). */
+        assert!(miniz.check_member(&set_editor, doc123, user_dan));
+        assert!(miniz.remove_subset(&set_editor, &set_owner));
+        assert!(!miniz.remove_subset(&set_editor, &set_owner));
+        assert!(!miniz.check_member(&set_editor, doc123, user_dan));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_dan));
+        assert!(miniz.check_member(&set_owner, doc123, user_dan));
+        assert!(miniz.check_member(&set_viewer, doc123, user_eve));
+
+        /* Viewers of "dir1" are no longer viewers of what's in it. */
+        assert!(miniz.remove_inherited_set(&set_viewer, &set_parent));
+        assert!(!miniz.remove_inherited_set(&set_viewer, &set_parent));
+        assert!(!miniz.check_member(&set_viewer, doc123, UserId("carol")));
+
+        /* Putting the rules back restores the original store. */
+        assert!(miniz.add_subset(&set_editor, &set_owner));
+        assert!(!miniz.add_subset(&set_editor, &set_owner));
+        assert!(miniz.add_inherited_set(&set_viewer, &set_parent));
+        assert!(miniz == doc_example().miniz);
+        assert!(miniz.check_member(&set_viewer, doc123, user_dan));
+
+        /* Rules can also inherit other relationships. */
+        assert!(!miniz.check_member(&set_owner, doc123, user_alice));
+        assert!(miniz.add_inherited_as(&set_owner, &set_parent, &set_owner));
+        assert!(miniz.check_member(&set_owner, doc123, user_alice));
+        assert!(miniz.remove_inherited_as(&set_owner, &set_parent, &set_owner));
+        assert!(!miniz.check_member(&set_owner, doc123, user_alice));
+        assert!(miniz == doc_example().miniz);
+    }
+
+    #[test]
+    fn test_replace_schema() {
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        assert!(!miniz.check_member(&set_editor, doc123, alice));
+
+        /*
+         * The new schema is the same except that editors of a directory are
+         * also editors of everything in it, and there's a new relationship.
+         */
+        let mut miniz_builder = MiniZ::builder();
+        let new_owner = miniz_builder.new_relationship("owner").build();
+        let new_parent = miniz_builder.new_relationship("parent").build();
+        let new_editor = miniz_builder
+            .new_relationship("editor")
+            .with_subset(&new_owner)
+            .with_inherited_set(&new_parent)
+            .build();
+        let new_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&new_editor)
+            .with_inherited_set(&new_parent)
+            .build();
+        let set_commenter = miniz_builder
+            .new_relationship("commenter")
+            .with_subset(&new_viewer)
+            .build();
+        assert_eq!(
+            [&new_owner, &new_parent, &new_editor, &new_viewer],
+            [&set_owner, &set_parent, &set_editor, &set_viewer]
+        );
+        let version = miniz.version();
+        miniz.replace_schema(miniz_builder).unwrap();
+        assert_eq!(miniz.version(), version);
+        assert!(miniz.check_invariants().is_ok());
+
+        /* Existing tuples still resolve, now including the new edge. */
+        assert!(miniz.check_member(&set_editor, doc123, alice));
+        assert!(miniz.check_member(&set_editor, doc123, bob));
+        assert!(miniz.check_member(&set_editor, dir1, bob));
+        assert!(miniz.check_member(&set_viewer, doc123, UserId("carol")));
+        assert!(!miniz.check_member(&set_editor, doc123, UserId("carol")));
+        assert!(miniz.check_member(&set_commenter, doc123, UserId("faythe")));
+        assert_eq!(miniz.stats().num_tuples, 12);
+
+        /*
+         * A schema that drops a relationship that has tuples is rejected, and
+         * the store is left alone.
+         */
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        miniz_builder.new_relationship("owner").build();
+        miniz_builder.new_relationship("parent").build();
+        miniz_builder.new_relationship("viewer").build();
+        assert_eq!(
+            miniz.replace_schema(miniz_builder),
+            Err(SchemaSwapError::MissingRelationship(set_editor.clone()))
+        );
+        assert!(miniz.check_member(&set_editor, doc123, alice));
+
+        /* So is one that disallows members that exist. */
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        miniz_builder.new_relationship("owner").build();
+        miniz_builder.new_relationship("parent").expect_users().build();
+        miniz_builder.new_relationship("editor").build();
+        miniz_builder.new_relationship("viewer").build();
+        assert_eq!(
+            miniz.replace_schema(miniz_builder),
+            Err(SchemaSwapError::UnexpectedMemberKind(set_parent.clone()))
+        );
+        assert!(miniz.check_member(&set_commenter, doc123, UserId("faythe")));
+
+        /* Dropping relationships without tuples is fine. */
+        let mut miniz_builder = MiniZ::builder();
+        for rid in [&set_owner, &set_parent, &set_editor, &set_viewer] {
+            miniz_builder.new_relationship(rid.to_string()).build();
+        }
+        miniz.replace_schema(miniz_builder).unwrap();
+        assert!(!miniz.check_member(&set_editor, doc123, alice));
+        assert!(miniz.check_member(&set_editor, doc123, UserId("eve")));
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let DocExample { mut miniz, set_parent, set_owner, .. } = doc_example();
+        let dir0 = ObjectId("dir0");
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        miniz.write_object(&set_parent, dir0, dir1);
+        /* This isn't a parent relationship, so it's not followed. */
+        miniz.write_object(&set_owner, ObjectId("dir9"), dir1);
+
+        assert_eq!(miniz.ancestors(&set_parent, &doc123), vec![dir1, dir0]);
+        assert_eq!(miniz.ancestors(&set_parent, &dir1), vec![dir0]);
+        assert!(miniz.ancestors(&set_parent, &dir0).is_empty());
+        assert!(miniz.ancestors(&set_owner, &doc123).is_empty());
+
+        /* Cycles are fine. */
+        miniz.write_object(&set_parent, doc123, dir0);
+        assert_eq!(
+            miniz.ancestors(&set_parent, &doc123),
+            vec![dir1, dir0, doc123]
+        );
+    }
+
+    #[test]
+    fn test_descendants() {
+        let DocExample { mut miniz, set_parent, set_owner, .. } = doc_example();
+        let dir0 = ObjectId("dir0");
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let doc789 = ObjectId("doc789");
+        miniz.write_object(&set_parent, dir0, dir1);
+        miniz.write_object(&set_parent, dir1, dir2);
+        miniz.write_object(&set_parent, dir2, doc789);
+        miniz.write_object(&set_owner, dir1, ObjectId("group1"));
+
+        assert_eq!(
+            miniz.descendants(&set_parent, &dir1),
+            vec![dir2, doc123, doc456, doc789]
+        );
+        assert_eq!(
+            miniz.descendants(&set_parent, &dir0),
+            vec![dir1, dir2, doc123, doc456, doc789]
+        );
+        assert!(miniz.descendants(&set_parent, &doc123).is_empty());
+        assert!(miniz.descendants(&set_owner, &doc123).is_empty());
+
+        /* Cycles are fine. */
+        miniz.write_object(&set_parent, doc789, dir1);
+        assert_eq!(
+            miniz.descendants(&set_parent, &dir2),
+            vec![doc456, doc789, dir1, dir2, doc123]
+        );
+        assert_eq!(
+            miniz.descendants(&set_parent, &dir1),
+            vec![dir2, doc123, doc456, doc789, dir1]
+        );
+    }
+
+    #[test]
+    fn test_simulate() {
+        let DocExample { miniz, set_owner, set_editor, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_bob = UserId("bob");
+        let user_oscar = UserId("oscar");
+        let probes = vec![
+            (set_viewer.clone(), doc123, user_alice),
+            (set_editor.clone(), dir1, user_bob),
+            (set_viewer.clone(), doc123, user_oscar),
+        ];
+
+        let results = miniz.simulate(
+            |miniz| {
+                assert!(miniz.remove_user(&set_owner, dir1, user_alice));
+                miniz.write_user(&set_viewer, doc123, user_oscar);
+            },
+            &probes,
+        );
+        assert_eq!(results, vec![(true, false), (true, true), (false, true)]);
+
+        /* The real store is unchanged. */
+        assert_matches_doc_example(&miniz);
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, doc123, user_oscar));
+        assert!(miniz.clone() == miniz);
+    }
+
+    #[test]
+    fn test_self_membership() {
+        let DocExample { mut miniz, set_owner, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let user_alice = UserId("alice");
+        let user_dan = UserId("dan");
+        let user_oscar = UserId("oscar");
+
+        /* "dir1" is its own parent.  That's allowed. */
+        miniz.write_object(&set_parent, dir1, dir1);
+        assert!(miniz.set_contains_object_directly(&set_parent, &dir1, dir1));
+        assert!(miniz.check_invariants().is_ok());
+
+        /* Checks terminate, with and without finding the user. */
+        assert!(miniz.check_member(&set_viewer, dir1, user_alice));
+        assert!(miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(!miniz.check_member(&set_viewer, dir1, user_dan));
+        assert!(!miniz.check_member(&set_viewer, dir1, user_oscar));
+        assert!(miniz
+            .shortest_grant_path(&set_viewer, dir1, user_dan)
+            .is_none());
+        assert!(!miniz.explain_denied(&set_viewer, dir1, user_dan).is_empty());
+
+        /* So do the other traversals, which see "dir1" once. */
+        assert_eq!(miniz.ancestors(&set_parent, &dir1), vec![dir1]);
+        assert_eq!(miniz.ancestors(&set_parent, &doc123), vec![dir1]);
+        assert_eq!(miniz.descendants(&set_parent, &dir1), vec![dir1, doc123]);
+        assert_eq!(
+            miniz.list_effective_users(&set_viewer, &dir1),
+            [user_alice, UserId("bob"), UserId("carol")]
+                .iter()
+                .copied()
+                .collect()
+        );
+        assert!(miniz.check_userset(&set_viewer, &dir1, &set_viewer, &dir1));
+
+        /* The tuple can be removed like any other. */
+        assert_eq!(miniz.delete_object(&dir1), 5);
+        assert!(!miniz.check_member(&set_viewer, doc123, user_alice));
+        assert!(miniz.check_member(&set_owner, doc123, user_dan));
+        assert!(miniz.check_invariants().is_ok());
+    }
+
+    #[test]
+    fn test_build_optimized() {
+        let mut ex = doc_example_schema_built(MiniZBuilder::build_optimized);
+        for tuple in doc_example_tuples(&ex) {
+            ex.miniz.write_tuple(tuple);
+        }
+        let DocExample {
+            miniz: mut optimized,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = ex;
+        let mut plain = doc_example().miniz;
+        assert_matches_doc_example(&optimized);
+        assert!(optimized == plain);
+
+        let rids = [&set_owner, &set_parent, &set_editor, &set_viewer];
+        let objects = plain.list_objects(None, usize::MAX);
+        let users = plain
+            .iter_tuples()
+            .filter_map(|t| match t.member {
+                Member::User(u) => Some(*u),
+                Member::Object(_) | Member::ObjectRelation { .. } => None,
+            })
+            .collect::<Vec<_>>();
+        let assert_same =
+            |optimized: &MiniZ<ObjectId, UserId>,
+             plain: &MiniZ<ObjectId, UserId>| {
+                for rid in rids {
+                    for object in &objects {
+                        for user in &users {
+                            assert_eq!(
+                                optimized.check_member(rid, *object, *user),
+                                plain.check_member(rid, *object, *user),
+                            );
+                        }
+                    }
+                }
+            };
+        assert_same(&optimized, &plain);
+
+        /* "viewer" skips straight to "owner" without going through "editor". */
+        let doc123 = ObjectId("doc123");
+        let user_dan = UserId("dan");
+        let (found, metrics) =
+            optimized.check_member_instrumented(&set_viewer, doc123, user_dan);
+        assert!(found);
+        assert_eq!(metrics.max_depth, 2);
+
+        /* Changing the schema keeps the answers the same, too. */
+        for miniz in [&mut optimized, &mut plain] {
+            assert!(miniz.remove_subset(&set_editor, &set_owner));
+            assert!(miniz.add_subset(&set_owner, &set_parent));
+        }
+        assert_same(&optimized, &plain);
+        assert!(!optimized.check_member(&set_viewer, doc123, user_dan));
+        let set_boss = optimized.rename_relation(&set_owner, "boss").unwrap();
+        assert!(optimized.add_subset(&set_editor, &set_boss));
+        assert!(optimized.check_member(&set_viewer, doc123, user_dan));
+    }
+
+    #[test]
+    fn test_stable_ordering() {
+        /*
+         * Explanations don't depend on how the store was built or on what
+         * was asked before.
+         */
+        let mut ex = doc_example_schema_built(MiniZBuilder::build_optimized);
+        for tuple in doc_example_tuples(&ex) {
+            ex.miniz.write_tuple(tuple);
+        }
+        let DocExample { miniz: optimized, set_viewer, .. } = ex;
+        let mut plain = doc_example().miniz;
+        plain.enable_check_cache();
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let explain = |miniz: &MiniZ<ObjectId, UserId>| {
+            format!(
+                "{:?}\n{:?}\n{:?}\n{:?}\n{:?}\n{}",
+                miniz.explain_denied(&set_viewer, doc456, UserId("alice")),
+                miniz.shortest_grant_path(&set_viewer, doc123, UserId("bob")),
+                miniz.trace_check(&set_viewer, doc456, UserId("alice")),
+                miniz.trace_check(&set_viewer, doc123, UserId("alice")),
+                miniz.list_effective_users(&set_viewer, &doc123),
+                miniz.expand(&set_viewer, &doc123),
+            )
+        };
+        let first = explain(&plain);
+        assert!(plain.check_member(&set_viewer, doc123, UserId("alice")));
+        assert_eq!(explain(&plain), first);
+        assert_eq!(explain(&optimized), first);
+        assert_eq!(explain(&plain.clone()), first);
+
+        /* Effective users are sorted no matter how they're found. */
+        let users = plain.list_effective_users(&set_viewer, &doc123);
+        let mut sorted = users.iter().copied().collect::<Vec<_>>();
+        sorted.sort();
+        assert_eq!(users.into_iter().collect::<Vec<_>>(), sorted);
+    }
+
+    #[test]
+    fn test_expand() {
+        let DocExample { miniz, set_owner, set_parent, set_editor, set_viewer } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+
+        let tree = miniz.expand(&set_editor, &dir1);
+        assert_eq!(
+            tree,
+            UsersetTree {
+                rid: set_editor.clone(),
+                object: dir1,
+                members: vec![Member::User(UserId("bob"))],
+                children: vec![UsersetTree {
+                    rid: set_owner.clone(),
+                    object: dir1,
+                    members: vec![Member::User(UserId("alice"))],
+                    children: vec![],
+                }],
+            }
+        );
+
+        let tree = miniz.expand(&set_viewer, &doc123);
+        assert_eq!(
+            tree.to_string(),
+            concat!(
+                "viewer on ObjectId(\"doc123\")\n",
+                "    user UserId(\"faythe\")\n",
+                "    editor on ObjectId(\"doc123\")\n",
+                "        user UserId(\"eve\")\n",
+                "        owner on ObjectId(\"doc123\")\n",
+                "            user UserId(\"dan\")\n",
+                "    viewer on ObjectId(\"dir1\")\n",
+                "        user UserId(\"carol\")\n",
+                "        editor on ObjectId(\"dir1\")\n",
+                "            user UserId(\"bob\")\n",
+                "            owner on ObjectId(\"dir1\")\n",
+                "                user UserId(\"alice\")\n",
+            )
+        );
+
+        let tree = miniz.expand(&set_parent, &dir1);
+        assert_eq!(
+            tree.to_string(),
+            "parent on ObjectId(\"dir1\")\n    object ObjectId(\"doc123\")\n"
+        );
+        assert_eq!(set_parent.to_string(), "parent");
+    }
+
+    #[test]
+    fn test_interning() {
+        /* Build the same store keyed by strings and by interned ids. */
+        let ex = doc_example();
+        let mut miniz_builder = MiniZ::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_editor = miniz_builder
+            .new_relationship("editor")
+            .with_subset(&set_owner)
+            .build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_subset(&set_editor)
+            .with_inherited_set(&set_parent)
+            .build();
+        let mut interned = miniz_builder.build().unwrap();
+        let mut interner = Interner::new();
+        for tuple in doc_example_tuples(&ex) {
+            match tuple.member {
+                Member::Object(child) => interned.write_object_str(
+                    &mut interner,
+                    &tuple.rid,
+                    tuple.parent.0,
+                    child.0,
+                ),
+                Member::User(child) => interned.write_user_str(
+                    &mut interner,
+                    &tuple.rid,
+                    tuple.parent.0,
+                    child.0,
+                ),
+                Member::ObjectRelation { .. } => {
+                    unreachable!("the example has no usersets")
+                }
+            };
+        }
+
+        let objects = ["dir1", "dir2", "doc123", "doc456", "nothing"];
+        let users = ["alice", "bob", "carol", "dan", "oscar", "nobody"];
+        for rid in [&set_owner, &set_parent, &set_editor, &set_viewer] {
+            for object in objects {
+                for user in users {
+                    assert_eq!(
+                        interned.check_member_str(&interner, rid, object, user),
+                        ex.miniz.check_member(
+                            rid,
+                            ObjectId(object),
+                            UserId(user)
+                        )
+                    );
+                }
+            }
+        }
+
+        /*
+         * Each distinct string was interned once, no matter how many tuples it
+         * appears in.
+         */
+        assert_eq!(interner.len(), 14);
+        let dir1 = interner.get("dir1").unwrap();
+        assert_eq!(interner.intern("dir1"), dir1);
+        assert_eq!(interner.resolve(dir1), "dir1");
+        assert_eq!(
+            interned.set_list_direct_members(&set_owner, &dir1).len(),
+            1
+        );
+        assert_eq!(interner.get("nobody"), None);
+        assert_ne!(interner.get("alice"), interner.get("bob"));
+    }
+
+    #[test]
+    fn test_caveats() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_inherited_set(&set_parent)
+            .build();
+        let us_only = miniz_builder.new_caveat(
+            "us_only",
+            Caveat::Equals { key: "region".to_owned(), value: "us".to_owned() },
+        );
+        let mut miniz: MiniZ<ObjectId, UserId> = miniz_builder.build().unwrap();
+        miniz.enable_log();
+
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+
+        let mut us = Context::new();
+        us.insert("region".to_owned(), "us".to_owned());
+        let mut eu = Context::new();
+        eu.insert("region".to_owned(), "eu".to_owned());
+        let empty = Context::new();
+
+        /* A caveated user tuple counts only when the caveat is satisfied. */
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user_caveated(&set_viewer, dir1, alice, &us_only);
+        assert!(miniz.check_member_with_context(&set_viewer, dir1, alice, &us));
+        assert!(!miniz.check_member_with_context(
+            &set_viewer,
+            dir1,
+            alice,
+            &eu
+        ));
+        assert!(!miniz.check_member_with_context(
+            &set_viewer,
+            dir1,
+            alice,
+            &empty
+        ));
+        assert!(!miniz.check_member(&set_viewer, dir1, alice));
+        assert!(miniz.check_member_with_context(
+            &set_viewer,
+            doc123,
+            alice,
+            &us
+        ));
+        assert!(!miniz.check_member_with_context(
+            &set_viewer,
+            doc123,
+            alice,
+            &eu
+        ));
+        assert!(miniz.list_effective_users(&set_viewer, &dir1).is_empty());
+
+        /* So does a caveated object tuple that others inherit through. */
+        miniz.write_object_caveated(&set_parent, dir2, doc456, &us_only);
+        miniz.write_user(&set_viewer, dir2, bob);
+        assert!(miniz.check_member(&set_viewer, dir2, bob));
+        assert!(miniz.check_member_with_context(&set_viewer, doc456, bob, &us));
+        assert!(!miniz.check_member_with_context(
+            &set_viewer,
+            doc456,
+            bob,
+            &eu
+        ));
+        assert!(!miniz.check_member(&set_viewer, doc456, bob));
+
+        /* The log and the binary format both preserve caveats. */
+        let replayed = miniz.replay_to(miniz.version());
+        assert!(replayed == miniz);
+        let mut bytes = Vec::new();
+        miniz.write_binary(&mut bytes).unwrap();
+        let loaded =
+            MiniZ::<ObjectId, UserId>::read_binary(bytes.as_slice()).unwrap();
+        assert!(loaded == miniz);
+        for store in [&replayed, &loaded].iter() {
+            assert!(!store.check_member(&set_viewer, doc123, alice));
+            assert!(store.check_member_with_context(
+                &set_viewer,
+                doc123,
+                alice,
+                &us
+            ));
+            assert!(!store.check_member(&set_viewer, doc456, bob));
+        }
+
+        /* Removing a caveated tuple removes its caveat, too. */
+        miniz.remove_user(&set_viewer, dir1, alice);
+        assert!(!miniz.check_member_with_context(
+            &set_viewer,
+            dir1,
+            alice,
+            &us
+        ));
+        miniz.write_user(&set_viewer, dir1, alice);
+        assert!(miniz.check_member(&set_viewer, dir1, alice));
+        assert!(miniz.check_member_with_context(
+            &set_viewer,
+            doc123,
+            alice,
+            &eu
+        ));
+    }
+
+    #[test]
+    fn test_build_unknown_reference() {
+        let bogus = RelationshipId(String::from("bogus"));
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        miniz_builder.new_relationship("owner").build();
+        miniz_builder.new_relationship("viewer").with_subset(&bogus).build();
+        assert_eq!(
+            miniz_builder.build().err(),
+            Some(BuildError::UnknownReference(bogus.clone()))
+        );
+
+        /* Inherited sets are checked too, as is a replacement schema. */
+        let DocExample { mut miniz, set_parent, .. } = doc_example();
+        let mut miniz_builder = MiniZ::builder();
+        for name in ["owner", "parent", "editor"] {
+            miniz_builder.new_relationship(name).build();
+        }
+        miniz_builder
+            .new_relationship("viewer")
+            .with_inherited_as(&set_parent, &bogus)
+            .build();
+        assert_eq!(
+            miniz.replace_schema(miniz_builder),
+            Err(SchemaSwapError::Build(BuildError::UnknownReference(bogus)))
+        );
+        assert_matches_doc_example(&miniz);
+    }
+
+    #[test]
+    fn test_common_objects() {
+        let DocExample { mut miniz, set_editor, set_viewer, .. } =
+            doc_example();
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        let carol = UserId("carol");
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let doc456 = ObjectId("doc456");
+
+        assert_eq!(
+            miniz.list_objects_for_user(&set_viewer, &carol),
+            BTreeSet::from([dir1, doc123])
+        );
+        assert_eq!(
+            miniz.common_objects(&set_editor, &alice, &bob),
+            BTreeSet::from([dir1])
+        );
+
+        /* Grants to only one of them don't count. */
+        miniz.write_user(&set_editor, doc456, alice);
+        miniz.write_user(&set_editor, doc123, bob);
+        assert_eq!(
+            miniz.common_objects(&set_editor, &alice, &bob),
+            BTreeSet::from([dir1])
+        );
+        miniz.write_user(&set_editor, doc456, bob);
+        assert_eq!(
+            miniz.common_objects(&set_editor, &alice, &bob),
+            BTreeSet::from([dir1, doc456])
+        );
+        assert_eq!(
+            miniz.common_objects(&set_editor, &bob, &alice),
+            BTreeSet::from([dir1, doc456])
+        );
+        assert!(miniz.common_objects(&set_editor, &alice, &carol).is_empty());
+    }
+
+    #[test]
+    fn test_iter_memberships() {
+        let ex = doc_example();
+        let miniz = &ex.miniz;
+        let pairs = miniz.iter_memberships().collect::<Vec<_>>();
+        assert_eq!(pairs.len(), doc_example_tuples(&ex).len());
+
+        let mut members = pairs.iter().map(|(m, _)| *m).collect::<Vec<_>>();
+        members.dedup();
+        for member in members {
+            let from_iter = pairs
+                .iter()
+                .filter(|(m, _)| *m == member)
+                .map(|(_, membership)| *membership)
+                .collect::<Vec<_>>();
+            let looked_up = match member {
+                Member::Object(object) => {
+                    miniz.object_lookup_memberships(*object)
+                }
+                Member::User(user) => miniz.user_lookup_memberships(*user),
+                Member::ObjectRelation { .. } => unreachable!(),
+            };
+            assert_eq!(from_iter, looked_up);
+        }
+    }
+
+    #[test]
+    fn test_try_check_member() {
+        let DocExample { miniz, set_owner, set_viewer, .. } = doc_example();
+        let bogus = RelationshipId(String::from("bogus"));
+        let dir1 = ObjectId("dir1");
+        let dir3 = ObjectId("dir3");
+        let alice = UserId("alice");
+
+        assert_eq!(miniz.try_check_member(&set_owner, dir1, alice), Ok(true));
+        assert_eq!(miniz.try_check_member(&set_viewer, dir3, alice), Ok(false));
+        assert_eq!(
+            miniz.try_check_member(&bogus, dir1, alice),
+            Err(CheckError::NoSuchRelationship(bogus.clone()))
+        );
+    }
+
+    #[test]
+    fn test_relation_member_breakdown() {
+        let DocExample { mut miniz, set_owner, set_parent, set_viewer, .. } =
+            doc_example();
+        assert_eq!(
+            miniz.relation_member_breakdown(&set_parent),
+            MemberBreakdown { objects: 2, users: 0, object_relations: 0 }
+        );
+        assert_eq!(
+            miniz.relation_member_breakdown(&set_owner),
+            MemberBreakdown { objects: 0, users: 4, object_relations: 0 }
+        );
+
+        /* A user written to "parent" by mistake shows up. */
+        miniz.write_user(&set_parent, ObjectId("doc123"), UserId("mallory"));
+        assert_eq!(
+            miniz.relation_member_breakdown(&set_parent),
+            MemberBreakdown { objects: 2, users: 1, object_relations: 0 }
+        );
+
+        miniz.write_object_relation(
+            &set_viewer,
+            ObjectId("doc456"),
+            ObjectId("dir1"),
+            &set_viewer,
+        );
+        assert_eq!(
+            miniz.relation_member_breakdown(&set_viewer),
+            MemberBreakdown { objects: 0, users: 3, object_relations: 1 }
+        );
+    }
+
+    #[test]
+    fn test_check_matrix() {
+        let DocExample { miniz, set_owner, set_editor, set_viewer, .. } =
+            doc_example();
+        let rids = [set_owner, set_editor, set_viewer];
+        let objects = [
+            ObjectId("dir1"),
+            ObjectId("doc123"),
+            ObjectId("dir2"),
+            ObjectId("doc456"),
+        ];
+
+        let user = UserId("bob");
+        let matrix = miniz.check_matrix(&rids, &objects, &user);
+        assert_eq!(
+            matrix,
+            vec![
+                vec![false, false, false, false],
+                vec![true, false, false, false],
+                vec![true, true, false, false],
+            ]
+        );
+
+        for user in ["alice", "dan", "heidi", "judy", "mallory"] {
+            let user = UserId(user);
+            let matrix = miniz.check_matrix(&rids, &objects, &user);
+            assert_eq!(matrix.len(), rids.len());
+            for (rid, row) in rids.iter().zip(&matrix) {
+                assert_eq!(row.len(), objects.len());
+                for (object, found) in objects.iter().zip(row) {
+                    assert_eq!(*found, miniz.check_member(rid, *object, user));
+                }
+            }
+        }
+
+        assert!(miniz.check_matrix(&[], &objects, &user).is_empty());
+        assert_eq!(
+            miniz.check_matrix(&rids, &[], &user),
+            vec![Vec::<bool>::new(); 3]
+        );
+    }
+
+    #[test]
+    fn test_set_entry() {
+        let mut miniz_builder = MiniZ::<ObjectId, UserId>::builder();
+        let set_owner = miniz_builder.new_relationship("owner").build();
+        let set_editor = miniz_builder.new_relationship("editor").build();
+        let set_parent = miniz_builder.set_entry("parent").id();
+
+        /* Each part of the schema contributes its own rules for "viewer". */
+        let set_viewer = miniz_builder
+            .set_entry("viewer")
+            .with_subset(&set_owner)
+            .with_inherited_set(&set_parent)
+            .id();
+        miniz_builder.set_entry("viewer").with_subset(&set_editor);
+
+        let mut miniz = miniz_builder.build().unwrap();
+        assert!(miniz.relation_reachable(&set_viewer, &set_owner));
+        assert!(miniz.relation_reachable(&set_viewer, &set_editor));
+
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        miniz.write_user(&set_owner, dir1, alice);
+        miniz.write_user(&set_editor, doc123, bob);
+        miniz.write_object(&set_parent, dir1, doc123);
+        assert!(miniz.check_member(&set_viewer, doc123, alice));
+        assert!(miniz.check_member(&set_viewer, doc123, bob));
+        assert!(!miniz.check_member(&set_viewer, dir1, bob));
+    }
+
+    #[test]
+    fn test_debug() {
+        let miniz = doc_example().miniz;
+        let debug = format!("{:?}", miniz);
+        for name in ["owner", "parent", "editor", "viewer"] {
+            assert!(debug.contains(&format!("{}: Relationship {{", name)));
+        }
+        assert!(debug.contains(
+            "editor: Relationship { subsets: {RelationshipId(\"owner\")}, \
+             inherited: {}, num_tuples: 3 }"
+        ));
+        assert!(debug.contains(
+            "viewer: Relationship { subsets: {RelationshipId(\"editor\")}, \
+             inherited: {RelationshipId(\"parent\"): \
+             {RelationshipId(\"viewer\")}}, num_tuples: 3 }"
+        ));
+        assert!(debug.contains("num_members: 12"));
+
+        /* The alternate form spreads it over several lines. */
+        let pretty = format!("{:#?}", miniz);
+        assert!(pretty.lines().count() > 4);
+        assert!(pretty.contains("num_tuples: 3,"));
+    }
+
+    #[test]
+    fn test_inherited_index() {
+        let mut set_tagged = None;
+        let mut ex = doc_example_schema_built(|mut miniz_builder| {
+            set_tagged = Some(miniz_builder.new_relationship("tagged").build());
+            miniz_builder.build()
+        });
+        for tuple in doc_example_tuples(&ex) {
+            ex.miniz.write_tuple(tuple);
+        }
+        let DocExample { mut miniz, set_parent, set_viewer, .. } = ex;
+        let set_tagged = set_tagged.unwrap();
+
+        /* Give doc123 hundreds of memberships besides its parent. */
+        let tags = (0..300)
+            .map(|i| ObjectId(Box::leak(format!("tag{}", i).into_boxed_str())))
+            .collect::<Vec<_>>();
+        for tag in &tags {
+            miniz.write_object(&set_tagged, *tag, ObjectId("doc123"));
+        }
+        let plain = miniz.clone();
+        miniz.enable_inherited_index();
+
+        /*
+         * Only the "parent" membership is looked at with the index, rather
+         * than all of them.
+         */
+        let doc123 = ObjectId("doc123");
+        let candidates = |miniz: &MiniZ<ObjectId, UserId>| {
+            miniz
+                .inherited_candidates(&miniz.sets[&set_viewer], &doc123)
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(candidates(&plain).len(), 301);
+        assert_eq!(
+            candidates(&miniz),
+            vec![Membership {
+                rid: set_parent.clone(),
+                object: ObjectId("dir1")
+            }]
+        );
+
+        let users = ["alice", "bob", "carol", "faythe", "judy", "mallory"];
+        let assert_same =
+            |miniz: &MiniZ<ObjectId, UserId>,
+             plain: &MiniZ<ObjectId, UserId>| {
+                for user in users {
+                    assert_eq!(
+                        miniz.check_member(&set_viewer, doc123, UserId(user)),
+                        plain.check_member(&set_viewer, doc123, UserId(user))
+                    );
+                }
+            };
+        assert!(miniz.check_member(&set_viewer, doc123, UserId("carol")));
+        assert_same(&miniz, &plain);
+
+        /* Moving the document is noticed. */
+        let mut plain = plain;
+        for miniz in [&mut miniz, &mut plain] {
+            miniz.remove_object(&set_parent, ObjectId("dir1"), doc123);
+            miniz.write_object(&set_parent, ObjectId("dir2"), doc123);
+        }
+        assert!(!miniz.check_member(&set_viewer, doc123, UserId("carol")));
+        assert!(miniz.check_member(&set_viewer, doc123, UserId("judy")));
+        assert_same(&miniz, &plain);
+        assert_eq!(
+            candidates(&miniz),
+            vec![Membership { rid: set_parent, object: ObjectId("dir2") }]
+        );
+    }
+
+    #[test]
+    fn test_direct_only() {
+        let mut set_direct_viewer = None;
+        let mut set_reader = None;
+        let mut ex = doc_example_schema_built(|mut miniz_builder| {
+            let set_editor = RelationshipId(String::from("editor"));
+            let set_parent = RelationshipId(String::from("parent"));
+            let set_viewer = RelationshipId(String::from("viewer"));
+            set_direct_viewer = Some(
+                miniz_builder
+                    .new_relationship("direct_viewer")
+                    .with_subset(&set_editor)
+                    .with_inherited_as(&set_parent, &set_viewer)
+                    .direct_only()
+                    .build(),
+            );
+            set_reader = Some(
+                miniz_builder
+                    .new_relationship("reader")
+                    .with_subset(set_direct_viewer.as_ref().unwrap())
+                    .build(),
+            );
+            miniz_builder.build_optimized()
+        });
+        for tuple in doc_example_tuples(&ex) {
+            ex.miniz.write_tuple(tuple);
+        }
+        let DocExample { mut miniz, set_viewer, .. } = ex;
+        let set_direct_viewer = set_direct_viewer.unwrap();
+        let set_reader = set_reader.unwrap();
+        let doc123 = ObjectId("doc123");
+        let carol = UserId("carol");
+        let eve = UserId("eve");
+        let faythe = UserId("faythe");
+
+        /*
+         * carol views doc123 by inheritance and eve by being an editor, but
+         * neither counts for the direct-only relationship.
+         */
+        miniz.write_user(&set_direct_viewer, doc123, faythe);
+        for user in [carol, eve, faythe] {
+            assert!(miniz.check_member(&set_viewer, doc123, user));
+        }
+        assert!(!miniz.check_member(&set_direct_viewer, doc123, carol));
+        assert!(!miniz.check_member(&set_direct_viewer, doc123, eve));
+        assert!(miniz.check_member(&set_direct_viewer, doc123, faythe));
+
+        /* Relationships including it get only its direct members, too. */
+        assert!(!miniz.check_member(&set_reader, doc123, eve));
+        assert!(miniz.check_member(&set_reader, doc123, faythe));
+        assert_eq!(
+            miniz.list_effective_users(&set_direct_viewer, &doc123),
+            BTreeSet::from([faythe])
+        );
+    }
+
+    #[test]
+    fn test_sample_effective_users() {
+        let DocExample { miniz, set_viewer, .. } = doc_example();
+        let doc123 = ObjectId("doc123");
+        let all = miniz.list_effective_users(&set_viewer, &doc123);
+        assert_eq!(all.len(), 6);
+
+        for n in 0..=all.len() + 1 {
+            let sample = miniz.sample_effective_users(&set_viewer, &doc123, n);
+            assert_eq!(sample.len(), n.min(all.len()));
+            assert!(sample.windows(2).all(|pair| pair[0] < pair[1]));
+            for user in sample {
+                assert!(all.contains(&user));
+                assert!(miniz.check_member(&set_viewer, doc123, user));
+            }
+        }
+        assert!(miniz
+            .sample_effective_users(&set_viewer, &ObjectId("dir3"), 3)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sample_effective_users_denied() {
+        let mut miniz_builder = MiniZ::builder();
+        let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_banned = miniz_builder.new_relationship("banned").build();
+        let set_viewer = miniz_builder
+            .new_relationship("viewer")
+            .with_inherited_set(&set_parent)
+            .with_deny_relation(&set_banned)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+
+        /*
+         * "alice" is a viewer of the folder, which makes her a viewer of the
+         * document, but she's banned from the document.  She comes first in
+         * sorted order, so she'd be the first one sampled if the ban were
+         * ignored.
+         */
+        let dir1 = ObjectId("dir1");
+        let doc123 = ObjectId("doc123");
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        let carol = UserId("carol");
+        miniz.write_object(&set_parent, dir1, doc123);
+        miniz.write_user(&set_viewer, dir1, alice);
+        miniz.write_user(&set_viewer, dir1, bob);
+        miniz.write_user(&set_viewer, doc123, carol);
+        miniz.write_user(&set_banned, doc123, alice);
+        assert!(!miniz.check_member(&set_viewer, doc123, alice));
+
+        for n in 1..=3 {
+            let sample = miniz.sample_effective_users(&set_viewer, &doc123, n);
+            assert_eq!(sample.len(), n.min(2));
+            assert!(!sample.contains(&alice));
+            for user in sample {
+                assert!(miniz.check_member(&set_viewer, doc123, user));
+            }
+        }
+        assert_eq!(
+            miniz.sample_effective_users(&set_viewer, &dir1, 3),
+            vec![alice, bob]
+        );
+    }
+
+    #[test]
+    fn test_error_display() {
+        let rid = RelationshipId(String::from("viewer"));
+        let parse_error = ParseError::UnknownRelationship {
+            line: 3,
+            name: String::from("editor"),
+        };
+        let cases: Vec<(Error, &[&str])> = vec![
+            (BuildError::UnknownReference(rid.clone()).into(), &["viewer"]),
+            (CheckError::NoSuchRelationship(rid.clone()).into(), &["viewer"]),
+            (ParseError::Syntax { line: 7 }.into(), &["line 7"]),
+            (
+                ParseError::Duplicate { line: 2, name: String::from("owner") }
+                    .into(),
+                &["line 2", "owner"],
+            ),
+            (parse_error.into(), &["line 3", "editor"]),
+            (RenameError::NoSuchRelationship(rid.clone()).into(), &["viewer"]),
+            (RenameError::AlreadyExists(rid.clone()).into(), &["viewer"]),
+            (
+                SchemaSwapError::MissingRelationship(rid.clone()).into(),
+                &["viewer"],
+            ),
+            (
+                SchemaSwapError::UnexpectedMemberKind(rid.clone()).into(),
+                &["viewer"],
+            ),
+            (
+                SchemaSwapError::MissingCaveat(CaveatId(String::from(
+                    "weekdays",
+                )))
+                .into(),
+                &["weekdays"],
+            ),
+            (
+                SchemaSwapError::Build(BuildError::UnknownReference(
+                    rid.clone(),
+                ))
+                .into(),
+                &["invalid", "viewer"],
+            ),
+            (WriteError::NoSuchRelationship(rid.clone()).into(), &["viewer"]),
+            (WriteError::UnexpectedMemberKind(rid.clone()).into(), &["viewer"]),
+        ];
+        for (error, expected) in cases {
+            let text = error.to_string();
+            for expected in expected {
+                assert!(text.contains(expected), "{:?}: {}", error, text);
+            }
+            assert!(std::error::Error::source(&error).is_some());
+        }
+
+        let errors: Vec<InvariantError<ObjectId, UserId>> = vec![
+            InvariantError::MissingMembership {
+                rid: rid.clone(),
+                parent: ObjectId("doc123"),
+                member: Member::User(UserId("alice")),
+            },
+            InvariantError::MissingDirectMember {
+                member: Member::User(UserId("alice")),
+                membership: Membership {
+                    rid: rid.clone(),
+                    object: ObjectId("doc123"),
+                },
+            },
+            InvariantError::EmptyDirectMembers {
+                rid,
+                parent: ObjectId("doc123"),
+            },
+        ];
+        for error in errors {
+            let text = error.to_string();
+            assert!(
+                text.contains("viewer") && text.contains("doc123"),
+                "{}",
+                text
+            );
+        }
+        let error: InvariantError<ObjectId, UserId> =
+            InvariantError::EmptyMemberships {
+                member: Member::User(UserId("alice")),
+            };
+        assert!(error.to_string().contains("alice"));
+
+        /* `?` works with every kind of error. */
+        fn build_and_rename() -> Result<MiniZ<ObjectId, UserId>, Error> {
+            let mut miniz =
+                MiniZBuilder::from_schema_str("owner\n")?.build()?;
+            let owner = RelationshipId(String::from("owner"));
+            miniz.rename_relation(&owner, "admin")?;
+            Ok(miniz)
+        }
+        assert!(build_and_rename().is_ok());
+        assert_eq!(
+            MiniZBuilder::<ObjectId, UserId>::from_schema_str("a: b")
+                .map_err(Error::from)
+                .err(),
+            Some(Error::Parse(ParseError::UnknownRelationship {
+                line: 1,
+                name: String::from("b"),
+            }))
+        );
+    }
+
+    #[test]
+    fn test_load_sorted() {
+        let ex = doc_example_schema();
+        let mut tuples = doc_example_tuples(&ex);
+        let DocExample { miniz: empty, set_parent, set_viewer, .. } = ex;
+        for i in 0..50 {
+            let doc = ObjectId(Box::leak(format!("doc{}", i).into_boxed_str()));
+            tuples.push(Tuple {
+                rid: set_parent.clone(),
+                parent: ObjectId(if i % 2 == 0 { "dir1" } else { "dir2" }),
+                member: Member::Object(doc),
+            });
+            for user in ["alice", "bob", "carol"] {
+                tuples.push(Tuple {
+                    rid: set_viewer.clone(),
+                    parent: doc,
+                    member: Member::User(UserId(user)),
+                });
+            }
+        }
+        tuples.reverse();
+
+        let mut incremental = empty.clone();
+        for tuple in tuples.clone() {
+            incremental.write_tuple(tuple);
+        }
+        let mut sorted = empty.clone();
+        sorted.load_sorted(tuples.clone());
+        assert!(sorted == incremental);
+        assert_eq!(sorted.version(), incremental.version());
+        assert_eq!(sorted.check_invariants(), Ok(()));
+        assert!(sorted.check_member(
+            &set_viewer,
+            ObjectId("doc7"),
+            UserId("judy")
+        ));
+
+        /* Objects that already have members get the new ones added. */
+        let (first, rest) = tuples.split_at(tuples.len() / 2);
+        let mut partial = empty;
+        partial.load_sorted(first.to_vec());
+        partial.load_sorted(rest.to_vec());
+        assert!(partial == incremental);
+        assert_eq!(partial.check_invariants(), Ok(()));
+        partial.load_sorted(Vec::new());
+        assert!(partial == incremental);
+    }
+
+    #[test]
+    fn test_check_member_fast() {
+        let ex = doc_example();
+        let DocExample { miniz, set_viewer, .. } = &ex;
+
+        /* Every direct grant is answered by the fast path. */
+        for tuple in doc_example_tuples(&ex) {
+            if let Member::User(user) = tuple.member {
+                assert_eq!(
+                    miniz.check_member_fast(&tuple.rid, &tuple.parent, &user),
+                    Some(true)
+                );
+                assert!(miniz.check_member(&tuple.rid, tuple.parent, user));
+            }
+        }
+
+        /* Anything else needs a full check. */
+        let doc123 = ObjectId("doc123");
+        let carol = UserId("carol");
+        let eve = UserId("eve");
+        let mallory = UserId("mallory");
+        assert_eq!(miniz.check_member_fast(set_viewer, &doc123, &carol), None);
+        assert!(miniz.check_member(set_viewer, doc123, carol));
+        assert_eq!(miniz.check_member_fast(set_viewer, &doc123, &eve), None);
+        assert!(miniz.check_member(set_viewer, doc123, eve));
+        assert_eq!(
+            miniz.check_member_fast(set_viewer, &doc123, &mallory),
+            None
+        );
+        assert!(!miniz.check_member(set_viewer, doc123, mallory));
+
+        /* So does a direct grant that a deny relationship could override. */
+        let mut miniz_builder = MiniZ::builder();
+        let set_banned = miniz_builder.new_relationship("banned").build();
+        let set_commenter = miniz_builder
+            .new_relationship("commenter")
+            .with_deny_relation(&set_banned)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+        miniz.write_user(&set_commenter, doc123, carol);
+        miniz.write_user(&set_commenter, doc123, mallory);
+        miniz.write_user(&set_banned, doc123, mallory);
+        assert_eq!(
+            miniz.check_member_fast(&set_commenter, &doc123, &carol),
+            None
+        );
+        assert!(miniz.check_member(&set_commenter, doc123, carol));
+        assert!(!miniz.check_member(&set_commenter, doc123, mallory));
+    }
+
+    #[test]
+    fn test_relations_between() {
+        let DocExample { mut miniz, set_parent, set_viewer, .. } =
+            doc_example();
+        let dir1 = ObjectId("dir1");
+        let dir2 = ObjectId("dir2");
+        let doc123 = ObjectId("doc123");
+        assert_eq!(
+            miniz.relations_between(&dir1, &doc123),
+            vec![set_parent.clone()]
+        );
+        assert!(miniz.relations_between(&doc123, &dir1).is_empty());
+        assert!(miniz.relations_between(&dir2, &doc123).is_empty());
+
+        miniz.write_object(&set_viewer, dir1, doc123);
+        assert_eq!(
+            miniz.relations_between(&dir1, &doc123),
+            vec![set_parent, set_viewer]
+        );
     }
 
-    /// List the users and objects having a direct relationship with `parent`
-    pub fn set_list_direct_members<'a, 'b>(
-        &'a self,
-        rid: &'b RelationshipId,
-        parent: &'b O,
-    ) -> Vec<&Member<O, U>> {
-        let set = self.sets.get(rid).expect("no such set");
-        match set.direct_members.get(parent) {
-            Some(members) => members.iter().collect(),
-            None => Vec::new(),
+    #[test]
+    fn test_verify_against_naive() {
+        let DocExample {
+            mut miniz,
+            set_owner,
+            set_parent,
+            set_editor,
+            set_viewer,
+        } = doc_example();
+        let mut probes = Vec::new();
+        for rid in [&set_owner, &set_parent, &set_editor, &set_viewer] {
+            for object in ["dir1", "dir2", "doc123", "doc456", "doc789"] {
+                for user in ["alice", "bob", "carol", "eve", "judy", "mallory"]
+                {
+                    probes.push((rid.clone(), ObjectId(object), UserId(user)));
+                }
+            }
         }
-    }
+        assert!(miniz.verify_against_naive(&probes).is_empty());
 
-    /// List the objects that this object has a direct relationship with
-    pub fn object_lookup_memberships(&self, object: O) -> Vec<&Membership<O>> {
-        match self.memberships.get(&Member::Object(object)) {
-            Some(memberships) => memberships.iter().collect(),
-            None => Vec::new(),
+        /* The same goes with every optimization turned on. */
+        miniz.enable_check_cache();
+        miniz.enable_inherited_index();
+        assert!(miniz.verify_against_naive(&probes).is_empty());
+        assert!(miniz.verify_against_naive(&probes).is_empty());
+        let mut optimized =
+            doc_example_schema_built(MiniZBuilder::build_optimized);
+        for tuple in doc_example_tuples(&optimized) {
+            optimized.miniz.write_tuple(tuple);
         }
+        assert!(optimized.miniz.verify_against_naive(&probes).is_empty());
+
+        /*
+         * Corrupting the reverse index (which the reference implementation
+         * doesn't use) produces discrepancies.
+         */
+        let mut miniz = doc_example().miniz;
+        miniz.memberships.remove(&Member::Object(ObjectId("doc123")));
+        let discrepancies = miniz.verify_against_naive(&probes);
+        assert!(discrepancies.contains(&Discrepancy {
+            rid: set_viewer,
+            object: ObjectId("doc123"),
+            user: UserId("carol"),
+            expected: true,
+            actual: false,
+        }));
+        assert!(discrepancies.iter().all(|d| d.object == ObjectId("doc123")
+            && d.expected
+            && !d.actual));
     }
 
-    /// List the objects that this user has a direct relationship with
-    pub fn user_lookup_memberships(&self, user: U) -> Vec<&Membership<O>> {
-        match self.memberships.get(&Member::User(user)) {
-            Some(memberships) => memberships.iter().collect(),
-            None => Vec::new(),
-        }
+    /// [`AsyncTupleSource`] that scans a list of tuples, yielding once before
+    /// each lookup completes as a remote store would
+    struct MockAsyncSource {
+        tuples: Vec<Tuple<ObjectId, UserId>>,
+        lookups: std::cell::Cell<usize>,
     }
 
-    ///
-    /// Check whether the user `user` has relationship `rid` with object
-    /// `object`, either directly or through a combination of implied or
-    /// inherited relationships
-    ///
-    pub fn check_member(
-        &self,
-        rid: &RelationshipId,
-        object: O,
-        user: U,
-    ) -> bool {
-        let set = self.sets.get(rid).expect("no such set");
+    /// Future that's pending the first time it's polled
+    struct YieldOnce<T>(Option<T>, bool);
 
-        /*
-         * First, check if the user is a direct member of this set.
-         */
-        if let Some(members) = set.direct_members.get(&object) {
-            if members.contains(&Member::User(user.clone())) {
-                return true;
+    impl<T: Unpin> std::future::Future for YieldOnce<T> {
+        type Output = T;
+
+        fn poll(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<T> {
+            if !self.1 {
+                self.1 = true;
+                cx.waker().wake_by_ref();
+                return std::task::Poll::Pending;
             }
+            std::task::Poll::Ready(self.0.take().unwrap())
         }
+    }
 
-        /*
-         * Next, check recursively if the user is a member (directly or
-         * otherwise) of a set directly contained in this set.
-         */
-        for subrid in &set.contained_relationships {
-            if self.check_member(subrid, object.clone(), user.clone()) {
-                return true;
-            }
+    impl AsyncTupleSource<ObjectId, UserId> for MockAsyncSource {
+        fn direct_members(
+            &self,
+            rid: &RelationshipId,
+            object: &ObjectId,
+        ) -> impl std::future::Future<Output = Vec<Member<ObjectId, UserId>>>
+        {
+            self.lookups.set(self.lookups.get() + 1);
+            let members = self
+                .tuples
+                .iter()
+                .filter(|t| t.rid == *rid && t.parent == *object)
+                .map(|t| t.member.clone())
+                .collect();
+            YieldOnce(Some(members), false)
         }
 
-        /*
-         * This is more expensive.  Check if there exists any object O2 such
-         * that the user has the desired relationship with O2 and this object
-         * inherits O2's relationships.
-         * XXX This could be more efficient with another index.
-         */
-        let memberships = self.memberships.get(&Member::Object(object));
-        if memberships.is_none() {
-            return false;
+        fn memberships(
+            &self,
+            member: &Member<ObjectId, UserId>,
+        ) -> impl std::future::Future<Output = Vec<Membership<ObjectId>>>
+        {
+            self.lookups.set(self.lookups.get() + 1);
+            let memberships = self
+                .tuples
+                .iter()
+                .filter(|t| t.member == *member)
+                .map(|t| Membership { rid: t.rid.clone(), object: t.parent })
+                .collect();
+            YieldOnce(Some(memberships), false)
         }
+    }
 
-        let inherited_present_memberships = memberships
-            .unwrap()
-            .into_iter()
-            .filter(|m| set.inherited_relationships.contains(&m.rid));
-        for m in inherited_present_memberships {
-            if self.check_member(&m.rid, m.object.clone(), user.clone()) {
-                return true;
+    /// Runs `future` to completion on the current thread
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut cx = std::task::Context::from_waker(std::task::Waker::noop());
+        loop {
+            if let std::task::Poll::Ready(output) =
+                future.as_mut().poll(&mut cx)
+            {
+                return output;
             }
         }
-
-        return false;
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::Member;
-    use super::Membership;
-    use super::MiniZ;
+    #[test]
+    fn test_check_member_async() {
+        let schema = doc_example_schema();
+        let source = MockAsyncSource {
+            tuples: doc_example_tuples(&schema),
+            lookups: std::cell::Cell::new(0),
+        };
+        let DocExample {
+            miniz: schema, set_owner, set_editor, set_viewer, ..
+        } = schema;
+        let miniz = doc_example().miniz;
 
-    #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
-    struct ObjectId(&'static str);
-    #[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
-    struct UserId(&'static str);
+        for rid in [&set_owner, &set_editor, &set_viewer] {
+            for object in ["dir1", "dir2", "doc123", "doc456"] {
+                for user in ["alice", "bob", "carol", "eve", "judy", "mallory"]
+                {
+                    let object = ObjectId(object);
+                    let user = UserId(user);
+                    let expected = miniz.check_member(rid, object, user);
+                    assert_eq!(
+                        block_on(
+                            schema
+                                .check_member_async(&source, rid, object, user)
+                        ),
+                        expected
+                    );
+
+                    /* A store can also serve as its own source. */
+                    assert_eq!(
+                        block_on(
+                            miniz.check_member_async(&miniz, rid, object, user)
+                        ),
+                        expected
+                    );
+                }
+            }
+        }
+        assert!(source.lookups.get() > 0);
+
+        /* The schema's own tuples are ignored. */
+        let empty = MockAsyncSource {
+            tuples: Vec::new(),
+            lookups: std::cell::Cell::new(0),
+        };
+        assert!(!block_on(miniz.check_member_async(
+            &empty,
+            &set_viewer,
+            ObjectId("doc123"),
+            UserId("carol")
+        )));
+
+        /* Deny relationships and usersets are followed, too. */
+        let mut miniz_builder = MiniZ::builder();
+        let set_banned = miniz_builder.new_relationship("banned").build();
+        let set_member = miniz_builder.new_relationship("member").build();
+        let set_commenter = miniz_builder
+            .new_relationship("commenter")
+            .with_deny_relation(&set_banned)
+            .build();
+        let mut miniz = miniz_builder.build().unwrap();
+        let doc123 = ObjectId("doc123");
+        let group1 = ObjectId("group1");
+        miniz.write_object_relation(
+            &set_commenter,
+            doc123,
+            group1,
+            &set_member,
+        );
+        miniz.write_user(&set_member, group1, UserId("carol"));
+        miniz.write_user(&set_member, group1, UserId("mallory"));
+        miniz.write_user(&set_banned, doc123, UserId("mallory"));
+        for user in ["carol", "mallory", "dan"] {
+            let user = UserId(user);
+            assert_eq!(
+                block_on(miniz.check_member_async(
+                    &miniz,
+                    &set_commenter,
+                    doc123,
+                    user
+                )),
+                miniz.check_member(&set_commenter, doc123, user)
+            );
+        }
+        assert!(miniz.check_member(&set_commenter, doc123, UserId("carol")));
+        assert!(!miniz.check_member(&set_commenter, doc123, UserId("mallory")));
+    }
 
     #[test]
-    fn test_example() {
+    fn test_check_member_async_matches_check() {
         /*
-         * The following block constructs a MiniZ instance with the same
-         * configuration as what's described in Figure 1 in the Zanzibar paper.
-         * It looks intended to describe the Google Docs authorization behavior.
+         * A schema that uses every kind of rule: subsets, inherited
+         * relationships (paired or not), usersets (nested, and written both
+         * as object members and as object#relation members), deny
+         * relationships (including inherited ones), and a direct-only
+         * relationship.
          */
         let mut miniz_builder = MiniZ::builder();
-        let set_owner = miniz_builder.new_relationship("owner").build();
         let set_parent = miniz_builder.new_relationship("parent").build();
+        let set_banned = miniz_builder
+            .new_relationship("banned")
+            .with_inherited_set(&set_parent)
+            .build();
+        let member_builder = miniz_builder.new_relationship("member");
+        let member_id = member_builder.id();
+        let set_member =
+            member_builder.with_userset_member_relation(&member_id).build();
+        let set_owner = miniz_builder
+            .new_relationship("owner")
+            .with_userset_member_relation(&set_member)
+            .direct_only()
+            .build();
         let set_editor = miniz_builder
             .new_relationship("editor")
             .with_subset(&set_owner)
+            .with_userset_member_relation(&set_member)
             .build();
-        let set_viewer = miniz_builder
-            .new_relationship("viewer")
+        let viewer_builder = miniz_builder.new_relationship("viewer");
+        let viewer_id = viewer_builder.id();
+        let set_viewer = viewer_builder
             .with_subset(&set_editor)
-            .with_inherited_set(&set_parent)
+            .with_inherited_as(&set_parent, &viewer_id)
+            .with_deny_relation(&set_banned)
             .build();
-        let mut miniz = miniz_builder.build();
-
-        /*
-         * Now demo it.  We'll construct this hierarchy of objects (defined by
-         * the "parent" relationship).
-         *
-         *    "dir1"                  owner: "alice"
-         *      |                     editor: "bob"
-         *      | "parent"            viewer: "carol"
-         *      v
-         *    "doc123"                owner: "dan"
-         *                            editor: "eve"
-         *                            viewer: "faythe"
-         *
-         *
-         *    "dir2"                  owner: "heidi"
-         *      |                     editor: "ivan"
-         *      |                     viewer: "judy"
-         *      v
-         *    "doc456"                owner: "oscar"
-         *
-         * (The names here are taken from
-         * https://en.wikipedia.org/wiki/Alice_and_Bob#Cast_of_characters.)
-         *
-         * TODO could add another parent directory of "dir1" to test recursion.
-         * TODO could add more levels of indirection, too?
-         */
-        let dir1 = ObjectId("dir1");
-        let dir2 = ObjectId("dir2");
-        let dir3 = ObjectId("dir3");
-        let doc123 = ObjectId("doc123");
-        let doc456 = ObjectId("doc456");
-        let doc789 = ObjectId("doc789");
-
-        let user_alice = UserId("alice");
-        let user_bob = UserId("bob");
-        let user_carol = UserId("carol");
-        let user_dan = UserId("dan");
-        let user_eve = UserId("eve");
-        let user_faythe = UserId("faythe");
-        let user_heidi = UserId("heidi");
-        let user_ivan = UserId("ivan");
-        let user_judy = UserId("judy");
-        let user_oscar = UserId("oscar");
-
-        miniz.write_object(&set_parent, dir1, doc123);
-        miniz.write_user(&set_owner, dir1, user_alice);
-        miniz.write_user(&set_editor, dir1, user_bob);
-        miniz.write_user(&set_viewer, dir1, user_carol);
-
-        miniz.write_user(&set_owner, doc123, user_dan);
-        miniz.write_user(&set_editor, doc123, user_eve);
-        miniz.write_user(&set_viewer, doc123, user_faythe);
-
-        miniz.write_object(&set_parent, dir2, doc456);
-        miniz.write_user(&set_owner, dir2, user_heidi);
-        miniz.write_user(&set_editor, dir2, user_ivan);
-        miniz.write_user(&set_viewer, dir2, user_judy);
-        miniz.write_user(&set_owner, doc456, user_oscar);
-
-        /* Contents of "dir1" */
-        assert!(miniz.set_contains_object_directly(&set_parent, &dir1, doc123));
-        assert!(!miniz.set_contains_object_directly(
-            &set_parent,
-            &dir1,
-            doc456
-        ));
-        /* Contents of "dir2" */
-        assert!(miniz.set_contains_object_directly(&set_parent, &dir2, doc456));
-        assert!(!miniz.set_contains_object_directly(
-            &set_parent,
-            &dir2,
-            doc123
-        ));
-        /* Non-existent document is contained nowhere. */
-        assert!(!miniz.set_contains_object_directly(
-            &set_parent,
-            &dir1,
-            doc789
-        ));
-        assert!(!miniz.set_contains_object_directly(
-            &set_parent,
-            &dir2,
-            doc789
-        ));
-        /* Non-existent set contains nothing */
-        assert!(!miniz.set_contains_object_directly(
-            &set_parent,
-            &dir3,
-            doc123
-        ));
-        assert!(!miniz.set_contains_object_directly(
+        let set_commenter = miniz_builder
+            .new_relationship("commenter")
+            .with_subset(&set_viewer)
+            .with_inherited_as(&set_parent, &set_editor)
+            .build();
+        let rids = [
             &set_parent,
-            &dir3,
-            doc123
-        ));
-
-        /* User associations (direct associations) for "dir1" */
-        assert_eq!(
-            miniz.set_list_direct_members(&set_owner, &dir1),
-            vec![&Member::User(user_alice)]
-        );
-        assert!(miniz.set_contains_user_directly(&set_owner, &dir1, user_alice));
-        assert!(!miniz.set_contains_user_directly(&set_owner, &dir1, user_bob));
-        assert!(
-            !miniz.set_contains_user_directly(&set_owner, &dir1, user_carol)
-        );
-
-        assert_eq!(
-            miniz.set_list_direct_members(&set_editor, &dir1),
-            vec![&Member::User(user_bob)]
-        );
-        assert!(!miniz.set_contains_user_directly(
-            &set_editor,
-            &dir1,
-            user_alice
-        ));
-        assert!(miniz.set_contains_user_directly(&set_editor, &dir1, user_bob));
-        assert!(!miniz.set_contains_user_directly(
+            &set_banned,
+            &set_member,
+            &set_owner,
             &set_editor,
-            &dir1,
-            user_carol
-        ));
-
-        assert_eq!(
-            miniz.set_list_direct_members(&set_viewer, &dir1),
-            vec![&Member::User(user_carol)]
-        );
-        assert!(!miniz.set_contains_user_directly(
-            &set_viewer,
-            &dir1,
-            user_alice
-        ));
-        assert!(!miniz.set_contains_user_directly(
-            &set_viewer,
-            &dir1,
-            user_bob
-        ));
-        assert!(miniz.set_contains_user_directly(
             &set_viewer,
-            &dir1,
-            user_carol
-        ));
+            &set_commenter,
+        ];
 
-        /* Reverse indexes */
-        assert_eq!(
-            miniz.object_lookup_memberships(dir1),
-            Vec::new() as Vec<&Membership<ObjectId>>,
-        );
-        assert_eq!(
-            miniz.object_lookup_memberships(dir2),
-            Vec::new() as Vec<&Membership<ObjectId>>,
-        );
-        assert_eq!(
-            miniz.object_lookup_memberships(doc123),
-            vec![&Membership { rid: set_parent.clone(), object: dir1 }]
-        );
-        assert_eq!(
-            miniz.user_lookup_memberships(user_alice),
-            vec![&Membership { rid: set_owner.clone(), object: dir1 }]
-        );
+        let dir1 = ObjectId("dir1");
+        let doc1 = ObjectId("doc1");
+        let doc2 = ObjectId("doc2");
+        let group1 = ObjectId("group1");
+        let group2 = ObjectId("group2");
+        let tuple = |rid: &RelationshipId, parent, member| Tuple {
+            rid: rid.clone(),
+            parent,
+            member,
+        };
+        let tuples = vec![
+            tuple(&set_parent, dir1, Member::Object(doc1)),
+            tuple(&set_parent, dir1, Member::Object(doc2)),
+            tuple(&set_member, group1, Member::User(UserId("alice"))),
+            tuple(&set_member, group1, Member::User(UserId("bob"))),
+            tuple(&set_member, group2, Member::Object(group1)),
+            tuple(&set_owner, doc1, Member::User(UserId("carol"))),
+            tuple(&set_owner, doc1, Member::Object(group2)),
+            tuple(&set_editor, dir1, Member::Object(group2)),
+            tuple(
+                &set_viewer,
+                doc2,
+                Member::ObjectRelation {
+                    object: group1,
+                    relation: set_member.clone(),
+                },
+            ),
+            tuple(&set_viewer, dir1, Member::User(UserId("dan"))),
+            tuple(&set_banned, dir1, Member::User(UserId("bob"))),
+            tuple(&set_banned, doc2, Member::User(UserId("erin"))),
+            tuple(&set_viewer, doc2, Member::User(UserId("erin"))),
+            tuple(&set_commenter, doc1, Member::User(UserId("frank"))),
+        ];
+        let source = MockAsyncSource {
+            tuples: tuples.clone(),
+            lookups: std::cell::Cell::new(0),
+        };
+        let mut miniz = miniz_builder.build().unwrap();
+        miniz.load_tuples(tuples);
 
-        /* "Check" API */
-        assert!(miniz.check_member(&set_viewer, dir1, user_alice));
-        assert!(miniz.check_member(&set_viewer, dir1, user_bob));
-        assert!(miniz.check_member(&set_viewer, dir1, user_carol));
-        assert!(miniz.check_member(&set_editor, dir1, user_alice));
-        assert!(miniz.check_member(&set_editor, dir1, user_bob));
-        assert!(!miniz.check_member(&set_editor, dir1, user_carol));
-        assert!(miniz.check_member(&set_owner, dir1, user_alice));
-        assert!(!miniz.check_member(&set_owner, dir1, user_bob));
-        assert!(!miniz.check_member(&set_owner, dir1, user_carol));
+        let objects = [dir1, doc1, doc2, group1, group2];
+        let users =
+            ["alice", "bob", "carol", "dan", "erin", "frank", "mallory"];
+        for rid in rids.iter().copied() {
+            for object in objects.iter().copied() {
+                for user in users.iter().copied().map(UserId) {
+                    let expected = miniz.check_member(rid, object, user);
+                    assert_eq!(
+                        block_on(
+                            miniz
+                                .check_member_async(&source, rid, object, user)
+                        ),
+                        expected,
+                        "{:?} {:?} {:?}",
+                        rid,
+                        object,
+                        user
+                    );
+                    assert_eq!(
+                        block_on(
+                            miniz.check_member_async(&miniz, rid, object, user)
+                        ),
+                        expected
+                    );
+                }
+            }
+        }
+
+        /* Make sure the interesting cases above came out as intended. */
+        let alice = UserId("alice");
+        let bob = UserId("bob");
+        assert!(!miniz.check_member(&set_owner, doc1, alice));
+        assert!(miniz.check_member(&set_owner, doc1, UserId("carol")));
+        assert!(miniz.check_member(&set_viewer, doc1, alice));
+        assert!(!miniz.check_member(&set_viewer, doc1, bob));
+        assert!(miniz.check_member(&set_commenter, doc1, bob));
+        assert!(miniz.check_member(&set_viewer, doc2, alice));
+        assert!(!miniz.check_member(&set_viewer, doc2, UserId("erin")));
+        assert!(miniz.check_member(&set_viewer, doc2, UserId("dan")));
     }
 }
@@ -0,0 +1,155 @@
+//! Rough estimates of how much memory a [`MiniZ`] uses
+//!
+//! [`MiniZ::approximate_memory_bytes()`] is meant for capacity planning, not
+//! accounting.  It counts the size of every entry in the store's maps and sets
+//! (with some allowance for the B-tree nodes that hold them) plus whatever the
+//! ids themselves have allocated on the heap, as reported by their
+//! [`HeapSize`] impls.  It doesn't know how the allocator rounds requests, so
+//! actual usage is typically somewhat higher.
+
+use crate::intern::InternedId;
+use crate::CaveatId;
+use crate::LogEntry;
+use crate::LogOp;
+use crate::Member;
+use crate::Membership;
+use crate::MiniZ;
+use crate::Relationship;
+use crate::RelationshipId;
+use alloc::string::String;
+use core::mem::size_of;
+
+///
+/// An object or user id type whose heap usage can be estimated
+///
+pub trait HeapSize {
+    /// Returns the number of bytes that `self` has allocated on the heap, not
+    /// counting `size_of_val(self)`
+    fn heap_size(&self) -> usize;
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for InternedId {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+macro_rules! impl_heap_size_int {
+    ($t:ty) => {
+        impl HeapSize for $t {
+            fn heap_size(&self) -> usize {
+                0
+            }
+        }
+    };
+}
+
+impl_heap_size_int!(u8);
+impl_heap_size_int!(u16);
+impl_heap_size_int!(u32);
+impl_heap_size_int!(u64);
+
+impl<O: HeapSize, U: HeapSize> HeapSize for Member<O, U> {
+    fn heap_size(&self) -> usize {
+        match self {
+            Member::Object(object) => object.heap_size(),
+            Member::User(user) => user.heap_size(),
+            Member::ObjectRelation { object, relation } => {
+                object.heap_size() + relation.heap_size()
+            }
+        }
+    }
+}
+
+impl HeapSize for RelationshipId {
+    fn heap_size(&self) -> usize {
+        self.0.capacity()
+    }
+}
+
+///
+/// Returns the approximate size of the nodes of a B-tree holding `len` entries
+/// with keys of type `K` and values of type `V`
+///
+/// Nodes hold up to 11 entries and are usually about two-thirds full, and each
+/// has a few words of bookkeeping, so we allow half again the size of the
+/// entries themselves.
+///
+fn btree_bytes<K, V>(len: usize) -> usize {
+    len * (size_of::<K>() + size_of::<V>()) * 3 / 2
+}
+
+impl<O: HeapSize, U: HeapSize> MiniZ<O, U> {
+    ///
+    /// Returns a rough estimate of the number of bytes of memory used by this
+    /// store
+    ///
+    /// This includes the schema, the tuples (which are stored twice: once for
+    /// each relationship and once in the reverse index), and the change log
+    /// (if enabled), but not the check cache or the inherited index.  See the
+    /// [`memory`](crate::memory) module for how it's computed.
+    ///
+    pub fn approximate_memory_bytes(&self) -> usize {
+        let mut total = size_of::<Self>();
+
+        total +=
+            btree_bytes::<RelationshipId, Relationship<O, U>>(self.sets.len());
+        for (rid, set) in &self.sets {
+            total += rid.heap_size();
+            total += btree_bytes::<RelationshipId, ()>(
+                set.contained_relationships.len()
+                    + set.deny_relationships.len(),
+            );
+            total += set
+                .inherited_relationships
+                .values()
+                .map(|parent_rids| {
+                    btree_bytes::<RelationshipId, ()>(parent_rids.len())
+                })
+                .sum::<usize>();
+            total += set.description.as_ref().map_or(0, |text| text.capacity());
+
+            total += btree_bytes::<O, ()>(set.direct_members.len());
+            for (parent, members) in &set.direct_members {
+                total += parent.heap_size();
+                total += btree_bytes::<Member<O, U>, ()>(members.len());
+                total += members.iter().map(|m| m.heap_size()).sum::<usize>();
+            }
+            for caveated in set.caveated_members.values() {
+                total += btree_bytes::<Member<O, U>, CaveatId>(caveated.len());
+            }
+        }
+
+        total += btree_bytes::<Member<O, U>, ()>(self.memberships.len());
+        for (member, memberships) in &self.memberships {
+            total += member.heap_size();
+            total += btree_bytes::<Membership<O>, ()>(memberships.len());
+            total += memberships
+                .iter()
+                .map(|m| m.rid.heap_size() + m.object.heap_size())
+                .sum::<usize>();
+        }
+
+        if let Some(log) = &self.log {
+            total += log.capacity() * size_of::<LogEntry<O, U>>();
+            for entry in log {
+                let tuple = match &entry.op {
+                    LogOp::Write(tuple)
+                    | LogOp::WriteCaveated(tuple, _)
+                    | LogOp::Remove(tuple) => tuple,
+                };
+                total += tuple.rid.heap_size()
+                    + tuple.parent.heap_size()
+                    + tuple.member.heap_size();
+            }
+        }
+
+        total
+    }
+}
@@ -0,0 +1,199 @@
+//! Compact textual syntax for schemas
+//!
+//! [`MiniZBuilder::from_schema_str()`] configures relationships from text like
+//! this, which describes the schema from Figure 1 of the Zanzibar paper:
+//!
+//! ```text
+//! owner
+//! parent
+//! editor: owner
+//! viewer: editor | parent->viewer
+//! ```
+//!
+//! Each line defines one relationship: its name, optionally followed by `:`
+//! and the rules for the relationship separated by `|`.  A rule is either the
+//! name of another relationship, which the relationship includes as a subset
+//! (see [`RelationshipBuilder::with_subset()`][with_subset]), or
+//! `via->parent_relation`, which means the relationship includes
+//! `parent_relation` on the objects reached through `via` (see
+//! [`RelationshipBuilder::with_inherited_as()`][with_inherited_as]).
+//! Relationships may refer to ones defined later.  Names consist of ASCII
+//! letters, digits, and underscores.  Blank lines are ignored, as is anything
+//! after a `#`.
+//!
+//! [with_subset]: crate::RelationshipBuilder::with_subset
+//! [with_inherited_as]: crate::RelationshipBuilder::with_inherited_as
+
+use crate::MiniZ;
+use crate::MiniZBuilder;
+use crate::RelationshipId;
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeSet;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+///
+/// Error returned by [`MiniZBuilder::from_schema_str()`]
+///
+/// Line numbers start at 1.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// line `line` is not a valid relationship definition
+    Syntax { line: usize },
+    /// relationship `name` is defined again on line `line`
+    Duplicate { line: usize, name: String },
+    /// line `line` refers to relationship `name`, which is not defined
+    UnknownRelationship { line: usize, name: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Syntax { line } => {
+                write!(f, "line {}: invalid relationship definition", line)
+            }
+            ParseError::Duplicate { line, name } => write!(
+                f,
+                "line {}: relationship \"{}\" is already defined",
+                line, name
+            ),
+            ParseError::UnknownRelationship { line, name } => write!(
+                f,
+                "line {}: relationship \"{}\" is not defined",
+                line, name
+            ),
+        }
+    }
+}
+
+#[cfg(any(feature = "std", test))]
+impl std::error::Error for ParseError {}
+
+/// One rule in a relationship definition
+enum Rule<'a> {
+    /// `name`: a subset
+    Subset(&'a str),
+    /// `via->parent_relation`: an inherited relationship
+    Inherited { via: &'a str, parent_relation: &'a str },
+}
+
+impl<O, U> MiniZBuilder<O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    ///
+    /// Returns a builder with the relationships described by `schema`
+    ///
+    /// See the [`schema`](crate::schema) module for the syntax.  More
+    /// relationships (or caveats) can be added to the returned builder before
+    /// building it.
+    ///
+    pub fn from_schema_str(
+        schema: &str,
+    ) -> Result<MiniZBuilder<O, U>, ParseError> {
+        let mut definitions = Vec::new();
+        let mut names = BTreeSet::new();
+        for (i, line) in schema.lines().enumerate() {
+            let line_number = i + 1;
+            let text = line.split('#').next().unwrap_or("").trim();
+            if text.is_empty() {
+                continue;
+            }
+
+            let (name, rules) = match text.split_once(':') {
+                Some((name, rules)) => (name.trim(), parse_rules(rules)),
+                None => (text, Some(Vec::new())),
+            };
+            let rules = match rules {
+                Some(rules) if is_name(name) => rules,
+                _ => return Err(ParseError::Syntax { line: line_number }),
+            };
+            if !names.insert(name) {
+                return Err(ParseError::Duplicate {
+                    line: line_number,
+                    name: name.to_owned(),
+                });
+            }
+            definitions.push((line_number, name, rules));
+        }
+
+        /*
+         * Check references only once we've seen every name, since
+         * relationships may refer to ones defined after them.
+         */
+        for (line_number, _, rules) in &definitions {
+            for rule in rules {
+                let referenced = match rule {
+                    Rule::Subset(subrid) => vec![*subrid],
+                    Rule::Inherited { via, parent_relation } => {
+                        vec![*via, *parent_relation]
+                    }
+                };
+                if let Some(name) =
+                    referenced.iter().find(|name| !names.contains(*name))
+                {
+                    return Err(ParseError::UnknownRelationship {
+                        line: *line_number,
+                        name: (*name).to_owned(),
+                    });
+                }
+            }
+        }
+
+        let rid = |name: &str| RelationshipId(name.to_owned());
+        let mut miniz_builder = MiniZ::builder();
+        for (_, name, rules) in definitions {
+            let mut relationship_builder = miniz_builder.new_relationship(name);
+            for rule in rules {
+                relationship_builder = match rule {
+                    Rule::Subset(subrid) => {
+                        relationship_builder.with_subset(&rid(subrid))
+                    }
+                    Rule::Inherited { via, parent_relation } => {
+                        relationship_builder
+                            .with_inherited_as(&rid(via), &rid(parent_relation))
+                    }
+                };
+            }
+            relationship_builder.build();
+        }
+
+        Ok(miniz_builder)
+    }
+}
+
+/// Parses the rules after the `:` in a definition, returning `None` if they're
+/// malformed
+fn parse_rules(rules: &str) -> Option<Vec<Rule<'_>>> {
+    if rules.trim().is_empty() {
+        return Some(Vec::new());
+    }
+
+    rules
+        .split('|')
+        .map(|rule| {
+            let rule = rule.trim();
+            match rule.split_once("->") {
+                Some((via, parent_relation)) => {
+                    let via = via.trim();
+                    let parent_relation = parent_relation.trim();
+                    if is_name(via) && is_name(parent_relation) {
+                        Some(Rule::Inherited { via, parent_relation })
+                    } else {
+                        None
+                    }
+                }
+                None if is_name(rule) => Some(Rule::Subset(rule)),
+                None => None,
+            }
+        })
+        .collect()
+}
+
+fn is_name(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
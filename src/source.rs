@@ -0,0 +1,233 @@
+//! Checking relationships against tuples stored outside a [`MiniZ`]
+//!
+//! A [`MiniZ`] normally holds both a schema and the tuples written against
+//! it.  Some consumers would rather keep the tuples somewhere else (say, in a
+//! database) and use MiniZ only to resolve checks.  [`TupleSource`] describes
+//! the two lookups that resolving a check needs: the direct members of a
+//! relationship on an object, and the direct memberships of an object.
+//! [`AsyncTupleSource`] is the same thing for stores that can only be reached
+//! asynchronously.  [`MiniZ::check_member_async()`] resolves a check using the
+//! schema of a `MiniZ` and the tuples of any `AsyncTupleSource`, awaiting each
+//! lookup as it goes.
+//!
+//! Every `TupleSource` is also an `AsyncTupleSource` whose lookups complete
+//! immediately.  That includes `MiniZ` itself, so a store can be checked
+//! against its own tuples this way, too (though [`MiniZ::check_member()`] is
+//! much faster, since it can use the store's indexes and caches).
+//!
+//! Sources have no notion of caveats: they should return only the tuples that
+//! ought to count for the check.  `MiniZ`'s own impl leaves out tuples whose
+//! caveats aren't satisfied by an empty context, which is what
+//! `check_member()` does.
+
+use crate::Member;
+use crate::Membership;
+use crate::MiniZ;
+use crate::RelationshipId;
+use crate::EMPTY_CONTEXT;
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::future::Future;
+
+///
+/// A store of tuples that checks can be resolved against
+///
+/// See the [`source`](crate::source) module.
+///
+pub trait TupleSource<O, U> {
+    /// Returns the direct members of relationship `rid` on object `object`
+    fn direct_members(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> Vec<Member<O, U>>;
+
+    /// Returns the direct memberships of `member`
+    fn memberships(&self, member: &Member<O, U>) -> Vec<Membership<O>>;
+}
+
+///
+/// A store of tuples that checks can be resolved against, whose lookups are
+/// asynchronous
+///
+/// See the [`source`](crate::source) module.
+///
+pub trait AsyncTupleSource<O, U> {
+    /// Returns the direct members of relationship `rid` on object `object`
+    fn direct_members(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> impl Future<Output = Vec<Member<O, U>>>;
+
+    /// Returns the direct memberships of `member`
+    fn memberships(
+        &self,
+        member: &Member<O, U>,
+    ) -> impl Future<Output = Vec<Membership<O>>>;
+}
+
+impl<O, U, S: TupleSource<O, U>> AsyncTupleSource<O, U> for S {
+    fn direct_members(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> impl Future<Output = Vec<Member<O, U>>> {
+        core::future::ready(TupleSource::direct_members(self, rid, object))
+    }
+
+    fn memberships(
+        &self,
+        member: &Member<O, U>,
+    ) -> impl Future<Output = Vec<Membership<O>>> {
+        core::future::ready(TupleSource::memberships(self, member))
+    }
+}
+
+impl<O, U> TupleSource<O, U> for MiniZ<O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    fn direct_members(
+        &self,
+        rid: &RelationshipId,
+        object: &O,
+    ) -> Vec<Member<O, U>> {
+        let set = self.sets.get(rid).expect("no such set");
+        set.direct_members
+            .get(object)
+            .into_iter()
+            .flatten()
+            .filter(|member| {
+                self.caveat_satisfied(
+                    set.caveat_for(object, member),
+                    &EMPTY_CONTEXT,
+                )
+            })
+            .cloned()
+            .collect()
+    }
+
+    fn memberships(&self, member: &Member<O, U>) -> Vec<Membership<O>> {
+        self.memberships
+            .get(member)
+            .into_iter()
+            .flatten()
+            .filter(|membership| {
+                let set = &self.sets[&membership.rid];
+                self.caveat_satisfied(
+                    set.caveat_for(&membership.object, member),
+                    &EMPTY_CONTEXT,
+                )
+            })
+            .cloned()
+            .collect()
+    }
+}
+
+impl<O, U> MiniZ<O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    ///
+    /// Check whether the user `user` has relationship `rid` with object
+    /// `object`, using this store's schema and the tuples in `source`
+    ///
+    /// This follows the same rules as [`MiniZ::check_member()`], but it looks
+    /// up tuples only through `source`, awaiting each lookup.  The tuples in
+    /// this store are ignored (unless `source` is this store).  None of the
+    /// store's indexes or caches are used, either, since they describe this
+    /// store's own tuples.
+    ///
+    pub async fn check_member_async<S: AsyncTupleSource<O, U>>(
+        &self,
+        source: &S,
+        rid: &RelationshipId,
+        object: O,
+        user: U,
+    ) -> bool {
+        self.resolve_async(source, rid, object, &user, true).await
+    }
+
+    /// Resolve a check for `check_member_async()`, optionally ignoring deny
+    /// relationships (as we do when resolving the deny relationships
+    /// themselves)
+    async fn resolve_async<S: AsyncTupleSource<O, U>>(
+        &self,
+        source: &S,
+        rid: &RelationshipId,
+        object: O,
+        user: &U,
+        apply_denials: bool,
+    ) -> bool {
+        /*
+         * Look for the user among the direct members of every (relationship,
+         * object) pair reachable from the one we were asked about.
+         */
+        let mut visited = BTreeSet::new();
+        let mut todo = vec![(rid.clone(), object)];
+        'nodes: while let Some(node) = todo.pop() {
+            if !visited.insert(node.clone()) {
+                continue;
+            }
+            let (rid, object) = node;
+            let set = self.sets.get(&rid).expect("no such set");
+
+            if apply_denials {
+                for deny in &set.deny_relationships {
+                    let denied = self.resolve_async(
+                        source,
+                        deny,
+                        object.clone(),
+                        user,
+                        false,
+                    );
+                    if Box::pin(denied).await {
+                        continue 'nodes;
+                    }
+                }
+            }
+
+            /*
+             * The edges out of this node are the same ones that
+             * `MiniZ::edges()` would find, except that we look up the tuples
+             * through `source`.
+             */
+            for member in source.direct_members(&rid, &object).await {
+                if let Member::User(member_user) = &member {
+                    if member_user == user {
+                        return true;
+                    }
+                }
+                if let Some((member_rid, group)) = set.userset_edge(&member) {
+                    todo.push((member_rid.clone(), group.clone()));
+                }
+            }
+            if set.direct_only {
+                continue;
+            }
+
+            for subrid in &set.contained_relationships {
+                todo.push((subrid.clone(), object.clone()));
+            }
+            if !set.inherited_relationships.is_empty() {
+                let child = Member::Object(object);
+                for membership in source.memberships(&child).await {
+                    for parent_rid in set.inherited_edges(&membership.rid) {
+                        todo.push((
+                            parent_rid.clone(),
+                            membership.object.clone(),
+                        ));
+                    }
+                }
+            }
+        }
+
+        false
+    }
+}
@@ -0,0 +1,153 @@
+//! Relationships whose object and member types are checked at compile time
+//!
+//! A [`MiniZ`] has a single object type, so in a schema with several kinds of
+//! objects (say, folders and documents), nothing stops a caller from writing
+//! a folder where a document was expected.  A [`TypedRelation`] pairs a
+//! [`RelationshipId`] with the types of its parent objects and its members,
+//! and [`MiniZ::typed_write()`] only accepts values of those types.  The types
+//! are typically newtypes that convert into the store's own id types:
+//!
+//! ```
+//! use miniz::typed::TypedRelation;
+//! use miniz::Member;
+//! use miniz::MiniZ;
+//!
+//! struct Folder(&'static str);
+//! struct Document(&'static str);
+//!
+//! impl From<Folder> for String {
+//!     fn from(folder: Folder) -> String {
+//!         format!("folder:{}", folder.0)
+//!     }
+//! }
+//!
+//! impl From<Document> for String {
+//!     fn from(document: Document) -> String {
+//!         format!("document:{}", document.0)
+//!     }
+//! }
+//!
+//! impl From<Document> for Member<String, String> {
+//!     fn from(document: Document) -> Member<String, String> {
+//!         Member::Object(String::from(document))
+//!     }
+//! }
+//!
+//! let mut builder = MiniZ::<String, String>::builder();
+//! let set_parent = builder.new_relationship("parent").build();
+//! let mut miniz = builder.build().unwrap();
+//!
+//! let parent: TypedRelation<Folder, Document> =
+//!     TypedRelation::new(&set_parent);
+//! miniz.typed_write(&parent, Folder("dir1"), Document("doc123"));
+//! ```
+//!
+//! Getting the order wrong fails to compile:
+//!
+//! ```compile_fail
+//! # use miniz::typed::TypedRelation;
+//! # use miniz::Member;
+//! # use miniz::MiniZ;
+//! # struct Folder(&'static str);
+//! # struct Document(&'static str);
+//! # impl From<Folder> for String {
+//! #     fn from(folder: Folder) -> String {
+//! #         format!("folder:{}", folder.0)
+//! #     }
+//! # }
+//! # impl From<Document> for String {
+//! #     fn from(document: Document) -> String {
+//! #         format!("document:{}", document.0)
+//! #     }
+//! # }
+//! # impl From<Document> for Member<String, String> {
+//! #     fn from(document: Document) -> Member<String, String> {
+//! #         Member::Object(String::from(document))
+//! #     }
+//! # }
+//! # let mut builder = MiniZ::<String, String>::builder();
+//! # let set_parent = builder.new_relationship("parent").build();
+//! # let mut miniz = builder.build().unwrap();
+//! let parent: TypedRelation<Folder, Document> =
+//!     TypedRelation::new(&set_parent);
+//! miniz.typed_write(&parent, Document("doc123"), Folder("dir1"));
+//! ```
+//!
+//! This is purely a compile-time aid: the tuples are stored exactly as if
+//! they had been written with [`MiniZ::write_object()`] or
+//! [`MiniZ::write_user()`], and nothing is checked at runtime beyond what
+//! those check.
+
+use crate::Member;
+use crate::Membership;
+use crate::MiniZ;
+use crate::RelationshipId;
+use core::fmt;
+use core::marker::PhantomData;
+
+///
+/// A relationship whose parent objects have type `P` and whose members have
+/// type `M`
+///
+/// See the [`typed`](crate::typed) module for details.
+///
+pub struct TypedRelation<P, M> {
+    rid: RelationshipId,
+    /* This neither owns nor borrows any `P` or `M`. */
+    marker: PhantomData<fn() -> (P, M)>,
+}
+
+impl<P, M> TypedRelation<P, M> {
+    /// Returns a typed handle for the existing relationship `rid`
+    pub fn new(rid: &RelationshipId) -> TypedRelation<P, M> {
+        TypedRelation { rid: rid.clone(), marker: PhantomData }
+    }
+
+    /// Returns the id of the underlying relationship
+    pub fn rid(&self) -> &RelationshipId {
+        &self.rid
+    }
+}
+
+/*
+ * These are implemented by hand because deriving them would require `P` and
+ * `M` to implement them too.
+ */
+impl<P, M> Clone for TypedRelation<P, M> {
+    fn clone(&self) -> Self {
+        TypedRelation::new(&self.rid)
+    }
+}
+
+impl<P, M> fmt::Debug for TypedRelation<P, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TypedRelation").field(&self.rid).finish()
+    }
+}
+
+impl<O, U> MiniZ<O, U>
+where
+    O: Clone + fmt::Debug + Ord,
+    U: Clone + fmt::Debug + Ord,
+{
+    ///
+    /// Specify that `member` directly has the relationship `rel` to object
+    /// `parent`
+    ///
+    /// Like [`MiniZ::write_object()`] and [`MiniZ::write_user()`], except that
+    /// the compiler checks the types of `parent` and `member` against `rel`.
+    /// Returns the [`Membership`] recorded for `member` in the reverse index.
+    ///
+    pub fn typed_write<P, M>(
+        &mut self,
+        rel: &TypedRelation<P, M>,
+        parent: P,
+        member: M,
+    ) -> Membership<O>
+    where
+        P: Into<O>,
+        M: Into<Member<O, U>>,
+    {
+        self.write_member(&rel.rid, parent.into(), member.into(), None)
+    }
+}